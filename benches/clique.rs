@@ -14,11 +14,11 @@ fn run_clique(size: u64) {
                 args: vec![i.to_value(), (i + 1).to_value()],
             })?;
         }
-        fact!(holmes, edge((size - 1), 0))?;
+        fact!(holmes, edge(size - 1, 0))?;
         holmes_exec!(holmes, {
-            rule!(reachable(X, Y) <= edge(X, Y));
-            rule!(reachable(X, Y) <= edge(X, Z) & reachable(Z, Y));
-            rule!(same_clique(X, Y) <= reachable(X, Y) & reachable(Y, X))
+            rule!(reachable_base: reachable(X, Y) <= edge(X, Y));
+            rule!(reachable_step: reachable(X, Y) <= edge(X, Z) & reachable(Z, Y));
+            rule!(same_clique: same_clique(X, Y) <= reachable(X, Y) & reachable(Y, X))
         })?;
 
         core.run(holmes.quiesce()).unwrap();
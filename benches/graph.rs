@@ -15,18 +15,18 @@ fn run_graph(size: u64) {
                 args: vec![i.to_value(), (i + 1).to_value()],
             })?;
         }
-        fact!(holmes, edge((size - 1), 0))?;
+        fact!(holmes, edge(size - 1, 0))?;
 
         holmes_exec!(holmes, {
-            rule!(reachable(X, Y) <= edge(X, Y));
-            rule!(reachable(X, Y) <= edge(X, Z) & reachable(Z, Y));
+            rule!(reachable_base: reachable(X, Y) <= edge(X, Y));
+            rule!(reachable_step: reachable(X, Y) <= edge(X, Z) & reachable(Z, Y));
             func!(let lt : (uint64, uint64) -> bool = |(x, y): (&u64, &u64)| {
                 x < y
             });
-            rule!(increasing(X, Y) <= edge(X, Y), {
+            rule!(increasing_base: increasing(X, Y) <= edge(X, Y), {
                 let (true) = {lt([X], [Y])}
             });
-            rule!(increasing(X, Y) <= edge(X, Z) & increasing(Z, Y), {
+            rule!(increasing_step: increasing(X, Y) <= edge(X, Z) & increasing(Z, Y), {
                 let (true) = {lt([X], [Z])}
             })
         })?;
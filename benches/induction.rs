@@ -10,8 +10,8 @@ fn run_induction(size: u64) {
             predicate!(q(uint64))
         })?;
         for i in 0..(size - 1) {
-            rule!(holmes, p((i + 1)) <= p((i)) & p((i + 1)))?;
-            rule!(holmes, q((i + 1)) <= q((i)) & p((i)))?;
+            rule!(holmes, p_step: p((i + 1)) <= p((i)) & p((i + 1)))?;
+            rule!(holmes, q_step: q((i + 1)) <= q((i)) & p((i)))?;
         }
         holmes.new_fact(&Fact {
             pred_name: "p".to_string(),
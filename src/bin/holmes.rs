@@ -0,0 +1,257 @@
+//! Holmes REPL
+//!
+//! Connects to a Postgres backed fact database, given as a URI on the command
+//! line, and reads statements in the textual Holmes language (see
+//! `holmes::lang`). Predicates, facts and rules are registered as they are
+//! entered, and queries (`?- clause & clause.`) are answered once the rules
+//! have quiesced. A statement may span several lines, up to its final `.`.
+//!
+//! Lines starting with `:` are commands, see `:help`.
+//!
+//! Only built with the `repl` feature, as in `cargo run --features repl`.
+
+extern crate holmes;
+extern crate rustyline;
+extern crate tokio_core;
+
+use std::cell::RefCell;
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+use std::time::Duration;
+
+use holmes::{Engine, PgDB, Result};
+use holmes::engine::types::Predicate;
+use holmes::pg::dyn::Type;
+use holmes::pg::dyn::types;
+use rustyline::Editor;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use tokio_core::reactor::Core;
+
+const HELP: &str = "\
+Enter statements in the Holmes language, e.g.
+  predicate edge(src: uint64, dst: uint64).
+  edge(1, 2).
+  path: reach(x, y) <= edge(x, y).
+  ?- reach(1, y).
+
+Commands:
+  :preds [prefix]  List predicates, along with their fields
+  :profile         Show the time spent in each rule
+  :indexes         Show the indexes made for rules, and which rules use them
+  :help            Show this message
+  :quit            Exit";
+
+// Completes predicate names, from a list kept up to date by the REPL loop
+struct PredCompleter {
+    names: Rc<RefCell<Vec<String>>>,
+}
+
+impl Completer for PredCompleter {
+    fn complete(&self, line: &str, pos: usize) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !(c.is_alphanumeric() || c == '_'))
+            .map(|(n, c)| n + c.len_utf8())
+            .unwrap_or(0);
+        let prefix = &line[start..pos];
+        let names = self.names
+            .borrow()
+            .iter()
+            .filter(|name| name.starts_with(prefix))
+            .cloned()
+            .collect();
+        Ok((start, names))
+    }
+}
+
+fn type_name(type_: &Type) -> String {
+    if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+        let elems: Vec<String> = tuple.elements().iter().map(type_name).collect();
+        return format!("({})", elems.join(", "));
+    }
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        return format!("[{}]", type_name(list.elem()));
+    }
+    types::name_of(&**type_).unwrap_or_else(|| "?".to_string())
+}
+
+// Shows a predicate as it would be declared
+fn show_predicate(pred: &Predicate) -> String {
+    let fields: Vec<String> = pred.fields
+        .iter()
+        .map(|field| {
+            let mut out = String::new();
+            if let Some(ref name) = field.name {
+                out.push_str(&format!("{}: ", name));
+            }
+            out.push_str(&type_name(&field.type_));
+            if let Some(ref join) = field.join {
+                out.push_str(&format!(" join {}", join));
+            }
+            if let Some(ref descr) = field.description {
+                out.push_str(&format!(" {:?}", descr));
+            }
+            out
+        })
+        .collect();
+    let descr = match pred.description {
+        Some(ref descr) => format!(" {:?}", descr),
+        None => "".to_string(),
+    };
+    format!("predicate {}({}){}.", pred.name, fields.join(", "), descr)
+}
+
+fn millis(d: Duration) -> f64 {
+    d.as_secs() as f64 * 1e3 + d.subsec_nanos() as f64 / 1e6
+}
+
+// Runs a `:command`, returning false if the REPL should exit
+fn command(holmes: &Engine, line: &str) -> Result<bool> {
+    let mut words = line[1..].split_whitespace();
+    match words.next() {
+        Some("quit") | Some("q") => return Ok(false),
+        Some("help") => println!("{}", HELP),
+        Some("preds") => {
+            let prefix = words.next().unwrap_or("");
+            for pred in holmes.list_predicates()? {
+                if pred.name.starts_with(prefix) {
+                    println!("{}", show_predicate(&pred));
+                }
+            }
+        }
+        Some("profile") => {
+            println!(
+                "{:<24} {:>10} {:>10} {:>10} {:>10}",
+                "rule",
+                "total ms",
+                "select ms",
+                "insert ms",
+                "compute ms"
+            );
+            for profile in holmes.dump_profile() {
+                println!(
+                    "{:<24} {:>10.1} {:>10.1} {:>10.1} {:>10.1}",
+                    profile.name,
+                    millis(profile.rule_time),
+                    millis(profile.select_time),
+                    millis(profile.insert_time),
+                    millis(profile.compute_time)
+                );
+            }
+        }
+        Some("indexes") => {
+            for index in holmes.index_report() {
+                println!("{}", index);
+            }
+        }
+        _ => println!("Unknown command {}, try :help", line),
+    }
+    Ok(true)
+}
+
+// Loads a chunk of program, then answers its queries
+fn run(holmes: &mut Engine, core: &mut Core, src: &str) -> Result<()> {
+    let queries = holmes.load_program(src)?;
+    if let Err(errors) = core.run(holmes.quiesce()) {
+        for error in errors {
+            println!("{}", error);
+        }
+    }
+    for query in queries {
+        let answers = holmes.derive(&query.clauses)?;
+        for answer in answers.iter() {
+            let bindings: Vec<String> = query
+                .vars
+                .iter()
+                .zip(answer.iter())
+                .map(|(var, val)| format!("{} = {}", var, val))
+                .collect();
+            println!("  {}", bindings.join(", "));
+        }
+        println!(
+            "{} answer{}",
+            answers.len(),
+            if answers.len() == 1 { "" } else { "s" }
+        );
+    }
+    Ok(())
+}
+
+fn main() {
+    let uri = match env::args().nth(1) {
+        Some(uri) => uri,
+        None => {
+            eprintln!("Usage: holmes <postgres uri>");
+            process::exit(2);
+        }
+    };
+    let mut core = Core::new().unwrap();
+    let db = match PgDB::new(&uri) {
+        Ok(db) => db,
+        Err(err) => {
+            eprintln!("Could not open {}: {}", uri, err);
+            process::exit(1);
+        }
+    };
+    let mut holmes = Engine::new(db, core.handle());
+
+    let names = Rc::new(RefCell::new(Vec::new()));
+    let mut editor = Editor::new();
+    editor.set_completer(Some(PredCompleter { names: names.clone() }));
+    let history = env::var_os("HOME").map(|home| PathBuf::from(home).join(".holmes_history"));
+    if let Some(ref path) = history {
+        // There is no history the first time round
+        let _ = editor.load_history(path);
+    }
+
+    let mut pending = String::new();
+    loop {
+        if let Ok(preds) = holmes.list_predicates() {
+            *names.borrow_mut() = preds.into_iter().map(|pred| pred.name).collect();
+        }
+        let prompt = if pending.is_empty() { "holmes> " } else { "   ...> " };
+        let line = match editor.readline(prompt) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => {
+                pending.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(err) => {
+                eprintln!("{}", err);
+                break;
+            }
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        editor.add_history_entry(line.as_str());
+        let res = if pending.is_empty() && line.trim().starts_with(':') {
+            match command(&holmes, line.trim()) {
+                Ok(false) => break,
+                res => res.map(|_| ()),
+            }
+        } else {
+            pending.push_str(&line);
+            pending.push('\n');
+            if !pending.trim().ends_with('.') {
+                continue;
+            }
+            let src = std::mem::take(&mut pending);
+            run(&mut holmes, &mut core, &src)
+        };
+        if let Err(err) = res {
+            let causes: Vec<String> = err.iter().map(|cause| cause.to_string()).collect();
+            println!("Error: {}", causes.join(": "));
+        }
+    }
+    if let Some(ref path) = history {
+        if let Err(err) = editor.save_history(path) {
+            eprintln!("Could not save history: {}", err);
+        }
+    }
+}
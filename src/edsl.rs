@@ -47,27 +47,51 @@ macro_rules! htype {
 #[macro_export]
 macro_rules! holmes_exec {
   ($holmes:ident, { $( $action:expr );* }) => {{
-        $( try!($action($holmes)); );*
+        $( $action($holmes)?; )*
         $holmes.nop()
   }};
 }
 
+/// Generates a predicate field
+///
+/// * `type` -> an anonymous field
+/// * `[name type]` -> a named field
+/// * `[name type "description"]` -> a named, documented field
+/// * `[name type join func]` -> a named lattice field, merged with `func`
+/// * `[name type join func "description"]` -> as above, but documented
 #[macro_export]
 macro_rules! field {
+    ($holmes:ident, [$name:ident $t:tt join $join:ident]) => {{::holmes::engine::types::Field {
+        name: Some(stringify!($name).to_string()),
+        description: None,
+        type_: htype!($holmes, $t),
+        join: Some(stringify!($join).to_string())
+    }}};
+    ($holmes:ident, [$name:ident $t:tt join $join:ident $descr:expr]) => {{
+        ::holmes::engine::types::Field {
+            name: Some(stringify!($name).to_string()),
+            description: Some($descr.to_string()),
+            type_: htype!($holmes, $t),
+            join: Some(stringify!($join).to_string())
+        }
+    }};
     ($holmes:ident, [$name:ident $t:tt $descr:expr]) => {{::holmes::engine::types::Field {
         name: Some(stringify!($name).to_string()),
         description: Some($descr.to_string()),
-        type_: htype!($holmes, $t)
+        type_: htype!($holmes, $t),
+        join: None
     }}};
     ($holmes:ident, [$name:ident $t:tt]) => {{::holmes::engine::types::Field {
         name: Some(stringify!($name).to_string()),
         description: None,
-        type_: htype!($holmes, $t)
+        type_: htype!($holmes, $t),
+        join: None
     }}};
     ($holmes:ident, $t:tt) => {{::holmes::engine::types::Field {
         name: None,
         description: None,
-        type_: htype!($holmes, $t)
+        type_: htype!($holmes, $t),
+        join: None
     }}};
 }
 
@@ -100,10 +124,10 @@ macro_rules! predicate {
       fields: fields
     })
   }};
-  ($pred_name:ident($($t:tt),*) : $descr:expr) => { |holmes: &mut ::holmes::Engine| {
+  ($pred_name:ident($($t:tt),*) : $descr:expr) => { |holmes: &mut ::holmes::Engine<_>| {
     predicate!(holmes, $pred_name($($t),*), $descr)
   }};
-  ($pred_name:ident($($t:tt),*)) => { |holmes: &mut ::holmes::Engine| {
+  ($pred_name:ident($($t:tt),*)) => { |holmes: &mut ::holmes::Engine<_>| {
     predicate!(holmes, $pred_name($($t),*))
   }};
 }
@@ -127,17 +151,49 @@ macro_rules! fact {
       args : vec![$(::holmes::pg::dyn::values::ToValue::to_value($a)),*]
     })
   };
-  ($pred_name:ident($($a:expr),*)) => { |holmes: &mut ::holmes::Engine| {
+  ($pred_name:ident($($a:expr),*)) => { |holmes: &mut ::holmes::Engine<_>| {
     fact!(holmes, $pred_name($($a),*))
   }};
 }
 
+/// Retracts a fact from the `Holmes` context, along with anything derived
+/// from it which cannot be derived some other way.
+///
+/// ```c
+/// retract!(holmes, foo("bar", 3))
+/// ```
+///
+/// evaluates to whether the fact was present. If the `holmes` parameter is
+/// omitted, it will generate a function taking a `holmes` parameter in its
+/// stead, which discards this.
+#[macro_export]
+macro_rules! retract {
+  ($holmes:ident, $pred_name:ident($($a:expr),*)) => {
+    $holmes.retract_fact(&::holmes::engine::types::Fact {
+      pred_name : stringify!($pred_name).to_string(),
+      args : vec![$(::holmes::pg::dyn::values::ToValue::to_value($a)),*]
+    })
+  };
+  ($pred_name:ident($($a:expr),*)) => { |holmes: &mut ::holmes::Engine<_>| {
+    retract!(holmes, $pred_name($($a),*)).map(|_| ())
+  }};
+}
+
+/// Builds a single body clause, as used by `query!` and `rule!`, allocating
+/// variables from `$vars` and `$next`. Slots follow the rules in
+/// `clause_match!`, and `not(...)` negates the clause.
 #[macro_export]
 macro_rules! clause {
+    ($holmes:ident, $vars:ident, $next:ident, not($pred_name:ident $inner:tt)) => {{
+        let mut clause = clause!($holmes, $vars, $next, $pred_name $inner);
+        clause.negated = true;
+        clause
+    }};
     ($holmes:ident, $vars:ident, $next:ident, $pred_name:ident($($m:tt),*)) => {{
         ::holmes::engine::types::Clause {
             pred_name: stringify!($pred_name).to_string(),
-            args: vec![$(clause_match!($vars, $next, $m)),*]
+            args: vec![$(clause_match!($vars, $next, $m)),*],
+            negated: false
         }
     }};
     ($holmes:ident, $vars:ident, $next:ident, $pred_name:ident{$($field:ident = $m:tt),*}) => {{
@@ -159,7 +215,8 @@ macro_rules! clause {
         }).collect();
         ::holmes::engine::types::Clause {
             pred_name: pred_name,
-            args: args
+            args: args,
+            negated: false
         }
     }};
 }
@@ -174,6 +231,12 @@ macro_rules! clause {
 /// ```c
 /// query!(holmes, foo((3), [_]) & bar([_], x))
 /// ```
+///
+/// Wrapping a clause in `not(...)` requires that no matching fact exists:
+///
+/// ```c
+/// query!(holmes, foo((3), x) & not(bar([_], x)))
+/// ```
 #[macro_export]
 macro_rules! query {
   ($holmes:ident, $($pred_name:ident $inner:tt)&*) => {{
@@ -196,6 +259,16 @@ macro_rules! query {
 /// will work as per a normal datalog rule, matching on foo and bar, and
 /// generating a baz using any solutions found.
 ///
+/// # Negation
+///
+/// ```c
+/// rule!(holmes, no_callers: leaf(f) <= func(f) & not(calls([_], f)))
+/// ```
+///
+/// A `not(...)` clause only fires once every rule producing `calls` has
+/// gone quiet. The rule is rejected if it would make the program
+/// unstratifiable.
+///
 /// # Extended Rules
 ///
 /// Holmes rules can also have "where clauses" which call out to native code
@@ -212,39 +285,120 @@ macro_rules! query {
 ///
 /// For more information on the expression and bind syntax, see the `hexpr!`
 /// and `bind_match!` macro docs.
+///
+/// # Multiple Heads
+///
+/// A rule may have several heads, separated by commas:
+///
+/// ```c
+/// rule!(holmes, split: lo(x), hi(y) <= pair(x, y))
+/// ```
+///
+/// Each answer produces one fact per head, and these are stored together.
+///
+/// # Disjunction
+///
+/// Groups of body clauses may be separated by `|`, in which case the rule
+/// fires on a match of any group:
+///
+/// ```c
+/// rule!(holmes, uses: used(f) <= calls([_], f) | exported(f))
+/// ```
+///
+/// Each head variable must be bound in every group.
+///
+/// # Aggregates
+///
+/// A head slot may be wrapped as `{agg var}`, where `agg` is one of `count`,
+/// `min`, `max`, `sum` or `collect`:
+///
+/// ```c
+/// rule!(holmes, block_count: blocks(f, {count b}) <= block(f, b))
+/// ```
+///
+/// Answers are grouped by the remaining head slots, and each group yields a
+/// single fact which is replaced as more answers arrive.
+///
+/// # Backward Rules
+///
+/// Writing `<-` in place of `<=` makes a backward rule:
+///
+/// ```c
+/// rule!(holmes, smt_sat: sat(f) <- constraint(f, c), {
+///   let (true) = {solve(c)}
+/// })
+/// ```
+///
+/// It will only run when a query or another rule's body asks for `sat`, and
+/// then only for the `sat` facts asked about.
 #[macro_export]
 macro_rules! rule {
-  ($holmes:ident, $rule_name:ident : $head_name:ident $head_inner:tt <= $($body_name:ident $body_inner:tt)&*,
-   {$(let $bind:tt = $hexpr:tt);*}) => {{
+  ($holmes:ident, $rule_name:ident : $($head_name:ident $head_inner:tt),* <=
+   $($($body_name:ident $body_inner:tt)&*)|*, {$(let $bind:tt = $hexpr:tt);*}) => {
+    rule!(@build $holmes, false, $rule_name : $($head_name $head_inner),*; $($($body_name $body_inner)&*)|*,
+          {$(let $bind = $hexpr);*})
+  };
+  ($holmes:ident, $rule_name:ident : $($head_name:ident $head_inner:tt),* <-
+   $($($body_name:ident $body_inner:tt)&*)|*, {$(let $bind:tt = $hexpr:tt);*}) => {
+    rule!(@build $holmes, true, $rule_name : $($head_name $head_inner),*; $($($body_name $body_inner)&*)|*,
+          {$(let $bind = $hexpr);*})
+  };
+  (@build $holmes:ident, $backward:expr, $rule_name:ident : $($head_name:ident $head_inner:tt),*;
+   $($($body_name:ident $body_inner:tt)&*)|*, {$(let $bind:tt = $hexpr:tt);*}) => {{
     use std::collections::HashMap;
     let mut _vars : HashMap<String, ::holmes::engine::types::Var> = HashMap::new();
     let mut _n : ::holmes::engine::types::Var = 0;
-    let body = vec![$(clause!($holmes, _vars, _n, $body_name $body_inner)),*];
+    let mut bodies = vec![$(vec![$(clause!($holmes, _vars, _n, $body_name $body_inner)),*]),*];
+    let body = bodies.remove(0);
     let wheres = vec![$(::holmes::engine::types::WhereClause {
         lhs: bind_match!(_vars, _n, $bind),
         rhs: hexpr!(_vars, _n, $hexpr)
     }),*];
-    let head = clause!($holmes, _vars, _n, $head_name $head_inner);
+    let mut head = Vec::new();
+    let mut aggregates = Vec::new();
+    $(
+        let (clause, aggs) = head_clause!($holmes, _vars, _n, $head_name $head_inner);
+        head.push(clause);
+        aggregates.extend(aggs);
+    )*
     $holmes.new_rule(&::holmes::engine::types::Rule {
       name: stringify!($rule_name).to_string(),
       body: body,
+      alternatives: bodies,
       head: head,
       wheres: wheres,
+      aggregates: aggregates,
+      backward: $backward,
     })
   }};
-  ($holmes:ident, $rule_name:ident : $($head_name:ident $head_inner:tt),* <= $($body_name:ident $inner:tt)&*) => {
-      rule!($holmes, $rule_name : $($head_name $head_inner),* <= $($body_name $inner)&*, {})
+  ($holmes:ident, $rule_name:ident : $($head_name:ident $head_inner:tt),* <= $($($body_name:ident $inner:tt)&*)|*) => {
+      rule!($holmes, $rule_name : $($head_name $head_inner),* <= $($($body_name $inner)&*)|*, {})
   };
-  ($rule_name:ident : $($head_name:ident $head_inner:tt),* <= $($body_name:ident $inner:tt)&*) => {
-    |holmes: &mut ::holmes::Engine| {
-      rule!(holmes, $rule_name : $($head_name $head_inner),* <= $($body_name $inner)&*, {})
+  ($holmes:ident, $rule_name:ident : $($head_name:ident $head_inner:tt),* <- $($($body_name:ident $inner:tt)&*)|*) => {
+      rule!($holmes, $rule_name : $($head_name $head_inner),* <- $($($body_name $inner)&*)|*, {})
+  };
+  ($rule_name:ident : $($head_name:ident $head_inner:tt),* <= $($($body_name:ident $inner:tt)&*)|*) => {
+    |holmes: &mut ::holmes::Engine<_>| {
+      rule!(holmes, $rule_name : $($head_name $head_inner),* <= $($($body_name $inner)&*)|*, {})
     }
   };
   ($rule_name:ident : $($head_name:ident $head_inner:tt),* <=
-   $($body_name:ident $inner:tt)&*, {$(let $bind:tt = $hexpr:tt);*}) => {
-    |holmes: &mut ::holmes::Engine| {
+   $($($body_name:ident $inner:tt)&*)|*, {$(let $bind:tt = $hexpr:tt);*}) => {
+    |holmes: &mut ::holmes::Engine<_>| {
       rule!(holmes, $rule_name : $($head_name $head_inner),* <=
-                    $($body_name $inner)&*, {$(let $bind = $hexpr);*})
+                    $($($body_name $inner)&*)|*, {$(let $bind = $hexpr);*})
+    }
+  };
+  ($rule_name:ident : $($head_name:ident $head_inner:tt),* <- $($($body_name:ident $inner:tt)&*)|*) => {
+    |holmes: &mut ::holmes::Engine<_>| {
+      rule!(holmes, $rule_name : $($head_name $head_inner),* <- $($($body_name $inner)&*)|*, {})
+    }
+  };
+  ($rule_name:ident : $($head_name:ident $head_inner:tt),* <-
+   $($($body_name:ident $inner:tt)&*)|*, {$(let $bind:tt = $hexpr:tt);*}) => {
+    |holmes: &mut ::holmes::Engine<_>| {
+      rule!(holmes, $rule_name : $($head_name $head_inner),* <-
+                    $($($body_name $inner)&*)|*, {$(let $bind = $hexpr);*})
     }
   };
 
@@ -276,7 +430,7 @@ macro_rules! func {
                      })})
   }};
   (let $name:ident : $src:tt -> $dst:tt = $body:expr) => {
-    |holmes: &mut ::holmes::Engine| {
+    |holmes: &mut ::holmes::Engine<_>| {
       func!(holmes, let $name : $src -> $dst = $body)
     }
   };
@@ -292,6 +446,63 @@ pub mod internal {
     //! the documentation in here may be useful for understanding the EDSL
     //! structure.
 
+    /// Builds a rule head, returning the clause along with the list of
+    /// aggregated slots for use in a `Rule`.
+    ///
+    /// Slots are as per `clause_match!`, but may also be of the form
+    /// `{agg slot}` to aggregate them (see `aggregate!`).
+    #[macro_export]
+    macro_rules! head_clause {
+        ($holmes:ident, $vars:ident, $next:ident, $pred_name:ident($($m:tt),*)) => {{
+            let slots: Vec<(::holmes::engine::types::MatchExpr,
+                            Option<::holmes::engine::types::Aggregate>)> =
+                vec![$(head_match!($vars, $next, $m)),*];
+            let mut args = Vec::new();
+            let mut aggregates = Vec::new();
+            for (slot, (arg, agg)) in slots.into_iter().enumerate() {
+                args.push(arg);
+                if let Some(agg) = agg {
+                    aggregates.push((slot, agg));
+                }
+            }
+            (::holmes::engine::types::Clause {
+                pred_name: stringify!($pred_name).to_string(),
+                args: args,
+                negated: false
+            }, aggregates)
+        }};
+        ($holmes:ident, $vars:ident, $next:ident, $pred_name:ident $inner:tt) => {{
+            (clause!($holmes, $vars, $next, $pred_name $inner), Vec::new())
+        }};
+    }
+
+    /// Generates a head slot, along with its aggregate if it has one
+    #[macro_export]
+    macro_rules! head_match {
+        ($vars:ident, $n:ident, {$agg:ident $m:tt}) => {
+            (clause_match!($vars, $n, $m), Some(aggregate!($agg)))
+        };
+        ($vars:ident, $n:ident, $m:tt) => {
+            (clause_match!($vars, $n, $m), None)
+        };
+    }
+
+    /// Names an `Aggregate`
+    ///
+    /// * `count` -> `Aggregate::Count`
+    /// * `min` -> `Aggregate::Min`
+    /// * `max` -> `Aggregate::Max`
+    /// * `sum` -> `Aggregate::Sum`
+    /// * `collect` -> `Aggregate::Collect`
+    #[macro_export]
+    macro_rules! aggregate {
+        (count) => { ::holmes::engine::types::Aggregate::Count };
+        (min) => { ::holmes::engine::types::Aggregate::Min };
+        (max) => { ::holmes::engine::types::Aggregate::Max };
+        (sum) => { ::holmes::engine::types::Aggregate::Sum };
+        (collect) => { ::holmes::engine::types::Aggregate::Collect };
+    }
+
     /// Given a value and a type it is believed to be, unpack it to the greatest
     /// extent possible (e.g. unpack through tupling and lists)
     #[macro_export]
@@ -408,7 +619,7 @@ pub mod internal {
       match $vars.entry(stringify!($m).to_string()) {
         Occupied(entry) => Var(*entry.get()),
         Vacant(entry) => {
-          $n = $n + 1;
+          $n += 1;
           entry.insert($n - 1);
           Var($n - 1)
         }
@@ -0,0 +1,232 @@
+// Static type checking of rules, so that mistakes are reported when a rule
+// is registered rather than deep inside the event loop when it runs.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use fact_db::FactDB;
+use pg::dyn::Type;
+use pg::dyn::types::{List, Tuple, UInt64};
+use super::types::{Aggregate, BindExpr, Clause, Expr, Func, MatchExpr, Predicate, Rule, Var};
+use super::{ErrorKind, Result};
+
+// Types of a rule's variables, as far as they have been bound
+struct Checker<'a> {
+    fdb: &'a dyn FactDB,
+    funcs: &'a HashMap<String, Rc<Func>>,
+    rule: &'a Rule,
+    vars: HashMap<Var, Type>,
+}
+
+// Checks that a rule's body, where clauses and heads agree on the type of
+// every variable, and with the predicates and functions they use.
+pub fn check_rule(fdb: &dyn FactDB, funcs: &HashMap<String, Rc<Func>>, rule: &Rule) -> Result<()> {
+    let mut checker = Checker {
+        fdb,
+        funcs,
+        rule,
+        vars: HashMap::new(),
+    };
+    // Negated clauses only filter, so their variables come from the rest
+    let positive = rule.body.iter().filter(|clause| !clause.negated);
+    let negative = rule.body.iter().filter(|clause| clause.negated);
+    for clause in positive.chain(negative) {
+        checker.body_clause(clause)?;
+    }
+    for wc in rule.wheres.iter() {
+        let type_ = checker.expr(&wc.rhs)?;
+        checker.bind(&wc.lhs, type_)?;
+    }
+    for head in rule.head.iter() {
+        checker.head_clause(head)?;
+    }
+    Ok(())
+}
+
+impl<'a> Checker<'a> {
+    fn fail<T>(&self, msg: String) -> Result<T> {
+        bail!(ErrorKind::Type(format!("Rule {}: {}", self.rule.name, msg)))
+    }
+
+    fn predicate(&self, clause: &Clause) -> Result<Predicate> {
+        let pred = match self.fdb.get_predicate(&clause.pred_name) {
+            Some(pred) => pred,
+            None => return self.fail(format!("{} is not a registered predicate", clause.pred_name)),
+        };
+        if clause.args.len() != pred.fields.len() {
+            return self.fail(format!(
+                "{} takes {} arguments, but was given {}",
+                pred.name,
+                pred.fields.len(),
+                clause.args.len()
+            ));
+        }
+        Ok(pred)
+    }
+
+    fn body_clause(&mut self, clause: &Clause) -> Result<()> {
+        let pred = self.predicate(clause)?;
+        for (arg, field) in clause.args.iter().zip(pred.fields.iter()) {
+            // A negated clause has no facts to bind a variable from
+            if let MatchExpr::Var(var) = *arg {
+                if clause.negated && !self.vars.contains_key(&var) {
+                    return self.fail(format!(
+                        "Variable {} is only bound under negation in {}",
+                        var,
+                        clause.pred_name
+                    ));
+                }
+            }
+            self.match_expr(arg, &field.type_)?;
+        }
+        Ok(())
+    }
+
+    // Matches a value of the given type, binding any new variable to it
+    fn match_expr(&mut self, expr: &MatchExpr, type_: &Type) -> Result<()> {
+        match *expr {
+            MatchExpr::Unbound => Ok(()),
+            MatchExpr::Const(ref val) => self.expect(&val.type_(), type_),
+            MatchExpr::Var(var) => {
+                match self.vars.get(&var).cloned() {
+                    Some(bound) => {
+                        if &bound != type_ {
+                            return self.fail(format!(
+                                "Variable {} is used as both {:?} and {:?}",
+                                var,
+                                bound,
+                                type_
+                            ));
+                        }
+                    }
+                    None => {
+                        self.vars.insert(var, type_.clone());
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn expect(&self, found: &Type, expected: &Type) -> Result<()> {
+        if found != expected {
+            return self.fail(format!("Expected {:?}, found {:?}", expected, found));
+        }
+        Ok(())
+    }
+
+    fn expr(&self, expr: &Expr) -> Result<Type> {
+        match *expr {
+            Expr::Var(var) => {
+                match self.vars.get(&var) {
+                    Some(type_) => Ok(type_.clone()),
+                    None => self.fail(format!("Variable {} is used before it is bound", var)),
+                }
+            }
+            Expr::Val(ref val) => Ok(val.type_()),
+            Expr::App(ref fun_name, ref args) => {
+                let func = match self.funcs.get(fun_name) {
+                    Some(func) => func,
+                    None => return self.fail(format!("Function {} is not registered", fun_name)),
+                };
+                let mut arg_types = Vec::new();
+                for arg in args.iter() {
+                    arg_types.push(self.expr(arg)?);
+                }
+                // Several arguments are passed as a tuple, as in `eval`
+                let input = if arg_types.len() == 1 {
+                    arg_types.pop().unwrap()
+                } else {
+                    Tuple::new(arg_types) as Type
+                };
+                if *input != *func.input_type {
+                    return self.fail(format!(
+                        "Function {} takes {:?}, but was given {:?}",
+                        fun_name,
+                        func.input_type,
+                        input
+                    ));
+                }
+                Ok(func.output_type.clone())
+            }
+        }
+    }
+
+    fn bind(&mut self, lhs: &BindExpr, type_: Type) -> Result<()> {
+        match *lhs {
+            BindExpr::Normal(ref expr) => self.match_expr(expr, &type_),
+            BindExpr::Destructure(ref lhss) => {
+                if let Some(tuple) = type_.inner().downcast_ref::<Tuple>() {
+                    if tuple.elements().len() != lhss.len() {
+                        return self.fail(format!(
+                            "Cannot destructure {:?} into {} parts",
+                            type_,
+                            lhss.len()
+                        ));
+                    }
+                    for (lhs, elem) in lhss.iter().zip(tuple.elements().iter()) {
+                        self.bind(lhs, elem.clone())?;
+                    }
+                    return Ok(());
+                }
+                if let Some(list) = type_.inner().downcast_ref::<List>() {
+                    for lhs in lhss.iter() {
+                        self.bind(lhs, list.elem().clone())?;
+                    }
+                    return Ok(());
+                }
+                self.fail(format!("Cannot destructure {:?}", type_))
+            }
+            BindExpr::Iterate(ref inner) => {
+                match type_.inner().downcast_ref::<List>() {
+                    Some(list) => self.bind(inner, list.elem().clone()),
+                    None => self.fail(format!("Cannot iterate over {:?}", type_)),
+                }
+            }
+        }
+    }
+
+    fn head_clause(&self, head: &Clause) -> Result<()> {
+        let pred = self.predicate(head)?;
+        for (slot, (arg, field)) in head.args.iter().zip(pred.fields.iter()).enumerate() {
+            let agg = self.rule
+                .aggregates
+                .iter()
+                .find(|&&(agg_slot, _)| agg_slot == slot)
+                .map(|&(_, agg)| agg);
+            let found = match *arg {
+                MatchExpr::Unbound if agg == Some(Aggregate::Count) => None,
+                MatchExpr::Unbound => {
+                    return self.fail(format!("Slot {} of {} is left unbound", slot, pred.name))
+                }
+                MatchExpr::Const(ref val) => Some(val.type_()),
+                MatchExpr::Var(var) => {
+                    match self.vars.get(&var) {
+                        Some(type_) => Some(type_.clone()),
+                        None => {
+                            return self.fail(format!(
+                                "Variable {} in the head of {} is never bound",
+                                var,
+                                pred.name
+                            ))
+                        }
+                    }
+                }
+            };
+            // Aggregates change the type of what ends up in the slot
+            let produced = match (agg, found) {
+                (Some(Aggregate::Count), _) => Arc::new(UInt64) as Type,
+                (Some(Aggregate::Sum), Some(type_)) => {
+                    self.expect(&type_, &(Arc::new(UInt64) as Type))?;
+                    type_
+                }
+                (Some(Aggregate::Collect), Some(type_)) => List::new(type_) as Type,
+                (_, Some(type_)) => type_,
+                (_, None) => unreachable!(),
+            };
+            self.expect(&produced, &field.type_)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,372 @@
+// Portable dumps of a fact database, for `Engine::export` and
+// `Engine::import`. A dump is a JSON document holding the predicate
+// signatures, the facts, and the contents of the `LargeBytes` values they
+// refer to, which would otherwise only be stored under `HOLMES_STORAGE`:
+//
+// {"format": "holmes-dump", "version": 1,
+//  "predicates": [{"name": .., "description": ..,
+//                  "fields": [{"name": .., "description": .., "type": ..,
+//                              "join": ..}, ..]}, ..],
+//  "facts": {"<predicate>": [[<arg>, ..], ..], ..},
+//  "blobs": {"<sha256>": "<hex>", ..}}
+//
+// Types are written as their name, `{"list": <type>}` or
+// `{"tuple": [<type>, ..]}`. Values are written according to the type of
+// their field: integers, booleans and strings as themselves, bytes as hex,
+// large bytes as their hash, and lists and tuples as arrays. Custom types
+// have no such encoding, so predicates using them cannot be exported.
+
+use std::collections::BTreeMap;
+
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::Json;
+use sha2::{Digest, Sha256};
+
+use fact_db::FactDB;
+use pg::dyn::{Type, Value};
+use pg::dyn::{types, values};
+use super::types::{Clause, Fact, Field, MatchExpr, Predicate};
+use super::{ErrorKind, Result};
+
+const FORMAT: &str = "holmes-dump";
+const VERSION: u64 = 1;
+
+fn malformed<T>(what: &str) -> Result<T> {
+    bail!(ErrorKind::Invalid(format!("Malformed dump: {}", what)))
+}
+
+fn object(entries: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        entries
+            .into_iter()
+            .map(|(key, val)| (key.to_string(), val))
+            .collect(),
+    )
+}
+
+fn opt_string(s: &Option<String>) -> Json {
+    match *s {
+        Some(ref s) => Json::String(s.clone()),
+        None => Json::Null,
+    }
+}
+
+fn get_opt_string(json: &Json, key: &str) -> Result<Option<String>> {
+    match json.find(key) {
+        None | Some(&Json::Null) => Ok(None),
+        Some(Json::String(s)) => Ok(Some(s.clone())),
+        _ => malformed(&format!("{} should be a string", key)),
+    }
+}
+
+fn get_string<'a>(json: &'a Json, key: &str) -> Result<&'a str> {
+    match json.find(key).and_then(|val| val.as_string()) {
+        Some(s) => Ok(s),
+        None => malformed(&format!("{} should be a string", key)),
+    }
+}
+
+fn get_array<'a>(json: &'a Json, key: &str) -> Result<&'a Vec<Json>> {
+    match json.find(key).and_then(|val| val.as_array()) {
+        Some(array) => Ok(array),
+        None => malformed(&format!("{} should be an array", key)),
+    }
+}
+
+// Writes out everything stored in `fdb`
+pub fn encode(fdb: &dyn FactDB) -> Result<Json> {
+    let mut preds = Vec::new();
+    let mut facts = BTreeMap::new();
+    let mut blobs = BTreeMap::new();
+    for pred in fdb.list_predicates() {
+        if let Some(field) = pred.fields.iter().find(|field| !exportable(&field.type_)) {
+            bail!(ErrorKind::Type(format!(
+                "Cannot export {}, as values of type {:?} have no encoding",
+                pred.name,
+                field.type_
+            )));
+        }
+        let query = vec![
+            Clause {
+                pred_name: pred.name.clone(),
+                args: (0..pred.fields.len()).map(MatchExpr::Var).collect(),
+                negated: false,
+            },
+        ];
+        let mut rows = Vec::new();
+        for (_, args) in fdb.search_facts(&query, None)? {
+            let mut row = Vec::new();
+            for (arg, field) in args.iter().zip(pred.fields.iter()) {
+                row.push(encode_value(&field.type_, arg, &mut blobs)?);
+            }
+            rows.push(Json::Array(row));
+        }
+        facts.insert(pred.name.clone(), Json::Array(rows));
+        preds.push(encode_predicate(&pred)?);
+    }
+    Ok(object(vec![
+        ("format", Json::String(FORMAT.to_string())),
+        ("version", Json::U64(VERSION)),
+        ("predicates", Json::Array(preds)),
+        ("facts", Json::Object(facts)),
+        ("blobs", Json::Object(blobs)),
+    ]))
+}
+
+fn encode_predicate(pred: &Predicate) -> Result<Json> {
+    let mut fields = Vec::new();
+    for field in pred.fields.iter() {
+        fields.push(object(vec![
+            ("name", opt_string(&field.name)),
+            ("description", opt_string(&field.description)),
+            ("type", encode_type(&field.type_)?),
+            ("join", opt_string(&field.join)),
+        ]));
+    }
+    Ok(object(vec![
+        ("name", Json::String(pred.name.clone())),
+        ("description", opt_string(&pred.description)),
+        ("fields", Json::Array(fields)),
+    ]))
+}
+
+fn encode_type(type_: &Type) -> Result<Json> {
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        return Ok(object(vec![("list", encode_type(list.elem())?)]));
+    }
+    if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+        let elems = tuple.elements().iter().map(encode_type).collect::<Result<_>>()?;
+        return Ok(object(vec![("tuple", Json::Array(elems))]));
+    }
+    match type_.name() {
+        Some(name) => Ok(Json::String(name.to_string())),
+        None => bail!(ErrorKind::Type(format!("Cannot export the type {:?}", type_))),
+    }
+}
+
+// Whether values of a type can be written by `encode_value`
+fn exportable(type_: &Type) -> bool {
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        return exportable(list.elem());
+    }
+    if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+        return tuple.elements().iter().all(exportable);
+    }
+    matches!(
+        type_.name(),
+        Some("uint64") | Some("bool") | Some("string") | Some("largestring") | Some("bytes") |
+            Some("largebytes")
+    )
+}
+
+fn encode_value(type_: &Type, val: &Value, blobs: &mut BTreeMap<String, Json>) -> Result<Json> {
+    let elem_types = if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        Some(vec![list.elem().clone(); val.get().downcast_ref::<Vec<Value>>().unwrap().len()])
+    } else { type_.inner().downcast_ref::<types::Tuple>().map(|tuple| tuple.elements().to_vec()) };
+    if let Some(elem_types) = elem_types {
+        let elems = val.get().downcast_ref::<Vec<Value>>().unwrap();
+        let mut out = Vec::new();
+        for (elem_type, elem) in elem_types.iter().zip(elems.iter()) {
+            out.push(encode_value(elem_type, elem, blobs)?);
+        }
+        return Ok(Json::Array(out));
+    }
+    Ok(match type_.name() {
+        Some("uint64") => Json::U64(*val.get().downcast_ref::<u64>().unwrap()),
+        Some("bool") => Json::Boolean(*val.get().downcast_ref::<bool>().unwrap()),
+        Some("string") |
+        Some("largestring") => Json::String(val.get().downcast_ref::<String>().unwrap().clone()),
+        Some("bytes") => Json::String(val.get().downcast_ref::<Vec<u8>>().unwrap().to_hex()),
+        Some("largebytes") => {
+            let large = val.inner().downcast_ref::<values::LargeBytes>().unwrap();
+            if !blobs.contains_key(large.hash()) {
+                blobs.insert(large.hash().to_string(), Json::String(large.contents()?.to_hex()));
+            }
+            Json::String(large.hash().to_string())
+        }
+        _ => bail!(ErrorKind::Type(format!("Cannot export values of type {:?}", type_))),
+    })
+}
+
+// Reads back a dump, checking it against the types registered with `fdb`.
+// The contents of large values are put back into storage, but only once
+// the whole dump has checked out.
+pub fn decode(fdb: &dyn FactDB, dump: &Json) -> Result<(Vec<Predicate>, Vec<Fact>)> {
+    if dump.find("format").and_then(|format| format.as_string()) != Some(FORMAT) {
+        return malformed("not a Holmes dump");
+    }
+    match dump.find("version").and_then(|version| version.as_u64()) {
+        Some(VERSION) => (),
+        _ => return malformed("unsupported version"),
+    }
+    let blobs = match dump.find("blobs").and_then(|blobs| blobs.as_object()) {
+        Some(blobs) => blobs,
+        None => return malformed("blobs should be an object"),
+    };
+    let mut contents = Vec::new();
+    for (hash, hex) in blobs.iter() {
+        let bytes = match hex.as_string().map(|hex| hex.from_hex()) {
+            Some(Ok(bytes)) => bytes,
+            _ => return malformed(&format!("blob {} should be hex", hash)),
+        };
+        let mut hasher = Sha256::default();
+        hasher.input(&bytes);
+        if hasher.result().to_hex() != *hash {
+            return malformed(&format!("blob {} does not match its hash", hash));
+        }
+        contents.push(bytes);
+    }
+    let mut preds = Vec::new();
+    for pred in get_array(dump, "predicates")?.iter() {
+        preds.push(decode_predicate(fdb, pred)?);
+    }
+    let mut checked = Vec::new();
+    for pred in preds.iter() {
+        let rows = match dump.find_path(&["facts", pred.name.as_str()]) {
+            Some(Json::Array(rows)) => rows,
+            None => continue,
+            _ => return malformed(&format!("facts for {} should be an array", pred.name)),
+        };
+        for row in rows.iter() {
+            let args = match *row {
+                Json::Array(ref args) if args.len() == pred.fields.len() => args,
+                _ => {
+                    bail!(ErrorKind::Type(format!(
+                        "{} does not match the signature of {}",
+                        row,
+                        pred.name
+                    )))
+                }
+            };
+            for (arg, field) in args.iter().zip(pred.fields.iter()) {
+                check_value(&field.type_, arg, blobs)?;
+            }
+            checked.push((pred, args));
+        }
+    }
+    // Large values can only be built once their contents are stored
+    for bytes in contents {
+        values::LargeBytes::new(bytes);
+    }
+    let mut facts = Vec::new();
+    for (pred, args) in checked {
+        facts.push(Fact {
+            pred_name: pred.name.clone(),
+            args: args.iter()
+                .zip(pred.fields.iter())
+                .map(|(arg, field)| decode_value(&field.type_, arg))
+                .collect(),
+        });
+    }
+    Ok((preds, facts))
+}
+
+fn decode_predicate(fdb: &dyn FactDB, json: &Json) -> Result<Predicate> {
+    let mut fields = Vec::new();
+    for field in get_array(json, "fields")?.iter() {
+        fields.push(Field {
+            name: get_opt_string(field, "name")?,
+            description: get_opt_string(field, "description")?,
+            type_: match field.find("type") {
+                Some(type_) => decode_type(fdb, type_)?,
+                None => return malformed("field without a type"),
+            },
+            join: get_opt_string(field, "join")?,
+        });
+    }
+    Ok(Predicate {
+        name: get_string(json, "name")?.to_string(),
+        description: get_opt_string(json, "description")?,
+        fields,
+    })
+}
+
+fn decode_type(fdb: &dyn FactDB, json: &Json) -> Result<Type> {
+    if let Some(name) = json.as_string() {
+        return match fdb.get_type(name) {
+            Some(type_) => Ok(type_),
+            None => bail!(ErrorKind::Type(format!("{} is not a registered type", name))),
+        };
+    }
+    if let Some(elem) = json.find("list") {
+        return Ok(types::List::new(decode_type(fdb, elem)?) as Type);
+    }
+    if let Some(Json::Array(elems)) = json.find("tuple") {
+        let elems = elems
+            .iter()
+            .map(|elem| decode_type(fdb, elem))
+            .collect::<Result<_>>()?;
+        return Ok(types::Tuple::new(elems) as Type);
+    }
+    malformed(&format!("{} is not a type", json))
+}
+
+// Checks that `json` encodes a value of type `type_`, as `decode_value`
+// expects
+fn check_value(type_: &Type, json: &Json, blobs: &BTreeMap<String, Json>) -> Result<()> {
+    let mismatch = || ErrorKind::Type(format!("{} is not a {:?}", json, type_));
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        for elem in json.as_array().ok_or_else(&mismatch)?.iter() {
+            check_value(list.elem(), elem, blobs)?;
+        }
+        return Ok(());
+    }
+    if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+        let elems = match json.as_array() {
+            Some(elems) if elems.len() == tuple.elements().len() => elems,
+            _ => bail!(mismatch()),
+        };
+        for (elem_type, elem) in tuple.elements().iter().zip(elems.iter()) {
+            check_value(elem_type, elem, blobs)?;
+        }
+        return Ok(());
+    }
+    match (type_.name(), json) {
+        (Some("uint64"), &Json::U64(_)) |
+        (Some("bool"), &Json::Boolean(_)) |
+        (Some("string"), &Json::String(_)) |
+        (Some("largestring"), &Json::String(_)) => Ok(()),
+        (Some("bytes"), Json::String(hex)) => {
+            hex.from_hex().map_err(|_| mismatch())?;
+            Ok(())
+        }
+        (Some("largebytes"), Json::String(hash)) => {
+            if !blobs.contains_key(hash) {
+                return malformed(&format!("blob {} is missing", hash));
+            }
+            Ok(())
+        }
+        _ => bail!(mismatch()),
+    }
+}
+
+// Builds the value `json` encodes, which `check_value` has accepted and
+// whose large contents are stored
+fn decode_value(type_: &Type, json: &Json) -> Value {
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        let vals = json.as_array()
+            .unwrap()
+            .iter()
+            .map(|elem| decode_value(list.elem(), elem))
+            .collect();
+        return values::List::of(list.elem().clone(), vals) as Value;
+    }
+    if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+        let vals = tuple
+            .elements()
+            .iter()
+            .zip(json.as_array().unwrap().iter())
+            .map(|(elem_type, elem)| decode_value(elem_type, elem))
+            .collect();
+        return values::Tuple::new(vals) as Value;
+    }
+    match (type_.name(), json) {
+        (Some("uint64"), &Json::U64(n)) => values::UInt64::new(n) as Value,
+        (Some("bool"), &Json::Boolean(b)) => values::Bool::new(b) as Value,
+        (Some("string"), &Json::String(ref s)) |
+        (Some("largestring"), &Json::String(ref s)) => values::String::new(s.clone()) as Value,
+        (Some("bytes"), Json::String(hex)) => values::Bytes::new(hex.from_hex().unwrap()) as Value,
+        (Some("largebytes"), Json::String(hash)) => values::LargeBytes::from_hash(hash) as Value,
+        _ => panic!("{} was not checked against {:?}", json, type_),
+    }
+}
@@ -4,15 +4,22 @@
 //! maintenance.
 
 pub mod types;
+mod check;
+mod dump;
 
 use std::collections::hash_map::HashMap;
+use std::collections::{HashSet, VecDeque};
 use pg::dyn::{Type, Value};
 use pg::dyn::values;
-use self::types::{BindExpr, Clause, Expr, Fact, Func, MatchExpr, Predicate, Rule};
-use pg::{FactId, PgDB};
+use self::types::{Aggregate, BindExpr, Clause, Derivation, Expr, Fact, Func, MatchExpr,
+                  Migration, Predicate, Rule, Var, WhereClause};
+use pg::PgDB;
+use fact_db::{FactDB, FactId};
+use lang::{self, Query, Statement};
 use tokio_core::reactor::Handle;
 use std::cell::{Cell, RefCell};
-use std::rc::Rc;
+use std::ops::Deref;
+use std::rc::{Rc, Weak};
 use futures::{Async, Future, Poll, Stream};
 use futures::future::{FutureResult, result};
 use futures::task::{Task, current};
@@ -26,26 +33,74 @@ enum RuleState {
     ShutDown,
 }
 
+#[derive(Debug)]
+struct SignalState {
+    state: Cell<RuleState>,
+    referents: RefCell<Vec<Task>>,
+    task: RefCell<Option<Task>>,
+    waiters: RefCell<Vec<Signal>>,
+    // Rules take turns through the scheduler, at their priority
+    schedule: Option<(Rc<Scheduler>, i32)>,
+}
+
 #[derive(Clone, Debug)]
-struct Signal {
-    state: Rc<Cell<RuleState>>,
-    referents: Rc<RefCell<Vec<Task>>>,
-    task: Rc<RefCell<Option<Task>>>,
+struct Signal(Rc<SignalState>);
+
+impl Deref for Signal {
+    type Target = SignalState;
+    fn deref(&self) -> &SignalState {
+        &self.0
+    }
 }
 
+// Lists of signals to fire when a predicate gets new facts only hold them
+// weakly, so that a signal goes away once whatever awaits it, such as a
+// subscription, is dropped.
+type WakeList = Rc<RefCell<Vec<Weak<SignalState>>>>;
+
 impl Signal {
     fn new() -> Self {
-        Signal {
-            state: Rc::new(Cell::new(RuleState::Idle)),
-            referents: Rc::new(RefCell::new(Vec::new())),
-            task: Rc::new(RefCell::new(None)),
-        }
+        Signal::with_schedule(None)
+    }
+
+    fn scheduled(scheduler: Rc<Scheduler>, priority: i32) -> Self {
+        Signal::with_schedule(Some((scheduler, priority)))
+    }
+
+    fn with_schedule(schedule: Option<(Rc<Scheduler>, i32)>) -> Self {
+        Signal(Rc::new(SignalState {
+            state: Cell::new(RuleState::Idle),
+            referents: RefCell::new(Vec::new()),
+            task: RefCell::new(None),
+            waiters: RefCell::new(Vec::new()),
+            schedule,
+        }))
+    }
+
+    fn same(&self, other: &Signal) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+
+    fn downgrade(&self) -> Weak<SignalState> {
+        Rc::downgrade(&self.0)
+    }
+
+    // The live signals on a wake list, forgetting the dead ones
+    fn live(list: &WakeList) -> Vec<Signal> {
+        let mut list = list.borrow_mut();
+        list.retain(|weak| weak.strong_count() > 0);
+        list.iter().filter_map(|weak| weak.upgrade().map(Signal)).collect()
     }
 
     fn refer(&self, task: Task) {
         self.referents.borrow_mut().push(task)
     }
 
+    // Queue up the other signal as soon as we next go dormant
+    fn signal_when_dormant(&self, other: Signal) {
+        self.waiters.borrow_mut().push(other)
+    }
+
     fn await(&self, task: Task) {
         // Only one task can await a signal, if there's already
         // one waiting, there's been a programming error
@@ -56,11 +111,40 @@ impl Signal {
     fn signal(&self) {
         if self.state.get() != RuleState::ShutDown {
             trace!("Queuing new work");
+            if self.state.get() != RuleState::Queued {
+                if let Some((ref scheduler, _)) = self.schedule {
+                    scheduler.enqueue(self);
+                }
+            }
             self.state.set(RuleState::Queued);
-            // If the target of this signal is blocked, unblock it
-            match self.task.borrow_mut().take() {
-                Some(t) => t.notify(),
-                None => (),
+            self.wake();
+        }
+    }
+
+    fn wake(&self) {
+        // If the target of this signal is blocked, unblock it
+        if let Some(t) = self.task.borrow_mut().take() { t.notify() }
+    }
+
+    // Whether the scheduler lets us run now, taking us off its queue if so
+    fn take_turn(&self) -> bool {
+        match self.schedule {
+            Some((ref scheduler, _)) => {
+                if !scheduler.next().is_none_or(|next| next.same(self)) {
+                    return false;
+                }
+                scheduler.remove(self);
+                true
+            }
+            None => true,
+        }
+    }
+
+    // Hands the turn on to the next rule in line
+    fn yield_turn(&self) {
+        if let Some((ref scheduler, _)) = self.schedule {
+            if let Some(next) = scheduler.next() {
+                next.wake();
             }
         }
     }
@@ -74,6 +158,12 @@ impl Signal {
         // They'll wake up from the notify, and so can let us
         // know if they need to be woken up again.
         self.referents.borrow_mut().truncate(0);
+
+        // Rules which were holding off on our account can go now
+        let waiters: Vec<Signal> = self.waiters.borrow_mut().drain(..).collect();
+        for waiter in waiters {
+            waiter.signal();
+        }
     }
 
     fn done(&self) -> FutureResult<(), ()> {
@@ -83,13 +173,20 @@ impl Signal {
             self.state.set(RuleState::Idle);
             self.go_dormant();
         }
+        self.yield_turn();
         result(Ok(()))
     }
 
     fn stop(&self) -> FutureResult<(), ()> {
         trace!("Work loop being terminated");
         self.state.set(RuleState::ShutDown);
+        if let Some((ref scheduler, _)) = self.schedule {
+            scheduler.remove(self);
+        }
+        // Let a work loop blocked on us see that it is over
+        self.wake();
         self.go_dormant();
+        self.yield_turn();
         result(Ok(()))
     }
 
@@ -113,6 +210,11 @@ impl Stream for Signal {
             Running => panic!("Tried to ask for more work while still running"),
             ShutDown => Ok(Async::Ready(None)),
             Queued => {
+                if !self.take_turn() {
+                    trace!("New work arrived, waiting for our turn");
+                    *self.task.borrow_mut() = Some(current());
+                    return Ok(Async::NotReady);
+                }
                 trace!("New work arrived, waking up");
                 self.state.set(Running);
                 Ok(Async::Ready(Some(())))
@@ -121,21 +223,90 @@ impl Stream for Signal {
     }
 }
 
+// Decides which of the rules with queued work runs next: the one with the
+// highest priority, and among those, the one which was queued first.
+struct Scheduler {
+    queue: RefCell<Vec<Signal>>,
+}
+
+impl ::std::fmt::Debug for Scheduler {
+    // Queued signals refer back to the scheduler, so don't print them
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(fmt, "Scheduler {{ queued: {} }}", self.queue.borrow().len())
+    }
+}
+
+impl Scheduler {
+    fn new() -> Self {
+        Scheduler { queue: RefCell::new(Vec::new()) }
+    }
+
+    fn enqueue(&self, signal: &Signal) {
+        let mut queue = self.queue.borrow_mut();
+        if !queue.iter().any(|queued| queued.same(signal)) {
+            queue.push(signal.clone());
+        }
+    }
+
+    fn remove(&self, signal: &Signal) {
+        self.queue.borrow_mut().retain(|queued| !queued.same(signal));
+    }
+
+    fn next(&self) -> Option<Signal> {
+        let priority = |signal: &Signal| signal.schedule.as_ref().map_or(0, |s| s.1);
+        let queue = self.queue.borrow();
+        let mut best: Option<&Signal> = None;
+        for signal in queue.iter() {
+            if best.is_none_or(|best| priority(signal) > priority(best)) {
+                best = Some(signal);
+            }
+        }
+        best.cloned()
+    }
+}
+
+/// A failure while running a rule. The rule is disabled once this happens,
+/// but the other rules carry on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuleError {
+    /// Name of the rule which failed
+    pub rule: String,
+    /// Description of the failure, including its causes
+    pub message: String,
+}
+
+impl ::std::fmt::Display for RuleError {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(fmt, "Rule {} failed: {}", self.rule, self.message)
+    }
+}
+
 /// Future representing the quiescence of the Holmes engine
 /// See `Engine::quiesce()` to create one
+///
+/// If any rules failed while it was waiting, it completes with their errors
+/// once the remaining rules are done.
 pub struct Quiescence {
     signals: Vec<Signal>,
+    errors: Rc<RefCell<Vec<RuleError>>>,
+    // Errors which were already recorded when the future was created
+    seen: usize,
 }
 
 impl Quiescence {
-    fn new(signals: Vec<Signal>) -> Self {
-        Quiescence { signals: signals }
+    fn new(signals: Vec<Signal>, errors: Rc<RefCell<Vec<RuleError>>>) -> Self {
+        let seen = errors.borrow().len();
+        Quiescence {
+            signals,
+            errors,
+            seen,
+        }
     }
 }
 
 impl Future for Quiescence {
     type Item = ();
-    type Error = ();
+    type Error = Vec<RuleError>;
     fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
         trace!("Checking quiescence");
         for signal in self.signals.iter() {
@@ -144,10 +315,117 @@ impl Future for Quiescence {
                 return Ok(Async::NotReady);
             }
         }
+        let errors = self.errors.borrow();
+        if errors.len() > self.seen {
+            return Err(errors[self.seen..].to_vec());
+        }
         Ok(Async::Ready(()))
     }
 }
 
+/// Stream of the answers to a query, see `Engine::subscribe()`
+///
+/// Answers already in the database are delivered first, followed by new ones
+/// as the facts they depend on arrive. Each answer is delivered once.
+pub struct Subscription<DB: FactDB = PgDB> {
+    fact_db: Rc<DB>,
+    demand: Rc<Demand>,
+    query: Vec<Clause>,
+    signal: Signal,
+    cursor: Option<FactId>,
+    pending: VecDeque<Vec<Value>>,
+}
+
+impl<DB: FactDB + 'static> Subscription<DB> {
+    // Looks for answers using facts we have not seen before
+    fn search(&mut self) -> Result<Vec<Vec<Value>>> {
+        for clause in self.query.iter() {
+            demand(&*self.fact_db, &self.demand, clause)?;
+        }
+        let states = self.fact_db.search_facts(&self.query, self.cursor)?;
+        self.cursor = states
+            .iter()
+            .flat_map(|x| x.0.iter())
+            .max()
+            .map(|x| x + 1)
+            .or(self.cursor);
+        Ok(states.into_iter().map(|x| x.1).collect())
+    }
+}
+
+impl<DB: FactDB + 'static> Stream for Subscription<DB> {
+    type Item = Vec<Value>;
+    type Error = Error;
+    fn poll(&mut self) -> Poll<Option<Self::Item>, Self::Error> {
+        loop {
+            if let Some(answer) = self.pending.pop_front() {
+                return Ok(Async::Ready(Some(answer)));
+            }
+            match self.signal.poll() {
+                Ok(Async::Ready(Some(()))) => (),
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                _ => return Ok(Async::Ready(None)),
+            }
+            trace!("Checking subscription for new answers");
+            let res = self.search();
+            // Only there to end a rule's work loop, nothing to wait on
+            let _ = self.signal.done();
+            self.pending.extend(res?);
+        }
+    }
+}
+
+/// Scheduling settings for a rule, see `Engine::new_rule_with()`
+///
+/// Budgets are checked before each activation of the rule, so a rule may
+/// overrun its time budget by up to one activation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RuleConfig {
+    /// Rules with queued work run in order of priority, highest first, and
+    /// otherwise in the order their work arrived.
+    pub priority: i32,
+    /// Stop the rule once it has spent this long running
+    pub time_budget: Option<Duration>,
+    /// Stop the rule once it has been activated this many times
+    pub max_activations: Option<usize>,
+}
+
+/// An index the engine made for the searches of its rules, see
+/// `Engine::index_report()`
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexUse {
+    /// Predicate whose facts are indexed
+    pub pred_name: String,
+    /// Slots the facts are indexed by
+    pub slots: Vec<usize>,
+    /// Names of the registered rules which look facts up by these slots
+    pub rules: Vec<String>,
+}
+
+impl ::std::fmt::Display for IndexUse {
+    fn fmt(&self, fmt: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        let slots: Vec<String> = self.slots.iter().map(|slot| slot.to_string()).collect();
+        write!(
+            fmt,
+            "{}({}) used by {}",
+            self.pred_name,
+            slots.join(", "),
+            if self.rules.is_empty() {
+                "no rules".to_string()
+            } else {
+                self.rules.join(", ")
+            }
+        )
+    }
+}
+
+/// Refers to a rule registered with `Engine::new_rule()`, so that it can be
+/// paused, resumed or removed later.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RuleHandle {
+    id: usize,
+}
+
 #[derive(Debug, Clone)]
 /// RuleProfile contains execution information about a single rule
 pub struct RuleProfile {
@@ -169,12 +447,19 @@ pub struct RuleProfile {
     pub max_insert_time: Duration,
     /// Worst case where clause
     pub max_compute_time: Duration,
+    /// Number of times the rule was activated
+    pub activations: usize,
+    /// Scheduling settings the rule was registered with
+    pub config: RuleConfig,
+    /// Whether the rule was stopped for going over its budget
+    pub exhausted: bool,
 }
 
 impl RuleProfile {
+    /// Creates an empty profile for the named rule
     pub fn new(name: String) -> Self {
         RuleProfile {
-            name: name,
+            name,
             select_time: Duration::new(0, 0),
             insert_time: Duration::new(0, 0),
             rule_time: Duration::new(0, 0),
@@ -182,46 +467,126 @@ impl RuleProfile {
             max_select_time: Duration::new(0, 0),
             max_insert_time: Duration::new(0, 0),
             max_compute_time: Duration::new(0, 0),
+            activations: 0,
+            config: RuleConfig::default(),
+            exhausted: false,
         }
     }
+    /// Records time spent inserting derived facts
     pub fn add_insert_time(&mut self, d: Duration) {
         self.insert_time += d;
         if d > self.max_insert_time {
             self.max_insert_time = d;
         }
     }
+    /// Records time spent searching for matches
     pub fn add_select_time(&mut self, d: Duration) {
         self.select_time += d;
         if d > self.max_select_time {
             self.max_select_time = d;
         }
     }
+    /// Records time spent evaluating where clauses
     pub fn add_compute_time(&mut self, d: Duration) {
         self.compute_time += d;
         if d > self.max_compute_time {
             self.max_compute_time = d;
         }
     }
+    /// Records the total time of an activation
     pub fn add_rule_time(&mut self, d: Duration) {
         self.rule_time += d;
     }
+    /// Whether the rule has used up its time budget or activations
+    pub fn over_budget(&self) -> bool {
+        self.config.time_budget.is_some_and(|budget| self.rule_time >= budget) ||
+            self.config.max_activations.is_some_and(|max| self.activations >= max)
+    }
+}
+
+// A backward rule, along with what it needs to run outside the event loop.
+// A rule with several heads has an entry for each.
+struct BackwardRule {
+    // Distinguishes the entry's cursors
+    id: usize,
+    rule: Rule,
+    head: usize,
+    funcs: HashMap<String, Rc<Func>>,
+    profile: Rc<RefCell<RuleProfile>>,
+}
+
+// Everything the engine needs to revisit a rule after it was registered
+struct RuleEntry {
+    rule: Rule,
+    // Shared by the entries for each alternative body
+    handle: RuleHandle,
+    // Forward rules only, while they are running
+    signal: Option<Signal>,
+    // Backward rules only, while they are running: the wake list entries
+    // made so that rules reading the head see changes to the body
+    links: Vec<(WakeList, Weak<SignalState>)>,
+    cursor: Rc<Cell<Option<FactId>>>,
+    profile: Rc<RefCell<RuleProfile>>,
+    paused: bool,
+}
+
+// Goals are identified by their predicate and the constants asked for
+type Goal = (String, Vec<MatchExpr>);
+
+// Backward rules, indexed by the predicate they produce, along with how far
+// each has got in answering each goal.
+struct Demand {
+    rules: RefCell<HashMap<String, Vec<Rc<BackwardRule>>>>,
+    // Keyed by backward rule id and the constants asked for
+    #[allow(clippy::type_complexity)]
+    cursors: RefCell<HashMap<(usize, Vec<MatchExpr>), Option<FactId>>>,
+    next_id: Cell<usize>,
+    // Goals being answered further up the stack, to cut off recursion
+    active: RefCell<HashSet<Goal>>,
+}
+
+impl Demand {
+    fn new() -> Self {
+        Demand {
+            rules: RefCell::new(HashMap::new()),
+            cursors: RefCell::new(HashMap::new()),
+            active: RefCell::new(HashSet::new()),
+            next_id: Cell::new(0),
+        }
+    }
 }
 
-/// The `Engine` type contains the context necessary to run a Holmes program
-pub struct Engine {
-    fact_db: Rc<PgDB>,
+/// The `Engine` type contains the context necessary to run a Holmes program.
+/// It is generic over the `FactDB` storing its facts, defaulting to `PgDB`.
+pub struct Engine<DB: FactDB = PgDB> {
+    fact_db: Rc<DB>,
     funcs: HashMap<String, Rc<Func>>,
-    rules: HashMap<String, Rc<RefCell<Vec<Signal>>>>,
+    rules: HashMap<String, WakeList>,
+    // Signals of the forward rules producing each predicate, and the
+    // predicates each one's producers read, shared with running rules so
+    // they see rules registered after them
+    producers: Rc<RefCell<HashMap<String, Vec<Signal>>>>,
+    #[allow(clippy::type_complexity)]
+    pred_deps: Rc<RefCell<HashMap<String, Vec<(String, bool)>>>>,
+    demand: Rc<Demand>,
+    rule_entries: Vec<RuleEntry>,
+    next_rule_id: usize,
+    // Indexes made for rules' searches, by predicate and slots, along with
+    // the names of the rules using them
+    indexes: HashMap<(String, Vec<usize>), Vec<String>>,
     rule_profiles: Vec<Rc<RefCell<RuleProfile>>>,
     signals: Vec<Signal>,
+    scheduler: Rc<Scheduler>,
+    rule_errors: Rc<RefCell<Vec<RuleError>>>,
     event_loop: Handle,
     start_time: Instant,
     limiter: Option<Duration>,
 }
 
-#[allow(missing_docs)]
+#[allow(missing_docs, deprecated)]
 mod errors {
-    use pg;
+    use fact_db;
+    use lang;
     use postgres;
     error_chain! {
         errors {
@@ -238,39 +603,133 @@ mod errors {
                 display("Type Error: {}", msg)
             }
         }
+        links {
+            FactDB(fact_db::Error, fact_db::ErrorKind);
+            Lang(lang::Error, lang::ErrorKind);
+        }
         foreign_links {
-            FactDB(pg::Error);
             Postgres(postgres::error::Error);
+            Io(::std::io::Error);
         }
     }
 }
 
 pub use self::errors::*;
 
-fn substitute(clause: &Clause, ans: &Vec<Value>) -> Fact {
+fn substitute(clause: &Clause, ans: &[Value]) -> Result<Fact> {
     use self::types::MatchExpr::*;
-    Fact {
+    let mut args = Vec::new();
+    for m_expr in clause.args.iter() {
+        args.push(match *m_expr {
+            Unbound => {
+                bail!(ErrorKind::Internal(
+                    "Unbound is not allowed in substituted facts".to_string(),
+                ))
+            }
+            Var(n) => {
+                match ans.get(n) {
+                    Some(val) => val.clone(),
+                    None => {
+                        bail!(ErrorKind::Internal(format!(
+                            "Variable {} of {} is not bound",
+                            n,
+                            clause.pred_name
+                        )))
+                    }
+                }
+            }
+            Const(ref v) => v.clone(),
+        });
+    }
+    Ok(Fact {
         pred_name: clause.pred_name.clone(),
-        args: clause
-            .args
-            .iter()
-            .map(|m_expr| match *m_expr {
-                Unbound => panic!("Unbound is not allowed in substituted facts"),
-                Var(ref n) => ans[*n as usize].clone(),
-                Const(ref v) => v.clone(),
-            })
-            .collect(),
+        args,
+    })
+}
+
+// Restricts a body to the answers in which the clause at `pos` matches
+// `fact`, by replacing its slots with the fact's values. Along with the new
+// body, returns what each of the old body's variables became: the constant
+// the fact fixes it to, or its number in the new body. Returns `None` if the
+// fact can't match the clause.
+fn pin_clause(body: &[Clause], pos: usize, fact: &Fact) -> Option<(Vec<Clause>, Vec<MatchExpr>)> {
+    use self::types::MatchExpr::*;
+    let num_vars = body.iter()
+        .flat_map(|clause| clause.args.iter())
+        .filter_map(|arg| match *arg {
+            Var(v) => Some(v + 1),
+            _ => None,
+        })
+        .max()
+        .unwrap_or(0);
+    let mut vars = vec![Unbound; num_vars];
+    for (arg, val) in body[pos].args.iter().zip(fact.args.iter()) {
+        let fixed = Const(val.clone());
+        match *arg {
+            Unbound => (),
+            Const(_) => if *arg != fixed {
+                return None;
+            },
+            Var(v) => {
+                if vars[v] != Unbound && vars[v] != fixed {
+                    return None;
+                }
+                vars[v] = fixed;
+            }
+        }
+    }
+    // Searches expect variables numbered in order of appearance, positive
+    // clauses first
+    let mut next = 0;
+    let positive = body.iter().filter(|clause| !clause.negated);
+    let negative = body.iter().filter(|clause| clause.negated);
+    for clause in positive.chain(negative) {
+        for arg in clause.args.iter() {
+            if let Var(v) = *arg {
+                if vars[v] == Unbound {
+                    vars[v] = Var(next);
+                    next += 1;
+                }
+            }
+        }
     }
+    let pinned = body.iter()
+        .enumerate()
+        .map(|(n, clause)| Clause {
+            pred_name: clause.pred_name.clone(),
+            args: if n == pos {
+                fact.args.iter().map(|val| Const(val.clone())).collect()
+            } else {
+                clause.args
+                    .iter()
+                    .map(|arg| match *arg {
+                        Var(v) => vars[v].clone(),
+                        ref other => other.clone(),
+                    })
+                    .collect()
+            },
+            negated: clause.negated,
+        })
+        .collect();
+    Some((pinned, vars))
 }
 
-impl Engine {
+impl<DB: FactDB + 'static> Engine<DB> {
     /// Create a fresh engine by handing it a fact database to use
-    pub fn new(db: PgDB, handle: Handle) -> Self {
+    pub fn new(db: DB, handle: Handle) -> Self {
         Engine {
             fact_db: Rc::new(db),
             funcs: HashMap::new(),
             rules: HashMap::new(),
+            producers: Rc::new(RefCell::new(HashMap::new())),
+            pred_deps: Rc::new(RefCell::new(HashMap::new())),
+            demand: Rc::new(Demand::new()),
+            rule_entries: Vec::new(),
+            next_rule_id: 0,
+            indexes: HashMap::new(),
             signals: Vec::new(),
+            scheduler: Rc::new(Scheduler::new()),
+            rule_errors: Rc::new(RefCell::new(Vec::new())),
             rule_profiles: Vec::new(),
             event_loop: handle,
             start_time: Instant::now(),
@@ -278,14 +737,6 @@ impl Engine {
         }
     }
 
-    pub fn run_sql(&self, path: &str) {
-        use std::io::Read;
-        let mut fd = ::std::fs::File::open(path).unwrap();
-        let mut sql = String::new();
-        fd.read_to_string(&mut sql);
-        let conn = self.fact_db.conn().unwrap();
-        conn.batch_execute(&sql).unwrap();
-    }
 
     /// For correct operation, limit_time must be called before the installation of
     /// any rules
@@ -298,6 +749,23 @@ impl Engine {
             self.rule_profiles.iter().map(|x| x.borrow().clone()).collect()
     }
 
+    /// List the indexes made for the searches of registered rules, ordered
+    /// by predicate and slots, along with the rules which use each
+    pub fn index_report(&self) -> Vec<IndexUse> {
+        let mut report: Vec<IndexUse> = self.indexes
+            .iter()
+            .map(|((pred_name, slots), rules)| {
+                IndexUse {
+                    pred_name: pred_name.clone(),
+                    slots: slots.clone(),
+                    rules: rules.clone(),
+                }
+            })
+            .collect();
+        report.sort_by(|a, b| (&a.pred_name, &a.slots).cmp(&(&b.pred_name, &b.slots)));
+        report
+    }
+
     /// Seach the type registry for a named type
     /// If present, it returns `Some(type)`, otherwise `None`
     pub fn get_type(&self, name: &str) -> Option<Type> {
@@ -317,25 +785,19 @@ impl Engine {
     pub fn new_predicate(&self, pred: &Predicate) -> Result<()> {
 
         // Verify we have at least one argument
-        if pred.fields.len() == 0 {
+        if pred.fields.is_empty() {
             bail!(ErrorKind::Invalid(
                 "Predicates must have at least one argument.".to_string(),
             ));
         }
 
         // Check for existing predicates/type issues
-        match self.fact_db.get_predicate(&pred.name) {
-            Some(p) => {
-                if pred.fields == p.fields {
-                    // TODO should this be return ()
-                    ()
-                } else {
-                    bail!(ErrorKind::Type(
-                        format!("{:?} != {:?}", pred.fields, p.fields),
-                    ));
-                }
+        if let Some(p) = self.fact_db.get_predicate(&pred.name) {
+            if pred.fields != p.fields {
+                bail!(ErrorKind::Type(
+                    format!("{:?} != {:?}", pred.fields, p.fields),
+                ));
             }
-            None => (),
         }
 
         Ok(self.fact_db.new_predicate(pred)?)
@@ -347,19 +809,190 @@ impl Engine {
         Ok(self.fact_db.get_predicate(name))
     }
 
-    fn get_dep_rules(&mut self, pred: &String) -> Rc<RefCell<Vec<Signal>>> {
+    /// Lists all registered predicates, ordered by name
+    pub fn list_predicates(&self) -> Result<Vec<Predicate>> {
+        Ok(self.fact_db.list_predicates())
+    }
+
+    /// Changes the signature of a registered predicate, converting the facts
+    /// already stored for it by applying the `steps` in order.
+    ///
+    /// * Rules using the predicate must not be registered yet, since they
+    ///   were checked against the old signature
+    /// * Facts which become duplicates of earlier ones are dropped
+    /// * If any step is invalid, nothing is changed
+    pub fn migrate_predicate(&mut self, pred_name: &str, steps: &[Migration]) -> Result<()> {
+        let old = self.fact_db.get_predicate(pred_name).ok_or_else(|| {
+            ErrorKind::Invalid(format!("{} is not a registered predicate", pred_name))
+        })?;
+        if self.pred_deps.borrow().iter().any(|(head, body)| {
+            head == pred_name || body.iter().any(|(dep, _)| dep == pred_name)
+        })
+        {
+            bail!(ErrorKind::Invalid(format!(
+                "{} is used by registered rules, and cannot be migrated",
+                pred_name
+            )));
+        }
+
+        let mut fields = old.fields.clone();
+        #[allow(clippy::type_complexity)]
+        let mut stages: Vec<Box<dyn Fn(Vec<Value>) -> Result<Vec<Value>>>> = Vec::new();
+        for step in steps.iter() {
+            match *step {
+                Migration::AddField(ref field, ref default) => {
+                    if *default.type_() != *field.type_ {
+                        bail!(ErrorKind::Type(format!(
+                            "Default {:?} is not a {:?}",
+                            default,
+                            field.type_
+                        )));
+                    }
+                    if let Some(ref join) = field.join {
+                        if !self.funcs.contains_key(join) {
+                            bail!(ErrorKind::Invalid(
+                                format!("Join function {} is not registered", join),
+                            ));
+                        }
+                    }
+                    fields.push(field.clone());
+                    let default = default.clone();
+                    stages.push(Box::new(move |mut args| {
+                        args.push(default.clone());
+                        Ok(args)
+                    }));
+                }
+                Migration::DropField(slot) => {
+                    if slot >= fields.len() {
+                        bail!(ErrorKind::Invalid(format!("No slot {} to drop", slot)));
+                    }
+                    fields.remove(slot);
+                    stages.push(Box::new(move |mut args| {
+                        args.remove(slot);
+                        Ok(args)
+                    }));
+                }
+                Migration::RenameField(slot, ref name) => {
+                    if slot >= fields.len() {
+                        bail!(ErrorKind::Invalid(format!("No slot {} to rename", slot)));
+                    }
+                    fields[slot].name = name.clone();
+                }
+                Migration::Rewrite(ref func_name) => {
+                    let func = self.funcs.get(func_name).cloned().ok_or_else(|| {
+                        ErrorKind::Invalid(format!("{} is not a registered function", func_name))
+                    })?;
+                    let packed = if fields.len() == 1 {
+                        fields[0].type_.clone()
+                    } else {
+                        ::pg::dyn::types::Tuple::new(
+                            fields.iter().map(|field| field.type_.clone()).collect(),
+                        ) as Type
+                    };
+                    if *func.input_type != *packed || *func.output_type != *packed {
+                        bail!(ErrorKind::Type(format!(
+                            "{} must map {:?} to itself, but has type {:?} -> {:?}",
+                            func_name,
+                            packed,
+                            func.input_type,
+                            func.output_type
+                        )));
+                    }
+                    let width = fields.len();
+                    let func_name = func_name.clone();
+                    stages.push(Box::new(move |args| {
+                        let out = if width == 1 {
+                            (func.run)(args[0].clone())
+                        } else {
+                            (func.run)(values::Tuple::new(args) as Value)
+                        };
+                        if *out.type_() != *packed {
+                            bail!(ErrorKind::Type(format!(
+                                "{} returned {:?}, which is not a {:?}",
+                                func_name,
+                                out,
+                                packed
+                            )));
+                        }
+                        if width == 1 {
+                            return Ok(vec![out]);
+                        }
+                        match out.get().downcast_ref::<Vec<Value>>() {
+                            Some(elems) => Ok(elems.clone()),
+                            None => bail!(ErrorKind::Type(
+                                format!("{} did not return a tuple", func_name),
+                            )),
+                        }
+                    }));
+                }
+            }
+        }
+        if fields.is_empty() {
+            bail!(ErrorKind::Invalid(
+                "Predicates must have at least one argument.".to_string(),
+            ));
+        }
+
+        let pred = Predicate {
+            name: old.name.clone(),
+            description: old.description.clone(),
+            fields,
+        };
+        // The first fact which can't be migrated stops the migration, and
+        // explains why
+        let failure = RefCell::new(None);
+        let migrated = self.fact_db.migrate_predicate(&pred, &|args| {
+            let res = stages.iter().try_fold(args.to_vec(), |args, stage| stage(args));
+            res.map_err(|err| *failure.borrow_mut() = Some(err)).ok()
+        });
+        if let Some(err) = failure.into_inner() {
+            return Err(err);
+        }
+        migrated?;
+        // Migration rebuilds the predicate's facts without their indexes
+        self.indexes.retain(|(indexed, _), _| indexed != pred_name);
+        Ok(())
+    }
+
+    fn get_dep_rules(&mut self, pred: &String) -> WakeList {
         self.rules
             .entry(pred.to_string())
             .or_insert(Rc::new(RefCell::new(Vec::new())))
             .clone()
     }
 
-    /// Adds a new fact to the database
-    /// If the fact is already present, a new copy will not be added.
-    ///
-    /// * The relevant predicate must already be registered
-    /// * The fact must be correctly typed
-    pub fn new_fact(&mut self, fact: &Fact) -> Result<()> {
+    // Works out the predicate dependencies with a rule added, refusing it if
+    // that would leave some predicate depending on its own negation. Nothing
+    // is recorded until the caller stores the result.
+    fn pred_deps_with(&self, rule: &Rule) -> Result<HashMap<String, Vec<(String, bool)>>> {
+        let mut deps = self.pred_deps.borrow().clone();
+        let bodies = Some(&rule.body).into_iter().chain(rule.alternatives.iter());
+        let clauses: Vec<&Clause> = bodies.flat_map(|body| body.iter()).collect();
+        for head in rule.head.iter() {
+            let head_deps = deps.entry(head.pred_name.clone()).or_default();
+            for clause in clauses.iter() {
+                head_deps.push((clause.pred_name.clone(), clause.negated));
+            }
+        }
+        for (head, body) in deps.iter() {
+            for &(ref dep, negated) in body.iter() {
+                if negated && depends_on(&deps, dep, head) {
+                    bail!(ErrorKind::Invalid(format!(
+                        "Rule {} is not stratifiable: {} depends on the negation of {}, \
+                         which depends on {}",
+                        rule.name,
+                        head,
+                        dep,
+                        head
+                    )));
+                }
+            }
+        }
+        Ok(deps)
+    }
+
+    // Checks a fact against the signature of its predicate
+    fn check_fact(&self, fact: &Fact) -> Result<()> {
         match self.fact_db.get_predicate(&fact.pred_name) {
             Some(ref pred) => {
                 if (fact.args.len() != pred.fields.len()) ||
@@ -379,155 +1012,782 @@ impl Engine {
             }
             None => bail!(ErrorKind::Invalid("Predicate not registered".to_string())),
         }
-        {
-            if self.fact_db.insert_fact(&fact)?.is_some() {
-                let deps = self.get_dep_rules(&fact.pred_name);
-                for signal in deps.borrow().iter() {
-                    signal.signal();
-                }
-            }
-            Ok(())
-        }
+        Ok(())
     }
 
-    /// Returns success in the appropriate type. This helper function is to
-    /// support the EDSL, and it is not anticipated to be useful normally.
-    pub fn nop(&self) -> Result<()> {
-        Ok(())
+    // Lets the rules depending on a predicate know it has new facts
+    fn signal_deps(&mut self, pred_name: &String) {
+        for signal in Signal::live(&self.get_dep_rules(pred_name)) {
+            signal.signal();
+        }
     }
 
-    /// Given a query (similar to the rhs of a rule in Datalog), provide the set
-    /// of satisfying answers in the database.
-    pub fn derive(&self, query: &Vec<Clause>) -> Result<Vec<Vec<Value>>> {
-        let outs = self.fact_db.search_facts(query, None)?;
-        let res = outs.into_iter().map(|x| x.1).collect();
-        Ok(res)
+    /// Adds a new fact to the database
+    /// If the fact is already present, a new copy will not be added.
+    ///
+    /// * The relevant predicate must already be registered
+    /// * The fact must be correctly typed
+    pub fn new_fact(&mut self, fact: &Fact) -> Result<()> {
+        self.check_fact(fact)?;
+        if store_fact(&*self.fact_db, &self.funcs, fact)?.is_some() {
+            self.signal_deps(&fact.pred_name);
+        }
+        Ok(())
     }
 
-    /// Render a predicate as an html table
-    pub fn render(&self, pred_name: &String) -> Result<String> {
-        let pred = self.get_predicate(pred_name)?.ok_or(ErrorKind::Invalid(
-            "Predicate absent".to_string(),
-        ))?;
-        let data = self.derive(&vec![
-            Clause {
-                pred_name: pred_name.to_string(),
-                args: pred.fields
-                    .iter()
-                    .enumerate()
-                    .map(|(i, _)| MatchExpr::Var(i))
-                    .collect(),
-            },
-        ])?;
-        let descr = match pred.description {
-            Some(descr) => format!("<h3>{}</h3><br />", descr),
-            None => "".to_string(),
-        };
-        let mut html = format!("<h1>{}:</h1><br />{}<table><tr>", pred_name, descr);
-        for field in pred.fields {
-            let name = match field.name {
-                Some(ref name) => name,
-                None => "N/A",
-            };
-            let descr = match field.description {
-                Some(ref descr) => format!(" title=\"{}\"", descr),
-                None => "".to_string(),
-            };
-            html.push_str(&format!("<th{}>{}</th>", descr, name));
+    /// Adds many facts to the database at once, returning for each whether
+    /// it was new. This is much faster than adding them one by one, as the
+    /// database may insert them in bulk, and the rules depending on each
+    /// predicate are only woken once.
+    ///
+    /// All of the facts are checked as per `new_fact` before any are added.
+    /// Facts for predicates with lattice fields are merged in a separate
+    /// batch from the rest, and so are not atomic with them.
+    pub fn new_facts(&mut self, facts: &[Fact]) -> Result<Vec<bool>> {
+        for fact in facts.iter() {
+            self.check_fact(fact)?;
         }
-        html.push_str("</tr>");
-        for row in data {
-            html.push_str("<tr>");
-            for col in row {
-                html.push_str(&format!("<td>{}</td>", col))
+        let (merged, plain): (Vec<usize>, Vec<usize>) = (0..facts.len()).partition(|n| {
+            self.fact_db
+                .get_predicate(&facts[*n].pred_name)
+                .map(|pred| pred.fields.iter().any(|field| field.join.is_some()))
+                .unwrap_or(false)
+        });
+        let mut new = vec![false; facts.len()];
+        if !plain.is_empty() {
+            let batch: Vec<Fact> = plain.iter().map(|n| facts[*n].clone()).collect();
+            let ids = self.fact_db.insert_facts(&batch)?;
+            for (n, id) in plain.iter().zip(ids) {
+                new[*n] = id.is_some();
             }
         }
-        html.push_str("</table>");
-        Ok(html)
+        if !merged.is_empty() {
+            let batch: Vec<Fact> = merged.iter().map(|n| facts[*n].clone()).collect();
+            let ids = store_facts(&*self.fact_db, &self.funcs, &batch)?;
+            for (n, id) in merged.iter().zip(ids) {
+                new[*n] = id.is_some();
+            }
+        }
+        let mut changed: Vec<&String> = facts
+            .iter()
+            .zip(new.iter())
+            .filter(|&(_, is_new)| *is_new)
+            .map(|(fact, _)| &fact.pred_name)
+            .collect();
+        changed.sort();
+        changed.dedup();
+        for pred_name in changed {
+            self.signal_deps(pred_name);
+        }
+        Ok(new)
     }
 
-    /// Register a new rule with the database
-    pub fn new_rule(&mut self, rule: &Rule) -> Result<()> {
-        trace!("Registering rule: {:?}", rule);
-        let signal = Signal::new();
-        let trigger = signal.clone();
-        let profile = Rc::new(RefCell::new(RuleProfile::new(rule.name.clone())));
-        self.rule_profiles.push(profile.clone());
-        self.signals.push(signal.clone());
-
-        for pred in &rule.body {
-            let dep_rules = self.get_dep_rules(&pred.pred_name);
-            dep_rules.borrow_mut().push(signal.clone());
+    /// Removes a fact from the database, along with the facts derived from
+    /// it, returning false if the fact was not present.
+    ///
+    /// Maintenance is delete-and-rederive: everything with a derivation using
+    /// a removed fact is removed, and then anything the rules can still
+    /// derive some other way is put back. This includes the retracted fact
+    /// itself, if it is derivable.
+    pub fn retract_fact(&mut self, fact: &Fact) -> Result<bool> {
+        if self.fact_db.get_predicate(&fact.pred_name).is_none() {
+            bail!(ErrorKind::Invalid("Predicate not registered".to_string()));
+        }
+        let pattern: Vec<MatchExpr> = fact.args
+            .iter()
+            .map(|arg| MatchExpr::Const(arg.clone()))
+            .collect();
+        let mut doomed: HashMap<FactId, Fact> = HashMap::new();
+        let mut frontier: HashSet<FactId> = HashSet::new();
+        for (id, found) in self.matching_facts(&fact.pred_name, &pattern)? {
+            doomed.insert(id, found);
+            frontier.insert(id);
+        }
+        if doomed.is_empty() {
+            return Ok(false);
         }
 
+        // Overdelete: find the answers of each rule in which some clause
+        // matched a newly doomed fact, to find everything that used it
+        while !frontier.is_empty() {
+            let mut next = HashSet::new();
+            for entry in self.rule_entries.iter() {
+                let rule = &entry.rule;
+                let mut states = Vec::new();
+                let bodies = Some(&rule.body).into_iter().chain(rule.alternatives.iter());
+                for body in bodies {
+                    for (pos, clause) in body.iter().enumerate() {
+                        if clause.negated {
+                            continue;
+                        }
+                        for id in frontier.iter() {
+                            if doomed[id].pred_name != clause.pred_name {
+                                continue;
+                            }
+                            let (pinned, vars) = match pin_clause(body, pos, &doomed[id]) {
+                                Some(pinned) => pinned,
+                                None => continue,
+                            };
+                            for (ids, ans) in self.fact_db.search_facts(&pinned, None)? {
+                                let state = vars.iter()
+                                    .map(|var| match *var {
+                                        MatchExpr::Var(n) => ans[n].clone(),
+                                        MatchExpr::Const(ref val) => val.clone(),
+                                        MatchExpr::Unbound => unreachable!(),
+                                    })
+                                    .collect();
+                                states.push((ids, state));
+                            }
+                        }
+                    }
+                }
+                // An aggregate fact is built from the whole group, so all of
+                // the group's facts go
+                let aggregated = |slot| rule.aggregates.iter().any(|&(agg, _)| agg == slot);
+                for state in run_wheres(&rule.wheres, states, &self.funcs)? {
+                    for head in rule.head.iter() {
+                        let pattern: Vec<MatchExpr> = substitute(head, &state.1)?
+                            .args
+                            .into_iter()
+                            .enumerate()
+                            .map(|(slot, arg)| if aggregated(slot) {
+                                MatchExpr::Unbound
+                            } else {
+                                MatchExpr::Const(arg)
+                            })
+                            .collect();
+                        for (id, found) in self.matching_facts(&head.pred_name, &pattern)? {
+                            if let std::collections::hash_map::Entry::Vacant(e) = doomed.entry(id) {
+                                e.insert(found);
+                                next.insert(id);
+                            }
+                        }
+                    }
+                }
+            }
+            frontier = next;
+        }
+
+        for (id, doomed_fact) in doomed.iter() {
+            self.fact_db.delete_fact(&doomed_fact.pred_name, *id)?;
+        }
+        let preds: HashSet<String> = doomed
+            .values()
+            .map(|doomed_fact| doomed_fact.pred_name.clone())
+            .collect();
+
+        // Rederive: restoring one fact may make another derivable again, so
+        // keep going until nothing changes
+        let mut pending: Vec<Fact> = doomed.into_values().collect();
+        loop {
+            let before = pending.len();
+            let mut remaining = Vec::new();
+            for doomed_fact in pending {
+                if !self.rederive(&doomed_fact)? {
+                    remaining.push(doomed_fact);
+                }
+            }
+            pending = remaining;
+            if pending.len() == before {
+                break;
+            }
+        }
+
+        // Aggregates are recomputed from whatever groups remain
+        for entry in self.rule_entries.iter().filter(|entry| !entry.paused) {
+            let rule = &entry.rule;
+            if !rule.aggregates.is_empty() &&
+                rule.body.iter().any(|clause| preds.contains(&clause.pred_name))
+            {
+                activate(
+                    &*self.fact_db,
+                    &self.demand,
+                    &self.funcs,
+                    rule,
+                    &mut None,
+                    &entry.profile,
+                ).chain_err(|| format!("Activating {}", rule.name))?;
+            }
+        }
+
+        // Rules negating what was removed may now have answers they have
+        // already passed over, so they need to start again
+        let negates = |rule: &Rule| {
+            rule.body.iter().any(|clause| {
+                clause.negated && preds.contains(&clause.pred_name)
+            })
+        };
+        if self.rule_entries.iter().any(|entry| {
+            entry.rule.backward && negates(&entry.rule)
+        })
+        {
+            self.demand.cursors.borrow_mut().clear();
+        }
+        for entry in self.rule_entries.iter() {
+            if !entry.rule.backward && negates(&entry.rule) {
+                entry.cursor.set(None);
+                self.fact_db.set_cursor(&entry.rule.name, &rule_hash(&entry.rule), None)?;
+                if let Some(ref signal) = entry.signal {
+                    signal.signal();
+                }
+            }
+        }
+        for pred in preds.iter() {
+            for signal in Signal::live(&self.get_dep_rules(pred)) {
+                signal.signal();
+            }
+        }
+        Ok(true)
+    }
+
+    /// Explains how a fact came to be in the database, returning `None` if it
+    /// is not present.
+    ///
+    /// Each fact derived by a rule is explained by the first derivation found
+    /// for it. Aggregate facts are explained by their rule alone.
+    pub fn explain(&self, fact: &Fact) -> Result<Option<Derivation>> {
+        let pattern: Vec<MatchExpr> = fact.args
+            .iter()
+            .map(|arg| MatchExpr::Const(arg.clone()))
+            .collect();
+        match self.matching_facts(&fact.pred_name, &pattern)?.into_iter().next() {
+            Some((id, found)) => Ok(Some(self.derivation(id, found)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn derivation(&self, id: FactId, fact: Fact) -> Result<Derivation> {
+        let (rule, premises) = match self.fact_db.get_provenance(id)? {
+            Some(provenance) => provenance,
+            None => {
+                return Ok(Derivation {
+                    fact,
+                    rule: None,
+                    premises: Vec::new(),
+                })
+            }
+        };
+        let mut derivations = Vec::new();
+        for (pred_name, premise_id) in premises {
+            // Premises always predate what they derive, so this terminates.
+            // A premise which has since been replaced will be missing.
+            if let Some(premise) = self.fact_db.get_fact(&pred_name, premise_id)? {
+                derivations.push(self.derivation(premise_id, premise)?);
+            }
+        }
+        Ok(Derivation {
+            fact,
+            rule: Some(rule),
+            premises: derivations,
+        })
+    }
+
+    // Finds the stored facts fitting a pattern of constants and unbound slots
+    fn matching_facts(
+        &self,
+        pred_name: &str,
+        pattern: &[MatchExpr],
+    ) -> Result<Vec<(FactId, Fact)>> {
+        let mut next_var = 0;
+        let args = pattern
+            .iter()
+            .map(|arg| match *arg {
+                MatchExpr::Const(ref val) => MatchExpr::Const(val.clone()),
+                _ => {
+                    next_var += 1;
+                    MatchExpr::Var(next_var - 1)
+                }
+            })
+            .collect();
+        let clause = Clause {
+            pred_name: pred_name.to_string(),
+            args,
+            negated: false,
+        };
+        let states = self.fact_db.search_facts(::std::slice::from_ref(&clause), None)?;
+        states
+            .into_iter()
+            .map(|(ids, vals)| Ok((ids[0], substitute(&clause, &vals)?)))
+            .collect()
+    }
+
+    // Checks whether any rule can still produce a fact, and stores it if so
+    fn rederive(&self, fact: &Fact) -> Result<bool> {
+        let pattern: Vec<MatchExpr> = fact.args
+            .iter()
+            .map(|arg| MatchExpr::Const(arg.clone()))
+            .collect();
+        let heads = self.rule_entries.iter().flat_map(|entry| {
+            entry.rule.head.iter().enumerate().map(move |(n, head)| (entry, n, head))
+        });
+        for (entry, n, head) in heads {
+            if head.pred_name != fact.pred_name || !entry.rule.aggregates.is_empty() {
+                continue;
+            }
+            let rule = match specialize(&entry.rule, n, &pattern) {
+                Some(rule) => rule,
+                None => continue,
+            };
+            for clause in rule.body.iter() {
+                demand(&*self.fact_db, &self.demand, clause)?;
+            }
+            let states = self.fact_db.search_facts(&rule.body, None)?;
+            let mut derived = None;
+            for state in run_wheres(&rule.wheres, states, &self.funcs)? {
+                if substitute(&rule.head[n], &state.1)? == *fact {
+                    derived = Some(state);
+                    break;
+                }
+            }
+            if let Some(state) = derived {
+                if let Some(id) = store_fact(&*self.fact_db, &self.funcs, fact)? {
+                    self.fact_db.record_provenance(
+                        id,
+                        &rule.name,
+                        &premises(&rule, &state.0),
+                    )?;
+                }
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns success in the appropriate type. This helper function is to
+    /// support the EDSL, and it is not anticipated to be useful normally.
+    pub fn nop(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Given a query (similar to the rhs of a rule in Datalog), provide the set
+    /// of satisfying answers in the database.
+    ///
+    /// Any backward rules able to answer the query are run first.
+    pub fn derive(&self, query: &[Clause]) -> Result<Vec<Vec<Value>>> {
+        for clause in query.iter() {
+            demand(&*self.fact_db, &self.demand, clause)?;
+        }
+        let outs = self.fact_db.search_facts(query, None)?;
+        let res = outs.into_iter().map(|x| x.1).collect();
+        Ok(res)
+    }
+
+    /// Subscribe to the answers of a query, as with `derive()`, but delivered
+    /// as a stream which keeps producing new answers as rules or callers add
+    /// the facts they depend on.
+    ///
+    /// The stream is driven by the same event loop as the rules, so it only
+    /// makes progress while that loop is running.
+    pub fn subscribe(&mut self, query: Vec<Clause>) -> Subscription<DB> {
+        let signal = Signal::new();
+        // As with rules, new facts for a negated predicate can only remove
+        // answers.
+        for clause in query.iter().filter(|clause| !clause.negated) {
+            self.wake_on(&clause.pred_name, &signal);
+        }
+        // Deliver the answers we already have
+        signal.signal();
+        Subscription {
+            fact_db: self.fact_db.clone(),
+            demand: self.demand.clone(),
+            query,
+            signal,
+            cursor: None,
+            pending: VecDeque::new(),
+        }
+    }
+
+    /// Render a predicate as an html table
+    pub fn render(&self, pred_name: &String) -> Result<String> {
+        let pred = self.get_predicate(pred_name)?.ok_or(ErrorKind::Invalid(
+            "Predicate absent".to_string(),
+        ))?;
+        let data = self.derive(&[
+            Clause {
+                pred_name: pred_name.to_string(),
+                args: pred.fields
+                    .iter()
+                    .enumerate()
+                    .map(|(i, _)| MatchExpr::Var(i))
+                    .collect(),
+                negated: false,
+            },
+        ])?;
+        let descr = match pred.description {
+            Some(descr) => format!("<h3>{}</h3><br />", descr),
+            None => "".to_string(),
+        };
+        let mut html = format!("<h1>{}:</h1><br />{}<table><tr>", pred_name, descr);
+        for field in pred.fields {
+            let name = match field.name {
+                Some(ref name) => name,
+                None => "N/A",
+            };
+            let descr = match field.description {
+                Some(ref descr) => format!(" title=\"{}\"", descr),
+                None => "".to_string(),
+            };
+            html.push_str(&format!("<th{}>{}</th>", descr, name));
+        }
+        html.push_str("</tr>");
+        for row in data {
+            html.push_str("<tr>");
+            for col in row {
+                html.push_str(&format!("<td>{}</td>", col))
+            }
+        }
+        html.push_str("</table>");
+        Ok(html)
+    }
+
+    /// Register a new rule with the database
+    ///
+    /// The rule is type checked against the registered predicates and
+    /// functions before it is installed, so functions it uses must be
+    /// registered first. Any mismatch fails with `ErrorKind::Type`.
+    ///
+    /// The returned handle can be used to pause, resume or remove the rule.
+    pub fn new_rule(&mut self, rule: &Rule) -> Result<RuleHandle> {
+        self.new_rule_with(rule, RuleConfig::default())
+    }
+
+    /// Register a new rule, as with `new_rule()`, along with its priority
+    /// and budget.
+    ///
+    /// Backward rules run on demand, so these settings only affect forward
+    /// rules.
+    pub fn new_rule_with(&mut self, rule: &Rule, config: RuleConfig) -> Result<RuleHandle> {
+        trace!("Registering rule: {:?}", rule);
+        if rule.head.is_empty() {
+            bail!(ErrorKind::Invalid(format!("Rule {} has no head", rule.name)));
+        }
+        if rule.head.iter().any(|head| head.negated) {
+            bail!(ErrorKind::Invalid(
+                format!("Rule {} has a negated head", rule.name),
+            ));
+        }
+        if !rule.aggregates.is_empty() && !rule.alternatives.is_empty() {
+            bail!(ErrorKind::Invalid(format!(
+                "Rule {} aggregates over more than one body",
+                rule.name
+            )));
+        }
+        if !rule.aggregates.is_empty() && rule.head.len() != 1 {
+            bail!(ErrorKind::Invalid(format!(
+                "Rule {} aggregates with more than one head",
+                rule.name
+            )));
+        }
+        for (n, &(slot, _)) in rule.aggregates.iter().enumerate() {
+            if slot >= rule.head[0].args.len() ||
+                rule.aggregates[..n].iter().any(|&(prev, _)| prev == slot)
+            {
+                bail!(ErrorKind::Invalid(format!(
+                    "Rule {} has a bad aggregate for slot {}",
+                    rule.name,
+                    slot
+                )));
+            }
+        }
+        // Each alternative body becomes a rule of its own, sharing the head
+        // and profile
+        let siblings = if rule.alternatives.is_empty() {
+            vec![rule.clone()]
+        } else {
+            Some(&rule.body)
+                .into_iter()
+                .chain(rule.alternatives.iter())
+                .map(|body| {
+                    renumber_rule(&Rule {
+                        body: body.clone(),
+                        alternatives: Vec::new(),
+                        ..rule.clone()
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        for sibling in siblings.iter() {
+            check::check_rule(&*self.fact_db, &self.funcs, sibling)?;
+        }
+        let deps = self.pred_deps_with(rule)?;
+        // Carry on from where an identical rule got to before a restart
+        let mut cursors = Vec::new();
+        for sibling in siblings.iter() {
+            cursors.push(if sibling.backward {
+                None
+            } else {
+                self.fact_db.get_cursor(&sibling.name, &rule_hash(sibling))?
+            });
+        }
+        // The rule is accepted, so index the slots its searches look facts
+        // up by
+        let mut patterns = Vec::new();
+        for sibling in siblings.iter() {
+            for pattern in access_patterns(&*self.fact_db, sibling) {
+                if !patterns.contains(&pattern) {
+                    patterns.push(pattern);
+                }
+            }
+        }
+        for (pred_name, slots) in patterns.iter() {
+            if !self.indexes.contains_key(&(pred_name.clone(), slots.clone())) {
+                self.fact_db.create_index(pred_name, slots)?;
+            }
+        }
+        *self.pred_deps.borrow_mut() = deps;
+        for pattern in patterns {
+            self.indexes.entry(pattern).or_default().push(
+                rule.name.clone(),
+            );
+        }
+        let profile = Rc::new(RefCell::new(RuleProfile {
+            config,
+            ..RuleProfile::new(rule.name.clone())
+        }));
+        self.rule_profiles.push(profile.clone());
+        let handle = RuleHandle { id: self.next_rule_id };
+        self.next_rule_id += 1;
+        for (sibling, cursor) in siblings.into_iter().zip(cursors) {
+            self.rule_entries.push(RuleEntry {
+                rule: sibling,
+                handle,
+                signal: None,
+                links: Vec::new(),
+                cursor: Rc::new(Cell::new(cursor)),
+                profile: profile.clone(),
+                paused: false,
+            });
+            let n = self.rule_entries.len() - 1;
+            self.start_rule(n);
+        }
+        Ok(handle)
+    }
+
+    /// Stop running a rule until it is resumed. Facts it has already derived
+    /// are kept.
+    pub fn pause_rule(&mut self, handle: RuleHandle) -> Result<()> {
+        for n in self.rule_indices(handle)? {
+            if !self.rule_entries[n].paused {
+                self.stop_rule(n);
+                self.rule_entries[n].paused = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Start running a paused rule again. It catches up on any facts which
+    /// arrived while it was paused.
+    pub fn resume_rule(&mut self, handle: RuleHandle) -> Result<()> {
+        for n in self.rule_indices(handle)? {
+            if self.rule_entries[n].paused {
+                self.rule_entries[n].paused = false;
+                self.start_rule(n);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stop a rule and forget it, so that a replacement may be registered.
+    /// Facts it has already derived are kept.
+    pub fn remove_rule(&mut self, handle: RuleHandle) -> Result<()> {
+        let indices = self.rule_indices(handle)?;
+        for n in indices.iter() {
+            if !self.rule_entries[*n].paused {
+                self.stop_rule(*n);
+            }
+        }
+        let profile = self.rule_entries[indices[0]].profile.clone();
+        self.rule_profiles.retain(|other| !Rc::ptr_eq(other, &profile));
+        // Indexes are left in place, but no longer used by the rule
+        let name = profile.borrow().name.clone();
+        for rules in self.indexes.values_mut() {
+            if let Some(pos) = rules.iter().position(|rule| *rule == name) {
+                rules.remove(pos);
+            }
+        }
+        self.rule_entries.retain(|entry| entry.handle != handle);
+        // The remaining rules can only be more stratifiable than before
+        let rules: Vec<Rule> = self.rule_entries.iter().map(|entry| entry.rule.clone()).collect();
+        self.pred_deps.borrow_mut().clear();
+        for rule in rules.iter() {
+            let deps = self.pred_deps_with(rule)?;
+            *self.pred_deps.borrow_mut() = deps;
+        }
+        Ok(())
+    }
+
+    fn rule_indices(&self, handle: RuleHandle) -> Result<Vec<usize>> {
+        let indices: Vec<usize> = (0..self.rule_entries.len())
+            .filter(|n| self.rule_entries[*n].handle == handle)
+            .collect();
+        if indices.is_empty() {
+            bail!(ErrorKind::Invalid(format!("{:?} is not a registered rule", handle)));
+        }
+        Ok(indices)
+    }
+
+    // Unhooks a rule from the rest of the engine, leaving its cursor where
+    // it was so that it can be started again later
+    fn stop_rule(&mut self, n: usize) {
+        let (rule, profile) = {
+            let entry = &self.rule_entries[n];
+            (entry.rule.clone(), entry.profile.clone())
+        };
+        if rule.backward {
+            let mut removed = HashSet::new();
+            for backwards in self.demand.rules.borrow_mut().values_mut() {
+                backwards.retain(|backward| {
+                    let ours = Rc::ptr_eq(&backward.profile, &profile) && backward.rule == rule;
+                    if ours {
+                        removed.insert(backward.id);
+                    }
+                    !ours
+                });
+            }
+            self.demand.cursors.borrow_mut().retain(
+                |&(id, _), _| !removed.contains(&id),
+            );
+            // A reader may be on a list more than once, so only take off the
+            // entries made for this rule
+            for (list, link) in self.rule_entries[n].links.drain(..) {
+                let mut list = list.borrow_mut();
+                if let Some(pos) = list.iter().position(|other| other.ptr_eq(&link)) {
+                    list.remove(pos);
+                }
+            }
+            return;
+        }
+        let signal = self.rule_entries[n].signal.take();
+        if let Some(signal) = signal {
+            // Only there to end the rule's work loop, nothing to wait on
+            let _ = signal.stop();
+            for signals in self.rules.values() {
+                signals.borrow_mut().retain(|other| {
+                    other.upgrade().is_some_and(|other| !Signal(other).same(&signal))
+                });
+            }
+            for signals in self.producers.borrow_mut().values_mut() {
+                signals.retain(|other| !other.same(&signal));
+            }
+            self.signals.retain(|other| !other.same(&signal));
+        }
+    }
+
+    // Sets an installed rule up to run, whether on demand or whenever its
+    // body changes, picking up from its cursor
+    fn start_rule(&mut self, n: usize) {
+        let (rule, cursor, profile) = {
+            let entry = &self.rule_entries[n];
+            (entry.rule.clone(), entry.cursor.clone(), entry.profile.clone())
+        };
+        if rule.backward {
+            let links = self.new_backward_rule(&rule, profile);
+            self.rule_entries[n].links = links;
+            return;
+        }
+        let priority = profile.borrow().config.priority;
+        let signal = Signal::scheduled(self.scheduler.clone(), priority);
+        let trigger = signal.clone();
+        self.signals.push(signal.clone());
+        self.rule_entries[n].signal = Some(signal.clone());
+
+        // New facts for a negated predicate can only remove answers, so we
+        // don't need to wake up for them.
+        for pred in rule.body.iter().filter(|clause| !clause.negated) {
+            self.wake_on(&pred.pred_name, &signal);
+        }
+        for head in rule.head.iter() {
+            self.producers
+                .borrow_mut()
+                .entry(head.pred_name.clone())
+                .or_default()
+                .push(signal.clone());
+        }
+        let negated: Vec<String> = Some(&rule.body)
+            .into_iter()
+            .chain(rule.alternatives.iter())
+            .flat_map(|body| body.iter())
+            .filter(|clause| clause.negated)
+            .map(|clause| clause.pred_name.clone())
+            .collect();
+        let producers = self.producers.clone();
+        let pred_deps = self.pred_deps.clone();
+
         let rule_future = {
-            let mut next_fact_id = None;
             let fdb = self.fact_db.clone();
             let funcs = self.funcs.clone();
-            let buddies = self.get_dep_rules(&rule.head.pred_name);
+            let demand = self.demand.clone();
+            let buddies: Vec<_> = rule.head
+                .iter()
+                .map(|head| self.get_dep_rules(&head.pred_name))
+                .collect();
+            let hash = rule_hash(&rule);
             let rule = rule.clone();
             let out_signal = signal.clone();
-            let start_time = self.start_time.clone();
-            let limiter = self.limiter.clone();
+            let start_time = self.start_time;
+            let limiter = self.limiter;
+            let errors = self.rule_errors.clone();
             signal.for_each(move |_| {
                 let rule_start = Instant::now();
                 match (start_time.elapsed(), limiter) {
                     (run_time, Some(limit_time)) if run_time > limit_time => return out_signal.stop(),
                     _ => ()
                 }
+                // Absence of a fact only means something once everything
+                // that could produce it has finished, so wait on any busy
+                // producers of predicates we negate, or of anything those
+                // depend on.
+                let busy = {
+                    let deps = pred_deps.borrow();
+                    let producers = producers.borrow();
+                    negated
+                        .iter()
+                        .flat_map(|pred| dependencies(&deps, pred))
+                        .filter_map(|pred| producers.get(&pred))
+                        .filter_map(|signals| {
+                            signals.iter().find(|producer| !producer.dormant()).cloned()
+                        })
+                        .next()
+                };
+                if let Some(producer) = busy {
+                    trace!("Deferring rule {:?} for negated predicates", rule.name);
+                    producer.signal_when_dormant(out_signal.clone());
+                    return out_signal.done();
+                }
+                if profile.borrow().over_budget() {
+                    info!("Stopping rule {}, it is out of budget", rule.name);
+                    profile.borrow_mut().exhausted = true;
+                    return out_signal.stop();
+                }
+                profile.borrow_mut().activations += 1;
                 trace!("Activating rule: {:?}", rule.name);
-                let mut productive: usize = 0;
-                    let pre_db = Instant::now();
-                    let states_0 = fdb.search_facts(&rule.body, next_fact_id).chain_err(|| format!("Search from {}", rule.name)).unwrap();
-                    let sql_time = pre_db.elapsed();
-                    profile.borrow_mut().add_select_time(sql_time);
-                    next_fact_id = states_0.iter().flat_map(|x| x.0.iter()).max().map(|x| x + 1).or(next_fact_id);
-                let results = states_0.len();
-                    trace!("Query submitted");
-                    let mut states: Box<
-                        Iterator<
-                            Item = (Vec<FactId>,
-                                    Vec<Value>),
-                        >,
-                    > = Box::new(states_0.into_iter());
-                    let where_start = Instant::now();
-                    for where_clause in rule.wheres.iter() {
-                        let wc = where_clause.clone();
-                        let bf = &funcs;
-                        let next_states = states.flat_map(move |state| {
-                            let resp = eval(&wc.rhs, &state.1, bf);
-                            let out: Vec<_> = bind(&wc.lhs, resp, &state.1)
-                                .into_iter()
-                                .map(|x| (state.0.clone(), x))
-                                .collect();
-                            out
-                        });
-                        states = Box::new(next_states);
-                    }
-                    let facts: Vec<Fact> = states.map(|state| substitute(&rule.head, &state.1)).collect();
-                    let compute_time = where_start.elapsed();
-                    profile.borrow_mut().add_compute_time(compute_time);
-                    trace!("Insertions beginning");
-                    let insert_start = Instant::now();
-                    for fact in facts {
-                        if fdb.insert_fact(&fact)
-                            .unwrap()
-                            .is_some()
-                        {
-                            productive += 1;
+                let mut next_fact_id = cursor.get();
+                let res = activate(&*fdb, &demand, &funcs, &rule, &mut next_fact_id, &profile)
+                    .and_then(|productive| {
+                        if next_fact_id != cursor.get() {
+                            fdb.set_cursor(&rule.name, &hash, next_fact_id)?;
                         }
+                        Ok(productive)
+                    });
+                let productive = match res {
+                    Ok(productive) => productive,
+                    Err(err) => {
+                        let message = err.iter()
+                            .map(|cause| cause.to_string())
+                            .collect::<Vec<_>>()
+                            .join(": ");
+                        error!("Disabling rule {}: {}", rule.name, message);
+                        errors.borrow_mut().push(RuleError {
+                            rule: rule.name.clone(),
+                            message,
+                        });
+                        return out_signal.stop();
                     }
-                    let insert_time = insert_start.elapsed();
-                    profile.borrow_mut().add_insert_time(insert_time);
-                    trace!("Insertions done");
-                trace!(
-                    "Generated {} results, turned into {} facts.",
-                    results,
-                    productive
-                );
+                };
+                cursor.set(next_fact_id);
 
                 if productive > 0 {
-                    for buddy in buddies.borrow().iter() {
+                    for buddy in buddies.iter().flat_map(Signal::live) {
                         buddy.signal();
                     }
                 }
@@ -540,7 +1800,72 @@ impl Engine {
 
         self.event_loop.spawn(rule_future);
         trigger.signal();
-        Ok(())
+    }
+
+    // Backward rules don't get a future of their own. Instead, rules reading
+    // their head are woken by whatever the backward rule reads. Returns the
+    // wake list entries made for them, to take down when the rule stops.
+    fn new_backward_rule(
+        &mut self,
+        rule: &Rule,
+        profile: Rc<RefCell<RuleProfile>>,
+    ) -> Vec<(WakeList, Weak<SignalState>)> {
+        let mut links = Vec::new();
+        for (n, head) in rule.head.iter().enumerate() {
+            let id = self.demand.next_id.get();
+            self.demand.next_id.set(id + 1);
+            self.demand
+                .rules
+                .borrow_mut()
+                .entry(head.pred_name.clone())
+                .or_default()
+                .push(Rc::new(BackwardRule {
+                    id,
+                    rule: rule.clone(),
+                    head: n,
+                    funcs: self.funcs.clone(),
+                    profile: profile.clone(),
+                }));
+            let readers = Signal::live(&self.get_dep_rules(&head.pred_name));
+            for clause in rule.body.iter().filter(|clause| !clause.negated) {
+                for reader in readers.iter() {
+                    for list in self.wake_on(&clause.pred_name, reader) {
+                        links.push((list, reader.downgrade()));
+                    }
+                }
+            }
+        }
+        links
+    }
+
+    // Arranges for `signal` to fire on new facts for `pred`, or for anything
+    // read by the backward rules producing `pred`. Returns the wake lists it
+    // was added to.
+    fn wake_on(&mut self, pred: &str, signal: &Signal) -> Vec<WakeList> {
+        let mut todo = vec![pred.to_string()];
+        let mut seen = HashSet::new();
+        let mut lists = Vec::new();
+        while let Some(pred) = todo.pop() {
+            if !seen.insert(pred.clone()) {
+                continue;
+            }
+            let list = self.get_dep_rules(&pred);
+            list.borrow_mut().push(signal.downgrade());
+            lists.push(list);
+            if let Some(rules) = self.demand.rules.borrow().get(&pred) {
+                for backward in rules.iter() {
+                    todo.extend(
+                        backward
+                            .rule
+                            .body
+                            .iter()
+                            .filter(|clause| !clause.negated)
+                            .map(|clause| clause.pred_name.clone()),
+                    );
+                }
+            }
+        }
+        lists
     }
 
     /// Register a new function with the database, to be called from within a
@@ -557,88 +1882,1013 @@ impl Engine {
     /// the engine was created. The future will only gaurantee quiescence upon
     /// completion so long as no new rules have been added.
     pub fn quiesce(&self) -> Quiescence {
-        Quiescence::new(self.signals.clone())
+        Quiescence::new(self.signals.clone(), self.rule_errors.clone())
+    }
+
+    /// Registers the predicates, facts and rules of a program written in the
+    /// textual Holmes language (see the `lang` module), in the order they
+    /// appear. The whole program is parsed before anything is registered, so
+    /// a syntax error leaves the engine untouched.
+    ///
+    /// Queries are not run, since rules only make progress while the event
+    /// loop turns. They are returned instead, to be run with `derive` once
+    /// the engine has quiesced.
+    pub fn load_program(&mut self, src: &str) -> Result<Vec<Query>> {
+        let stmts = lang::parse(src, &|name| self.get_type(name))?;
+        let mut queries = Vec::new();
+        // Runs of facts are stored together
+        let mut facts = Vec::new();
+        for stmt in stmts {
+            match stmt {
+                Statement::Fact(fact) => facts.push(fact),
+                stmt => {
+                    if !facts.is_empty() {
+                        self.new_facts(&facts)?;
+                        facts.clear();
+                    }
+                    match stmt {
+                        Statement::Predicate(pred) => self.new_predicate(&pred)?,
+                        Statement::Rule(rule) => {
+                            self.new_rule(&rule)?;
+                        }
+                        Statement::Query(query) => queries.push(query),
+                        Statement::Fact(_) => unreachable!(),
+                    }
+                }
+            }
+        }
+        if !facts.is_empty() {
+            self.new_facts(&facts)?;
+        }
+        Ok(queries)
+    }
+
+    /// Writes every predicate and fact to a file at `path`, along with the
+    /// contents of any `LargeBytes` they hold, so that `import` can load
+    /// them into another database. The dump is a self-describing JSON
+    /// document, and only holds values of the default types: exporting a
+    /// predicate over a custom type fails with `ErrorKind::Type` naming it.
+    pub fn export(&self, path: &str) -> Result<()> {
+        use std::io::Write;
+        let dump = dump::encode(&*self.fact_db)?;
+        let mut file = ::std::fs::File::create(path)?;
+        write!(file, "{}", dump)?;
+        Ok(())
+    }
+
+    /// Loads a dump written by `export`, returning the number of new facts.
+    ///
+    /// * Predicates are registered, and must agree with any already present
+    /// * Types are looked up among those registered, and facts are checked
+    ///   against them before anything is stored
+    /// * Facts which are already present are skipped
+    pub fn import(&mut self, path: &str) -> Result<usize> {
+        use std::io::Read;
+        use rustc_serialize::json::Json;
+        let mut text = String::new();
+        ::std::fs::File::open(path)?.read_to_string(&mut text)?;
+        let dump = Json::from_str(&text).map_err(|err| {
+            ErrorKind::Invalid(format!("Malformed dump: {}", err))
+        })?;
+        let (preds, facts) = dump::decode(&*self.fact_db, &dump)?;
+        for pred in preds.iter() {
+            self.new_predicate(pred)?;
+        }
+        let new = self.new_facts(&facts)?;
+        Ok(new.into_iter().filter(|new| *new).count())
+    }
+
+    /// Lists the rules which have failed while running, and so have been
+    /// disabled, along with what went wrong.
+    pub fn rule_errors(&self) -> Vec<RuleError> {
+        self.rule_errors.borrow().clone()
     }
 }
 
+impl Engine<PgDB> {
+    /// Runs a file of SQL statements directly against the database
+    pub fn run_sql(&self, path: &str) {
+        use std::io::Read;
+        let mut fd = ::std::fs::File::open(path).unwrap();
+        let mut sql = String::new();
+        fd.read_to_string(&mut sql).unwrap();
+        let conn = self.fact_db.conn().unwrap();
+        conn.batch_execute(&sql).unwrap();
+    }
+}
+
+// Runs the where clauses in sequence over a set of answers, producing the
+// extended (or filtered) answers
+fn run_wheres(
+    wheres: &[WhereClause],
+    states: Vec<(Vec<FactId>, Vec<Value>)>,
+    funcs: &HashMap<String, Rc<Func>>,
+) -> Result<Vec<(Vec<FactId>, Vec<Value>)>> {
+    let mut states = states;
+    for wc in wheres.iter() {
+        let mut next_states = Vec::new();
+        for state in states {
+            let resp = eval(&wc.rhs, &state.1, funcs)?;
+            for next in bind(&wc.lhs, resp, &state.1)? {
+                next_states.push((state.0.clone(), next));
+            }
+        }
+        states = next_states;
+    }
+    Ok(states)
+}
+
+// Runs the backward rules which could produce facts matching `goal`, until
+// they stop producing anything new. Returns the number of new facts.
+fn demand(fdb: &dyn FactDB, demand: &Demand, goal: &Clause) -> Result<usize> {
+    let rules = match demand.rules.borrow().get(&goal.pred_name) {
+        Some(rules) => rules.clone(),
+        None => return Ok(0),
+    };
+    let pattern: Vec<MatchExpr> = goal.args
+        .iter()
+        .map(|arg| match *arg {
+            MatchExpr::Const(ref val) => MatchExpr::Const(val.clone()),
+            _ => MatchExpr::Unbound,
+        })
+        .collect();
+    let key = (goal.pred_name.clone(), pattern);
+    // A recursive goal will be finished off by the loop further up the stack
+    if !demand.active.borrow_mut().insert(key.clone()) {
+        return Ok(0);
+    }
+    let res = answer_goal(fdb, demand, &rules, &key.1);
+    demand.active.borrow_mut().remove(&key);
+    res
+}
+
+fn answer_goal(
+    fdb: &dyn FactDB,
+    demand: &Demand,
+    rules: &[Rc<BackwardRule>],
+    pattern: &[MatchExpr],
+) -> Result<usize> {
+    let mut total = 0;
+    loop {
+        let mut productive = 0;
+        for backward in rules.iter() {
+            let rule = match specialize(&backward.rule, backward.head, pattern) {
+                Some(rule) => rule,
+                None => continue,
+            };
+            let cursor_key = (backward.id, pattern.to_vec());
+            let mut cursor = demand
+                .cursors
+                .borrow()
+                .get(&cursor_key)
+                .cloned()
+                .unwrap_or(None);
+            trace!("Demanding rule: {:?}", rule.name);
+            let res = activate(
+                fdb,
+                demand,
+                &backward.funcs,
+                &rule,
+                &mut cursor,
+                &backward.profile,
+            ).chain_err(|| format!("Activating {}", rule.name));
+            demand.cursors.borrow_mut().insert(cursor_key, cursor);
+            productive += res?;
+        }
+        if productive == 0 {
+            return Ok(total);
+        }
+        total += productive;
+    }
+}
+
+// Narrows a rule to the constants asked for by a goal on one of its heads,
+// returning `None` if the rule can't produce any matching facts.
+// Variables fixed by the goal become constants, and the rest are renumbered
+// to stay sequential.
+fn specialize(rule: &Rule, which: usize, pattern: &[MatchExpr]) -> Option<Rule> {
+    // Fixing a slot would change the groups being aggregated over
+    if !rule.aggregates.is_empty() {
+        return Some(rule.clone());
+    }
+    let mut subst = HashMap::new();
+    for (head, want) in rule.head[which].args.iter().zip(pattern.iter()) {
+        if let MatchExpr::Const(ref val) = *want {
+            match *head {
+                MatchExpr::Var(var) => {
+                    if subst.get(&var).is_some_and(|prev| prev != val) {
+                        return None;
+                    }
+                    subst.insert(var, val.clone());
+                }
+                MatchExpr::Const(ref have) => {
+                    if have != val {
+                        return None;
+                    }
+                }
+                MatchExpr::Unbound => (),
+            }
+        }
+    }
+    if subst.is_empty() {
+        return Some(rule.clone());
+    }
+    // Variables keep their relative order, so dropping the fixed ones and
+    // shifting the rest down keeps the numbering sequential.
+    Some(rewrite_rule(rule, &|var| match subst.get(&var) {
+        Some(val) => Rewrite::Val(val.clone()),
+        None => Rewrite::Var(var - subst.keys().filter(|fixed| **fixed < var).count()),
+    }))
+}
+
+// Renumbers a rule's variables in order of first appearance, as the database
+// expects. Bodies split off from a disjunction need this, since the other
+// alternatives may have taken some of the numbers.
+fn renumber_rule(rule: &Rule) -> Result<Rule> {
+    let mut seen = Vec::new();
+    let positive = rule.body.iter().filter(|clause| !clause.negated);
+    let negative = rule.body.iter().filter(|clause| clause.negated);
+    for clause in positive.chain(negative) {
+        for arg in clause.args.iter() {
+            match_vars(arg, &mut seen);
+        }
+    }
+    for wc in rule.wheres.iter() {
+        expr_vars(&wc.rhs, &mut seen);
+        bind_vars(&wc.lhs, &mut seen);
+    }
+    let mut order = HashMap::new();
+    for var in seen {
+        let next = order.len();
+        order.entry(var).or_insert(next);
+    }
+    let mut head_vars = Vec::new();
+    for head in rule.head.iter() {
+        for arg in head.args.iter() {
+            match_vars(arg, &mut head_vars);
+        }
+    }
+    if head_vars.iter().any(|var| !order.contains_key(var)) {
+        bail!(ErrorKind::Invalid(format!(
+            "Rule {} has a head variable not bound by every alternative",
+            rule.name
+        )));
+    }
+    Ok(rewrite_rule(rule, &|var| Rewrite::Var(order[&var])))
+}
+
+fn match_vars(expr: &MatchExpr, out: &mut Vec<Var>) {
+    if let MatchExpr::Var(var) = *expr {
+        out.push(var);
+    }
+}
+
+fn bind_vars(expr: &BindExpr, out: &mut Vec<Var>) {
+    match *expr {
+        BindExpr::Normal(ref m) => match_vars(m, out),
+        BindExpr::Destructure(ref bs) => {
+            for b in bs.iter() {
+                bind_vars(b, out);
+            }
+        }
+        BindExpr::Iterate(ref b) => bind_vars(b, out),
+    }
+}
+
+fn expr_vars(expr: &Expr, out: &mut Vec<Var>) {
+    match *expr {
+        Expr::Var(var) => out.push(var),
+        Expr::Val(_) => (),
+        Expr::App(_, ref args) => {
+            for arg in args.iter() {
+                expr_vars(arg, out);
+            }
+        }
+    }
+}
+
+// What a variable turns into when rewriting a rule
+enum Rewrite {
+    Var(Var),
+    Val(Value),
+}
+
+fn rewrite_rule(rule: &Rule, f: &dyn Fn(Var) -> Rewrite) -> Rule {
+    let rewrite_clause = |clause: &Clause| {
+        Clause {
+            pred_name: clause.pred_name.clone(),
+            args: clause.args.iter().map(|arg| rewrite_match(arg, f)).collect(),
+            negated: clause.negated,
+        }
+    };
+    Rule {
+        name: rule.name.clone(),
+        head: rule.head.iter().map(&rewrite_clause).collect(),
+        body: rule.body.iter().map(&rewrite_clause).collect(),
+        alternatives: rule.alternatives
+            .iter()
+            .map(|body| body.iter().map(&rewrite_clause).collect())
+            .collect(),
+        wheres: rule.wheres
+            .iter()
+            .map(|wc| {
+                WhereClause {
+                    lhs: rewrite_bind(&wc.lhs, f),
+                    rhs: rewrite_expr(&wc.rhs, f),
+                }
+            })
+            .collect(),
+        aggregates: rule.aggregates.clone(),
+        backward: rule.backward,
+    }
+}
+
+fn rewrite_match(expr: &MatchExpr, f: &dyn Fn(Var) -> Rewrite) -> MatchExpr {
+    match *expr {
+        MatchExpr::Var(var) => {
+            match f(var) {
+                Rewrite::Var(var) => MatchExpr::Var(var),
+                Rewrite::Val(val) => MatchExpr::Const(val),
+            }
+        }
+        ref other => other.clone(),
+    }
+}
+
+fn rewrite_bind(expr: &BindExpr, f: &dyn Fn(Var) -> Rewrite) -> BindExpr {
+    match *expr {
+        BindExpr::Normal(ref m) => BindExpr::Normal(rewrite_match(m, f)),
+        BindExpr::Destructure(ref bs) => {
+            BindExpr::Destructure(bs.iter().map(|b| rewrite_bind(b, f)).collect())
+        }
+        BindExpr::Iterate(ref b) => BindExpr::Iterate(Box::new(rewrite_bind(b, f))),
+    }
+}
+
+fn rewrite_expr(expr: &Expr, f: &dyn Fn(Var) -> Rewrite) -> Expr {
+    match *expr {
+        Expr::Var(var) => {
+            match f(var) {
+                Rewrite::Var(var) => Expr::Var(var),
+                Rewrite::Val(val) => Expr::Val(val),
+            }
+        }
+        Expr::Val(ref val) => Expr::Val(val.clone()),
+        Expr::App(ref name, ref args) => {
+            Expr::App(
+                name.clone(),
+                args.iter().map(|arg| rewrite_expr(arg, f)).collect(),
+            )
+        }
+    }
+}
+
+// Stores a fact, merging it into an existing one if its predicate has lattice
+// fields. Returns `None` if the database did not change.
+fn store_fact(
+    fdb: &dyn FactDB,
+    funcs: &HashMap<String, Rc<Func>>,
+    fact: &Fact,
+) -> Result<Option<FactId>> {
+    Ok(store_facts(fdb, funcs, ::std::slice::from_ref(fact))?.pop().unwrap())
+}
+
+// Stores several facts at once, as per `store_fact`
+fn store_facts(
+    fdb: &dyn FactDB,
+    funcs: &HashMap<String, Rc<Func>>,
+    facts: &[Fact],
+) -> Result<Vec<Option<FactId>>> {
+    Ok(fdb.store_facts(
+        facts,
+        &|fact, slot, old, new| join_field(fdb, funcs, fact, slot, old, new),
+    )?)
+}
+
+// Stores the facts derived by one activation of a rule along with their
+// provenance, returning the ids of those which were new
+fn store_derived(
+    fdb: &dyn FactDB,
+    funcs: &HashMap<String, Rc<Func>>,
+    rule: &str,
+    derived: &[(Fact, Vec<(String, FactId)>)],
+) -> Result<Vec<Option<FactId>>> {
+    Ok(fdb.store_derived(
+        rule,
+        derived,
+        &|fact, slot, old, new| join_field(fdb, funcs, fact, slot, old, new),
+    )?)
+}
+
+// Combines the stored and new values of a lattice field of a fact, or gives
+// `None` if its join function is not registered
+fn join_field(
+    fdb: &dyn FactDB,
+    funcs: &HashMap<String, Rc<Func>>,
+    fact: &Fact,
+    slot: usize,
+    old: &Value,
+    new: &Value,
+) -> Option<Value> {
+    let join_name = fdb.get_predicate(&fact.pred_name)
+        .and_then(|pred| pred.fields[slot].join.clone())
+        .unwrap();
+    match funcs.get(&join_name) {
+        Some(func) => Some((func.run)(values::Tuple::new(vec![old.clone(), new.clone()]) as Value)),
+        None => {
+            error!("Join function {} not registered", join_name);
+            None
+        }
+    }
+}
+
+// The slots each body clause of a rule may look facts up by, i.e. those
+// holding constants or variables bound by other clauses. Lookups by a
+// leading run of slots are left to the unique index over all of them, and
+// large values are not indexed.
+fn access_patterns(fdb: &dyn FactDB, rule: &Rule) -> Vec<(String, Vec<usize>)> {
+    let mut patterns = Vec::new();
+    for (n, clause) in rule.body.iter().enumerate() {
+        let pred = match fdb.get_predicate(&clause.pred_name) {
+            Some(pred) => pred,
+            None => continue,
+        };
+        let shared = |var: Var| {
+            rule.body.iter().enumerate().any(|(m, other)| {
+                m != n && !other.negated && other.args.contains(&MatchExpr::Var(var))
+            })
+        };
+        let slots: Vec<usize> = (0..clause.args.len())
+            .filter(|slot| match clause.args[*slot] {
+                MatchExpr::Const(_) => true,
+                MatchExpr::Var(var) => shared(var),
+                MatchExpr::Unbound => false,
+            })
+            .filter(|slot| !pred.fields[*slot].type_.large())
+            .collect();
+        let plain = pred.fields.iter().all(|field| {
+            field.join.is_none() && !field.type_.large()
+        });
+        let leading = slots.iter().enumerate().all(|(i, slot)| i == *slot);
+        if slots.is_empty() || (plain && leading) {
+            continue;
+        }
+        let pattern = (clause.pred_name.clone(), slots);
+        if !patterns.contains(&pattern) {
+            patterns.push(pattern);
+        }
+    }
+    patterns
+}
+
+// Identifies the definition of a rule, so that a stored cursor is only
+// picked up again by the rule which made it
+fn rule_hash(rule: &Rule) -> String {
+    use sha2::*;
+    use rustc_serialize::hex::ToHex;
+    let mut canon = Canonical(Vec::new());
+    canon.rule(rule);
+    let mut hasher = Sha256::default();
+    hasher.input(&canon.0);
+    hasher.result().to_hex()
+}
+
+// Encodes a rule's definition as bytes which only change when the definition
+// does. Debug output is no good for this, as it includes details like the
+// file handles behind large values.
+// Every variable length field is preceded by its length, so that no two
+// definitions share an encoding.
+struct Canonical(Vec<u8>);
+
+impl Canonical {
+    fn len(&mut self, len: usize) {
+        self.0.extend_from_slice(&(len as u64).to_be_bytes());
+    }
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.len(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+    fn str(&mut self, text: &str) {
+        self.bytes(text.as_bytes())
+    }
+    fn rule(&mut self, rule: &Rule) {
+        self.str(&rule.name);
+        self.clauses(&rule.head);
+        self.clauses(&rule.body);
+        self.len(rule.alternatives.len());
+        for body in &rule.alternatives {
+            self.clauses(body);
+        }
+        self.len(rule.wheres.len());
+        for where_ in &rule.wheres {
+            self.bind(&where_.lhs);
+            self.expr(&where_.rhs);
+        }
+        self.len(rule.aggregates.len());
+        for &(slot, agg) in &rule.aggregates {
+            self.len(slot);
+            self.str(&format!("{:?}", agg));
+        }
+        self.0.push(rule.backward as u8);
+    }
+    fn clauses(&mut self, clauses: &[Clause]) {
+        self.len(clauses.len());
+        for clause in clauses {
+            self.str(&clause.pred_name);
+            self.0.push(clause.negated as u8);
+            self.len(clause.args.len());
+            for arg in &clause.args {
+                self.match_expr(arg);
+            }
+        }
+    }
+    fn match_expr(&mut self, expr: &MatchExpr) {
+        match *expr {
+            MatchExpr::Unbound => self.0.push(b'u'),
+            MatchExpr::Var(var) => {
+                self.0.push(b'v');
+                self.len(var);
+            }
+            MatchExpr::Const(ref val) => {
+                self.0.push(b'c');
+                self.value(val);
+            }
+        }
+    }
+    fn bind(&mut self, bind: &BindExpr) {
+        match *bind {
+            BindExpr::Normal(ref expr) => {
+                self.0.push(b'n');
+                self.match_expr(expr);
+            }
+            BindExpr::Destructure(ref binds) => {
+                self.0.push(b'd');
+                self.len(binds.len());
+                for bind in binds {
+                    self.bind(bind);
+                }
+            }
+            BindExpr::Iterate(ref bind) => {
+                self.0.push(b'i');
+                self.bind(bind);
+            }
+        }
+    }
+    fn expr(&mut self, expr: &Expr) {
+        match *expr {
+            Expr::Var(var) => {
+                self.0.push(b'v');
+                self.len(var);
+            }
+            Expr::Val(ref val) => {
+                self.0.push(b'c');
+                self.value(val);
+            }
+            Expr::App(ref func, ref args) => {
+                self.0.push(b'a');
+                self.str(func);
+                self.len(args.len());
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+        }
+    }
+    fn type_(&mut self, type_: &Type) {
+        use pg::dyn::types;
+        if let Some(name) = type_.name() {
+            self.0.push(b'n');
+            self.str(name);
+        } else if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+            self.0.push(b'l');
+            self.type_(list.elem());
+        } else if let Some(tuple) = type_.inner().downcast_ref::<types::Tuple>() {
+            self.0.push(b't');
+            self.len(tuple.elements().len());
+            for elem in tuple.elements() {
+                self.type_(elem);
+            }
+        } else {
+            self.0.push(b'r');
+            self.str(type_.repr());
+        }
+    }
+    fn value(&mut self, val: &Value) {
+        self.type_(&val.type_());
+        let any = val.get();
+        if let Some(large) = val.inner().downcast_ref::<values::LargeBytes>() {
+            // Large values are stored under the hash of their contents
+            self.str(large.hash());
+        } else if let Some(elems) = any.downcast_ref::<Vec<Value>>() {
+            self.len(elems.len());
+            for elem in elems {
+                self.value(elem);
+            }
+        } else if let Some(num) = any.downcast_ref::<u64>() {
+            self.0.extend_from_slice(&num.to_be_bytes());
+        } else if let Some(flag) = any.downcast_ref::<bool>() {
+            self.0.push(*flag as u8);
+        } else if let Some(text) = any.downcast_ref::<String>() {
+            self.str(text);
+        } else if let Some(bytes) = any.downcast_ref::<Vec<u8>>() {
+            self.bytes(bytes);
+        } else {
+            // User defined types are written out by their display form
+            self.str(&val.to_string());
+        }
+    }
+}
+
+// Performs a single activation of a rule: searches for answers it has not
+// seen before, runs the where clauses on them, and inserts the results.
+// Returns the number of facts which were new to the database.
+fn activate(
+    fdb: &dyn FactDB,
+    demand: &Demand,
+    funcs: &HashMap<String, Rc<Func>>,
+    rule: &Rule,
+    next_fact_id: &mut Option<FactId>,
+    profile: &RefCell<RuleProfile>,
+) -> Result<usize> {
+    for clause in rule.body.iter() {
+        self::demand(fdb, demand, clause)?;
+    }
+    if !rule.aggregates.is_empty() {
+        return activate_aggregate(fdb, funcs, rule, profile);
+    }
+    let pre_db = Instant::now();
+    let states = fdb.search_facts(&rule.body, *next_fact_id)?;
+    profile.borrow_mut().add_select_time(pre_db.elapsed());
+    *next_fact_id = states
+        .iter()
+        .flat_map(|x| x.0.iter())
+        .max()
+        .map(|x| x + 1)
+        .or(*next_fact_id);
+    let results = states.len();
+    trace!("Query submitted");
+
+    let where_start = Instant::now();
+    let answers: Vec<(Vec<FactId>, Vec<Fact>)> = run_wheres(&rule.wheres, states, funcs)?
+        .into_iter()
+        .map(|state| {
+            let facts = rule.head
+                .iter()
+                .map(|head| substitute(head, &state.1))
+                .collect::<Result<_>>()?;
+            Ok((state.0, facts))
+        })
+        .collect::<Result<_>>()?;
+    profile.borrow_mut().add_compute_time(where_start.elapsed());
+
+    trace!("Insertions beginning");
+    let insert_start = Instant::now();
+    // Everything derived by this activation is stored in one batch
+    let derived: Vec<(Fact, Vec<(String, FactId)>)> = answers
+        .into_iter()
+        .flat_map(|(ids, facts)| {
+            let sources = premises(rule, &ids);
+            facts.into_iter().map(move |fact| (fact, sources.clone()))
+        })
+        .collect();
+    let productive = store_derived(fdb, funcs, &rule.name, &derived)?
+        .into_iter()
+        .filter(|id| id.is_some())
+        .count();
+    profile.borrow_mut().add_insert_time(insert_start.elapsed());
+    trace!("Insertions done");
+    trace!(
+        "Generated {} results, turned into {} facts.",
+        results,
+        productive
+    );
+    Ok(productive)
+}
+
+// Pairs the fact ids of an answer with the predicates they came from, which
+// are the positive body clauses in order
+fn premises(rule: &Rule, ids: &[FactId]) -> Vec<(String, FactId)> {
+    rule.body
+        .iter()
+        .filter(|clause| !clause.negated)
+        .map(|clause| clause.pred_name.clone())
+        .zip(ids.iter().cloned())
+        .collect()
+}
+
+// Aggregates summarize every answer rather than just the new ones, so each
+// activation recomputes all the groups and replaces any facts which changed.
+fn activate_aggregate(
+    fdb: &dyn FactDB,
+    funcs: &HashMap<String, Rc<Func>>,
+    rule: &Rule,
+    profile: &RefCell<RuleProfile>,
+) -> Result<usize> {
+    let key: Vec<usize> = (0..rule.head[0].args.len())
+        .filter(|slot| aggregate_at(rule, *slot).is_none())
+        .collect();
+
+    let pre_db = Instant::now();
+    let pushed = match grouping(rule) {
+        Some((group, aggs)) => fdb.search_grouped(&rule.body, &group, &aggs, &sql_aggregate)?,
+        None => None,
+    };
+    let facts = match pushed {
+        Some(rows) => {
+            profile.borrow_mut().add_select_time(pre_db.elapsed());
+            rows.into_iter().map(|row| grouped_fact(rule, row)).collect()
+        }
+        None => {
+            let states = fdb.search_facts(&rule.body, None)?;
+            profile.borrow_mut().add_select_time(pre_db.elapsed());
+            let where_start = Instant::now();
+            let states = run_wheres(&rule.wheres, states, funcs)?;
+            let facts = reduce_groups(rule, states.into_iter().map(|state| state.1).collect())?;
+            profile.borrow_mut().add_compute_time(where_start.elapsed());
+            facts
+        }
+    };
+
+    let insert_start = Instant::now();
+    let mut productive = 0;
+    for fact in facts.iter() {
+        // Which answers went into a group isn't tracked, so aggregate facts
+        // are recorded without premises
+        if let Some(id) = fdb.replace_fact(fact, &key)? {
+            fdb.record_provenance(id, &rule.name, &[])?;
+            productive += 1;
+        }
+    }
+    profile.borrow_mut().add_insert_time(insert_start.elapsed());
+    Ok(productive)
+}
+
+fn aggregate_at(rule: &Rule, slot: usize) -> Option<Aggregate> {
+    rule.aggregates
+        .iter()
+        .find(|&&(agg_slot, _)| agg_slot == slot)
+        .map(|&(_, agg)| agg)
+}
+
+// If the database can compute the rule's aggregates straight from its body,
+// provides the variables to group by and the variables to aggregate.
+#[allow(clippy::type_complexity)]
+fn grouping(rule: &Rule) -> Option<(Vec<Var>, Vec<(Aggregate, Var)>)> {
+    if !rule.wheres.is_empty() {
+        return None;
+    }
+    let mut group = Vec::new();
+    let mut aggs = Vec::new();
+    for (slot, arg) in rule.head[0].args.iter().enumerate() {
+        match (aggregate_at(rule, slot), arg) {
+            (Some(agg), &MatchExpr::Var(v)) => aggs.push((agg, v)),
+            (None, &MatchExpr::Var(v)) => group.push(v),
+            (None, &MatchExpr::Const(_)) => (),
+            _ => return None,
+        }
+    }
+    Some((group, aggs))
+}
+
+// Whether the database may compute an aggregate over a type itself, which
+// needs its SQL aggregate to agree with `reduce`
+fn sql_aggregate(type_: &Type, agg: Aggregate) -> bool {
+    match type_.name() {
+        Some("uint64") => true,
+        Some("string") => agg != Aggregate::Sum,
+        Some("bool") | Some("bytes") | Some("largebytes") => agg == Aggregate::Collect,
+        _ => false,
+    }
+}
+
+// Builds the head fact from a row produced by `search_grouped`
+fn grouped_fact(rule: &Rule, row: Vec<Value>) -> Fact {
+    let group_len = rule.head[0]
+        .args
+        .iter()
+        .enumerate()
+        .filter(|&(slot, arg)| match *arg {
+            MatchExpr::Var(_) => aggregate_at(rule, slot).is_none(),
+            _ => false,
+        })
+        .count();
+    let mut group = row[..group_len].iter();
+    let mut aggs = row[group_len..].iter();
+    Fact {
+        pred_name: rule.head[0].pred_name.clone(),
+        args: rule.head[0]
+            .args
+            .iter()
+            .enumerate()
+            .map(|(slot, arg)| match (aggregate_at(rule, slot), arg) {
+                (Some(_), _) => aggs.next().unwrap().clone(),
+                (None, MatchExpr::Const(v)) => v.clone(),
+                (None, _) => group.next().unwrap().clone(),
+            })
+            .collect(),
+    }
+}
+
+// Reduces answers down to one fact per group, for when the database can't
+// compute the aggregates itself
+fn reduce_groups(rule: &Rule, states: Vec<Vec<Value>>) -> Result<Vec<Fact>> {
+    let mut order = Vec::new();
+    let mut groups: HashMap<Vec<Value>, Vec<Vec<Option<Value>>>> = HashMap::new();
+    for state in states {
+        let mut key = Vec::new();
+        let mut agg_vals = Vec::new();
+        for (slot, arg) in rule.head[0].args.iter().enumerate() {
+            let val = match *arg {
+                MatchExpr::Var(v) => Some(state[v].clone()),
+                MatchExpr::Const(ref c) => Some(c.clone()),
+                MatchExpr::Unbound => None,
+            };
+            match (aggregate_at(rule, slot), val) {
+                (Some(_), val) => agg_vals.push(val),
+                (None, Some(val)) => key.push(val),
+                (None, None) => {
+                    bail!(ErrorKind::Invalid(
+                        format!("Rule {} has an unbound head slot", rule.name),
+                    ))
+                }
+            }
+        }
+        if !groups.contains_key(&key) {
+            order.push(key.clone());
+            groups.insert(key.clone(), rule.aggregates.iter().map(|_| Vec::new()).collect());
+        }
+        let columns = groups.get_mut(&key).unwrap();
+        for (column, val) in columns.iter_mut().zip(agg_vals) {
+            column.push(val);
+        }
+    }
+
+    let mut facts = Vec::new();
+    for key in order {
+        let mut columns = groups.remove(&key).unwrap().into_iter();
+        let mut key_vals = key.into_iter();
+        let mut args = Vec::new();
+        for slot in 0..rule.head[0].args.len() {
+            match aggregate_at(rule, slot) {
+                Some(agg) => args.push(reduce(agg, columns.next().unwrap())?),
+                None => args.push(key_vals.next().unwrap()),
+            }
+        }
+        facts.push(Fact {
+            pred_name: rule.head[0].pred_name.clone(),
+            args,
+        });
+    }
+    Ok(facts)
+}
+
+fn reduce(agg: Aggregate, vals: Vec<Option<Value>>) -> Result<Value> {
+    if agg == Aggregate::Count {
+        return Ok(values::UInt64::new(vals.len() as u64) as Value);
+    }
+    let vals = match vals.into_iter().collect::<Option<Vec<Value>>>() {
+        Some(vals) => vals,
+        None => bail!(ErrorKind::Invalid(format!("{:?} of an unbound slot", agg))),
+    };
+    match agg {
+        Aggregate::Count => unreachable!(),
+        Aggregate::Min => Ok(vals.into_iter().min().unwrap()),
+        Aggregate::Max => Ok(vals.into_iter().max().unwrap()),
+        Aggregate::Sum => {
+            let mut total = 0;
+            for val in vals.iter() {
+                match val.get().downcast_ref::<u64>() {
+                    Some(n) => total += *n,
+                    None => bail!(ErrorKind::Type(format!("Cannot sum {:?}", val.type_()))),
+                }
+            }
+            Ok(values::UInt64::new(total) as Value)
+        }
+        Aggregate::Collect => Ok(values::List::new(vals) as Value),
+    }
+}
+
+// Whether the predicate `from` depends on `to`, possibly transitively,
+// according to a map from rule heads to the predicates their bodies use
+fn depends_on(deps: &HashMap<String, Vec<(String, bool)>>, from: &str, to: &str) -> bool {
+    dependencies(deps, from).contains(to)
+}
+
+// The predicates `from` depends on, possibly transitively, including itself
+fn dependencies(deps: &HashMap<String, Vec<(String, bool)>>, from: &str) -> HashSet<String> {
+    let mut seen = HashSet::new();
+    let mut stack = vec![from.to_string()];
+    while let Some(pred) = stack.pop() {
+        if seen.insert(pred.clone()) {
+            if let Some(body) = deps.get(&pred) {
+                stack.extend(body.iter().map(|(dep, _)| dep.clone()));
+            }
+        }
+    }
+    seen
+}
+
 // In an assignment statement, once the rhs has been computed, binds the
 // rhs value onto the expression on the left, using the state to check that
 // already bound variables are bound to the same things
 // It returns list of output states, each of which is a list of var bindings
-fn bind(lhs: &BindExpr, rhs: Value, state: &Vec<Value>) -> Vec<Vec<Value>> {
+fn bind(lhs: &BindExpr, rhs: Value, state: &Vec<Value>) -> Result<Vec<Vec<Value>>> {
     use self::types::BindExpr::*;
     use self::types::MatchExpr::*;
     match *lhs {
         // If we are unbound, we no-op
-        Normal(Unbound) => vec![state.clone()],
+        Normal(Unbound) => Ok(vec![state.clone()]),
         // To bind to a variable,
         Normal(Var(v)) => {
             // If the variable is defined, check equality
             if v < state.len() {
                 if state[v] == rhs {
-                    vec![state.clone()]
+                    Ok(vec![state.clone()])
                 } else {
-                    vec![]
+                    Ok(vec![])
                 }
             // If the variable is to be defined, define it
             } else if v == state.len() {
                 let mut next = state.clone();
                 next.push(rhs.clone());
-                vec![next]
+                Ok(vec![next])
             // Otherwise it is a malformed binding
             } else {
-                panic!("Variable out of range")
+                bail!(ErrorKind::Internal(format!("Variable {} out of range", v)))
             }
         }
         Normal(Const(ref v)) => {
             if *v == rhs {
-                vec![state.clone()]
+                Ok(vec![state.clone()])
             } else {
-                vec![]
+                Ok(vec![])
             }
         }
         Destructure(ref lhss) => {
             let rhss = match rhs.get().downcast_ref::<Vec<Value>>() {
-                Some(ref rhss) => rhss.iter(),
-                _ => panic!("Attempted to destructure non-list"),
+                Some(rhss) => rhss,
+                _ => {
+                    bail!(ErrorKind::Type(
+                        format!("Attempted to destructure non-list {:?}", rhs),
+                    ))
+                }
             };
+            // A list's length is only known once it is produced
+            if rhss.len() != lhss.len() {
+                bail!(ErrorKind::Type(format!(
+                    "Cannot destructure {:?} into {} parts",
+                    rhs,
+                    lhss.len()
+                )))
+            }
             let mut next = vec![state.clone()];
-            for (lhs, rhs) in lhss.iter().zip(rhss) {
+            for (lhs, rhs) in lhss.iter().zip(rhss.iter()) {
                 let mut next_next = vec![];
                 for state in next {
-                    next_next.extend(bind(lhs, rhs.clone(), &state));
+                    next_next.extend(bind(lhs, rhs.clone(), &state)?);
                 }
                 next = next_next;
             }
-            next
+            Ok(next)
         }
         Iterate(ref inner) => {
             let rhss = match rhs.get().downcast_ref::<Vec<Value>>() {
-                Some(ref rhss) => rhss.iter(),
-                _ => panic!("Attempted to destructure non-list"),
+                Some(rhss) => rhss.iter(),
+                _ => {
+                    bail!(ErrorKind::Type(
+                        format!("Attempted to iterate over non-list {:?}", rhs),
+                    ))
+                }
             };
-            rhss.flat_map(|rhs| bind(inner, rhs.clone(), &state))
-                .collect()
+            let mut next = Vec::new();
+            for rhs in rhss {
+                next.extend(bind(inner, rhs.clone(), state)?);
+            }
+            Ok(next)
         }
     }
 }
 
 // Evaluates an expression, given a set of bindings to variables
-fn eval(expr: &Expr, subs: &Vec<Value>, funcs: &HashMap<String, Rc<Func>>) -> Value {
+fn eval(expr: &Expr, subs: &Vec<Value>, funcs: &HashMap<String, Rc<Func>>) -> Result<Value> {
     use self::types::Expr::*;
     match *expr {
-        Var(var) => subs[var as usize].clone(),
-        Val(ref val) => val.clone(),
+        Var(var) => Ok(subs[var].clone()),
+        Val(ref val) => Ok(val.clone()),
         App(ref fun_name, ref args) => {
-            let arg_vals: Vec<Value> = args.iter()
-                .map(|arg_expr| eval(arg_expr, subs, funcs))
-                .collect();
+            let func = funcs.get(fun_name).ok_or_else(|| {
+                ErrorKind::Invalid(format!("Function {} not registered", fun_name))
+            })?;
+            let mut arg_vals = Vec::new();
+            for arg_expr in args.iter() {
+                arg_vals.push(eval(arg_expr, subs, funcs)?);
+            }
             let arg = if arg_vals.len() == 1 {
                 arg_vals[0].clone()
             } else {
                 values::Tuple::new(arg_vals) as Value
             };
-            (funcs[fun_name].run)(arg)
+            Ok((func.run)(arg))
         }
     }
 }
@@ -22,11 +22,13 @@ use pg::dyn::{Type, Value};
 ///     fields: vec![Field {
 ///         name: None,
 ///         description: None,
-///         type_: Arc::new(types::UInt64)
+///         type_: Arc::new(types::UInt64),
+///         join: None
 ///     }, Field {
 ///         name: None,
 ///         description: None,
-///         type_: Arc::new(types::String)
+///         type_: Arc::new(types::String),
+///         join: None
 ///     }]
 /// };
 /// ```
@@ -47,7 +49,14 @@ pub struct Predicate {
 /// results.
 /// The `Type` is the only required component of a field, as it defines how to
 /// actually interact with the field.
+///
+/// If a `join` is provided, the field is a lattice: facts which agree on all
+/// the non-lattice fields of the predicate are merged into one, combining
+/// their lattice fields with the named function. That function must be
+/// registered with the engine, taking a pair of the field's type and
+/// returning a single one.
 #[derive(Clone, Debug, Hash, Eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 pub struct Field {
     /// Name of field, for use in matching and instantiating predicates
     pub name: Option<String>,
@@ -56,6 +65,8 @@ pub struct Field {
     /// Type of the predicate, explaining how to store and retrieve
     /// information from the `FactDB`
     pub type_: Type,
+    /// Name of the function merging values of this field, if it is a lattice
+    pub join: Option<String>,
 }
 
 // Manually implement PartialEq to work around rustc #[derive(PartialEq)] bug
@@ -63,7 +74,7 @@ pub struct Field {
 impl PartialEq for Field {
     fn eq(&self, other: &Self) -> bool {
         (self.name == other.name) && (self.description == other.description) &&
-            (self.type_.eq(&other.type_))
+            (self.type_.eq(&other.type_)) && (self.join == other.join)
     }
 }
 
@@ -104,6 +115,7 @@ pub type Var = usize;
 /// A `MatchExpr` represents the possible things that could show up in a slot
 /// in the body of a rule
 #[derive(Clone, Debug, Hash, Eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 pub enum MatchExpr {
     /// We do not care about the contents of the slot
     Unbound,
@@ -122,7 +134,7 @@ impl PartialEq for MatchExpr {
         match (self, other) {
             (&Unbound, &Unbound) => true,
             (&Var(x), &Var(y)) => x == y,
-            (&Const(ref v), &Const(ref vv)) => v == vv,
+            (Const(v), Const(vv)) => v == vv,
             _ => false,
         }
     }
@@ -161,20 +173,28 @@ pub enum BindExpr {
 /// use holmes::engine::types::{Clause,MatchExpr};
 /// Clause {
 ///   pred_name : "foo".to_string(),
-///   args : vec![MatchExpr::Unbound, MatchExpr::Var(0)]
+///   args : vec![MatchExpr::Unbound, MatchExpr::Var(0)],
+///   negated : false
 /// };
 /// ```
+///
+/// A negated clause (only meaningful in a body) matches when there is _no_
+/// fact fitting the pattern. Any variables it mentions must already be bound
+/// by a positive clause.
 #[derive(PartialEq, Clone, Debug, Hash, Eq)]
 pub struct Clause {
     /// Name of the predicate to match against
     pub pred_name: String,
     /// List of how to restrict or bind each slot
     pub args: Vec<MatchExpr>,
+    /// Whether this clause requires the absence of a match
+    pub negated: bool,
 }
 
 /// `Expr` represents the right hand side of the where clause sublanguage of
 /// Holmes.
 #[derive(Clone, Debug, Hash, Eq)]
+#[allow(clippy::derived_hash_with_manual_eq)]
 pub enum Expr {
     /// Evaluates to whatever the inner variable is defined to.
     Var(Var),
@@ -191,28 +211,80 @@ impl PartialEq for Expr {
     fn eq(&self, other: &Expr) -> bool {
         use self::Expr::*;
         match (self, other) {
-            (&Var(ref x), &Var(ref y)) => x == y,
-            (&Val(ref x), &Val(ref y)) => x == y,
-            (&App(ref s0, ref ex0), &App(ref s1, ref ex1)) => (s0 == s1) && (ex0 == ex1),
+            (Var(x), Var(y)) => x == y,
+            (Val(x), Val(y)) => x == y,
+            (App(s0, ex0), App(s1, ex1)) => (s0 == s1) && (ex0 == ex1),
             _ => false,
         }
     }
 }
 
+/// An `Aggregate` reduces the values a head slot takes across a group of
+/// answers down to a single value.
+#[derive(PartialEq, Clone, Copy, Debug, Hash, Eq)]
+pub enum Aggregate {
+    /// The number of answers in the group, as a `uint64`
+    Count,
+    /// The least value in the group
+    Min,
+    /// The greatest value in the group
+    Max,
+    /// The total of the values in the group, which must be `uint64`s
+    Sum,
+    /// All the values in the group, as a list
+    Collect,
+}
+
 /// A `Rule` represents a complete inference technique in the Holmes system
 /// If the `body` clauses match, the `wheres` clauses are run on the answer
-/// set, producing a new answer set, and the `head` clause is instantiated
-/// at that answer set and inserted into the database.
+/// set, producing a new answer set, and each of the `head` clauses is
+/// instantiated at that answer set and inserted into the database. The facts
+/// for a single answer are inserted together, or not at all.
+///
+/// If the body contains negated clauses, the program must remain stratified:
+/// no predicate may depend on the negation of itself, directly or otherwise.
+///
+/// If `aggregates` is non-empty, there must be exactly one head clause.
+/// Answers are grouped by the values of its other slots, and only one fact
+/// is kept per group. When new answers arrive, the group's fact is replaced
+/// rather than added to.
+///
+/// If there are `alternatives`, the rule is a disjunction: it fires on a
+/// match of `body` or of any alternative body. Every head variable must be
+/// bound by each of them.
+///
+/// A `backward` rule is never run on its own. Instead, it runs when a query
+/// or another rule's body asks for facts of its head predicate, and only for
+/// facts matching the constants asked for.
 #[derive(PartialEq, Clone, Debug, Hash, Eq)]
 pub struct Rule {
     /// Identifier for the rule
     pub name: String,
-    /// Template for the facts this rule will output
-    pub head: Clause,
+    /// Templates for the facts this rule will output
+    pub head: Vec<Clause>,
     /// Datalog body to search the database with
     pub body: Vec<Clause>,
+    /// Further bodies which may match in place of `body`
+    pub alternatives: Vec<Vec<Clause>>,
     /// Embedded language to call native functions on the results
     pub wheres: Vec<WhereClause>,
+    /// Head slots to reduce, and how to reduce them
+    pub aggregates: Vec<(usize, Aggregate)>,
+    /// Whether to run only on demand, rather than whenever the body changes
+    pub backward: bool,
+}
+
+/// A `Derivation` explains why a fact is in the database, as a tree of the
+/// rules which produced it and the facts they matched on.
+#[derive(PartialEq, Clone, Debug, Hash, Eq)]
+pub struct Derivation {
+    /// The fact being explained
+    pub fact: Fact,
+    /// Name of the rule which produced the fact, or `None` if the fact was
+    /// stored directly
+    pub rule: Option<String>,
+    /// Derivations of the facts the rule's body matched
+    pub premises: Vec<Derivation>,
 }
 
 /// A `WhereClause` is a single assignment in the Holmes sublanguage.
@@ -234,5 +306,23 @@ pub struct Func {
     /// The type of the `Value` the function will produce as output
     pub output_type: Type,
     /// The function itself
-    pub run: Box<Fn(Value) -> Value>,
+    pub run: Box<dyn Fn(Value) -> Value>,
+}
+
+/// A `Migration` is one step in changing the signature of a predicate which
+/// already has facts stored, for use with `Engine::migrate_predicate`.
+/// Slots are numbered as they stand after any earlier steps.
+#[derive(Clone, Debug)]
+pub enum Migration {
+    /// Appends a field, filling it with the provided value in existing facts
+    AddField(Field, Value),
+    /// Removes the field in the given slot
+    DropField(usize),
+    /// Renames the field in the given slot
+    RenameField(usize, Option<String>),
+    /// Rewrites each fact through the named function, which must be
+    /// registered with the engine. It receives the fact's arguments, and
+    /// must return them in the same shape, packed into a tuple if there are
+    /// several, as for function application in rules.
+    Rewrite(String),
 }
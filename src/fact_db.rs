@@ -0,0 +1,329 @@
+//! Fact Database Interface
+//!
+//! The `Engine` stores and searches facts through the `FactDB` trait, so that
+//! it can run over more than one backend. `PgDB` persists facts in Postgres,
+//! and `SqliteDB` (with the `sqlite` feature) in a SQLite file, while `MemDB`
+//! keeps them in memory, which is handy for tests and short interactive
+//! sessions where a database server would be a burden.
+//!
+//! Backends only need to provide the basic operations. The rest have default
+//! implementations built from those, which a backend may override if it can
+//! do better (e.g. by pushing work into its query language, or by using
+//! transactions).
+
+use engine::types::{Aggregate, Clause, Fact, MatchExpr, Predicate, Var};
+use pg::dyn::{Type, Value};
+
+#[allow(missing_docs)]
+mod errors {
+    use pg;
+    #[cfg(feature = "sqlite")]
+    use sqlite;
+    error_chain! {
+        errors {
+            Type(msg: String) {
+                description("Type Error")
+                display("Type Error: {}", msg)
+            }
+            Internal(msg: String) {
+                description("FactDB Internal Error")
+                display("FactDB Internal Error: {}", msg)
+            }
+            Arg(msg: String) {
+                description("Bad argument")
+                display("Bad argument: {}", msg)
+            }
+        }
+        links {
+            Pg(pg::Error, pg::ErrorKind);
+            Sqlite(sqlite::Error, sqlite::ErrorKind) #[cfg(feature = "sqlite")];
+        }
+    }
+}
+
+pub use self::errors::*;
+
+/// Identifier for a fact, unique across all predicates in a database.
+/// Facts stored later are given larger ids.
+pub type FactId = i64;
+
+/// A `FactDB` stores typed predicates and the facts inhabiting them.
+pub trait FactDB {
+    /// Registers a new named type
+    fn add_type(&self, type_: Type) -> Result<()>;
+
+    /// Looks up a type by name
+    fn get_type(&self, name: &str) -> Option<Type>;
+
+    /// Registers a predicate. Registering an identical predicate again is
+    /// allowed, but registering a different one under the same name is not.
+    fn new_predicate(&self, pred: &Predicate) -> Result<()>;
+
+    /// Looks up a predicate by name
+    fn get_predicate(&self, pred_name: &str) -> Option<Predicate>;
+
+    /// Lists all registered predicates, ordered by name
+    fn list_predicates(&self) -> Vec<Predicate>;
+
+    /// Adds a new fact, returning its id, or `None` if it was already present
+    fn insert_fact(&self, fact: &Fact) -> Result<Option<FactId>>;
+
+    /// Adds several facts, returning the id of each, or `None` for those
+    /// which were already present. Facts repeated within `facts` are only
+    /// stored once.
+    ///
+    /// Facts for predicates with lattice fields are not merged, and should
+    /// go through `store_facts` instead. Backends which can should store all
+    /// or none of the facts, and are encouraged to do it in bulk. By default,
+    /// they are inserted one at a time.
+    fn insert_facts(&self, facts: &[Fact]) -> Result<Vec<Option<FactId>>> {
+        facts.iter().map(|fact| self.insert_fact(fact)).collect()
+    }
+
+    /// Finds the answers to a query, each as the ids of the facts matching
+    /// the positive clauses and the values of the variables.
+    ///
+    /// If `min_fact_id` is provided, only answers using at least one fact
+    /// with at least that id are returned.
+    fn search_facts(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> Result<Vec<(Vec<FactId>, Vec<Value>)>>;
+
+    /// Removes the fact with the given id, returning false if there was no
+    /// such fact
+    fn delete_fact(&self, pred_name: &str, id: FactId) -> Result<bool>;
+
+    /// Looks up a fact by its id
+    fn get_fact(&self, pred_name: &str, id: FactId) -> Result<Option<Fact>>;
+
+    /// Records that a fact was derived by `rule` from the `premises`, given
+    /// as predicate names and fact ids. Only the first derivation recorded
+    /// for a fact is kept.
+    fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> Result<()>;
+
+    /// Finds the rule which derived a fact and the premises it used, or
+    /// `None` if the fact was not derived by a rule.
+    #[allow(clippy::type_complexity)]
+    fn get_provenance(&self, fact_id: FactId) -> Result<Option<(String, Vec<(String, FactId)>)>>;
+
+    /// Looks up how far a rule has got, as stored by `set_cursor`. Rules are
+    /// identified by their name and a hash of their definition, so a rule
+    /// which has changed has no cursor.
+    fn get_cursor(&self, rule: &str, hash: &str) -> Result<Option<FactId>>;
+
+    /// Stores how far a rule has got, i.e. the least fact id it has not yet
+    /// seen. Storing `None` forgets the cursor, so that the rule starts over.
+    fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> Result<()>;
+
+    /// Indexes a predicate's facts by the given slots, for searches which
+    /// look them up by those slots together, unless there is such an index
+    /// already.
+    ///
+    /// Backends which don't benefit, e.g. because they index every slot
+    /// anyway, may do nothing. By default, nothing is done.
+    fn create_index(&self, _pred_name: &str, _slots: &[usize]) -> Result<()> {
+        Ok(())
+    }
+
+    /// Changes the fields of a registered predicate to those of `pred`,
+    /// converting the arguments of each stored fact with `migrate`.
+    /// Facts keep their ids, except that a fact which becomes a duplicate of
+    /// an earlier one is dropped along with its provenance. `migrate` gives
+    /// `None` if it can't convert a fact, in which case this fails.
+    ///
+    /// Backends should apply the whole change or none of it.
+    fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> Result<()>;
+
+    /// Groups the answers to a query by the `group` variables and
+    /// aggregates the rest, returning a row of group values followed by
+    /// aggregates for each group.
+    ///
+    /// `supported` says whether the backend may compute an aggregate over a
+    /// type itself, rather than leaving it to the engine. Returns `None` if
+    /// the backend can't compute these aggregates, in which case the engine
+    /// does it instead. By default, this is always the case.
+    fn search_grouped(
+        &self,
+        _query: &[Clause],
+        _group: &[Var],
+        _aggs: &[(Aggregate, Var)],
+        _supported: &dyn Fn(&Type, Aggregate) -> bool,
+    ) -> Result<Option<Vec<Vec<Value>>>> {
+        Ok(None)
+    }
+
+    /// Stores a fact, replacing any facts which agree with it on the `key`
+    /// slots. Returns `None` if the fact was already present as-is.
+    fn replace_fact(&self, fact: &Fact, key: &[usize]) -> Result<Option<FactId>> {
+        let all: Vec<usize> = (0..fact.args.len()).collect();
+        if !matching_facts(self, fact, &all)?.is_empty() {
+            return Ok(None);
+        }
+        for (id, _) in matching_facts(self, fact, key)? {
+            self.delete_fact(&fact.pred_name, id)?;
+        }
+        self.insert_fact(fact)
+    }
+
+    /// Stores a fact for a predicate with lattice fields. If a fact agreeing
+    /// on all the non-lattice fields is present, the lattice fields are
+    /// combined using `join`, which receives the slot and the stored and new
+    /// values, and the result is stored under a new id. `join` gives `None`
+    /// if it has no way to combine them, in which case this fails.
+    /// Returns `None` if this did not change the stored fact.
+    fn merge_fact(
+        &self,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Option<FactId>> {
+        let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        let (lattice, key): (Vec<usize>, Vec<usize>) =
+            (0..pred.fields.len()).partition(|slot| pred.fields[*slot].join.is_some());
+        let (id, old) = match matching_facts(self, fact, &key)?.into_iter().next() {
+            Some(existing) => existing,
+            None => return self.insert_fact(fact),
+        };
+        let mut merged = fact.clone();
+        let mut changed = false;
+        for slot in lattice {
+            let joined = match join(slot, &old[slot], &fact.args[slot]) {
+                Some(joined) => joined,
+                None => bail!(ErrorKind::Arg(
+                    format!("Slot {} of {} cannot be joined", slot, fact.pred_name),
+                )),
+            };
+            changed |= *joined != *old[slot];
+            merged.args[slot] = joined;
+        }
+        if !changed {
+            return Ok(None);
+        }
+        self.delete_fact(&fact.pred_name, id)?;
+        self.insert_fact(&merged)
+    }
+
+    /// Stores several facts, merging those for predicates with lattice fields
+    /// as per `merge_fact`, with `join` also receiving the fact.
+    ///
+    /// Backends which can should store all or none of the facts. By default,
+    /// they are stored one at a time.
+    fn store_facts(
+        &self,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let mut ids = Vec::new();
+        for fact in facts.iter() {
+            let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+                ErrorKind::Arg("Predicate not registered".to_string())
+            })?;
+            if pred.fields.iter().any(|field| field.join.is_some()) {
+                ids.push(self.merge_fact(
+                    fact,
+                    &|slot, old, new| join(fact, slot, old, new),
+                )?);
+            } else {
+                ids.push(self.insert_fact(fact)?);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Stores the facts derived by an activation of `rule`, each along with
+    /// the premises it was derived from, and records the provenance of those
+    /// which were new. Returns the id of each new fact, or `None` for the
+    /// rest. Facts for predicates with lattice fields are merged as per
+    /// `store_facts`.
+    ///
+    /// Backends which can should do this in a single transaction. By default,
+    /// the facts are stored with `insert_facts`, or `store_facts` if any need
+    /// merging, and then each provenance is recorded separately.
+    fn store_derived(
+        &self,
+        rule: &str,
+        derived: &[(Fact, Vec<(String, FactId)>)],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let facts: Vec<Fact> = derived.iter().map(|(fact, _)| fact.clone()).collect();
+        let ids = if any_lattice(self, &facts)? {
+            self.store_facts(&facts, join)?
+        } else {
+            self.insert_facts(&facts)?
+        };
+        for (id, (_, premises)) in ids.iter().zip(derived.iter()) {
+            if let Some(id) = *id {
+                self.record_provenance(id, rule, premises)?;
+            }
+        }
+        Ok(ids)
+    }
+}
+
+// Whether any of the facts are for predicates with lattice fields, and so
+// need merging rather than plain insertion
+fn any_lattice<DB: FactDB + ?Sized>(db: &DB, facts: &[Fact]) -> Result<bool> {
+    for fact in facts.iter() {
+        let pred = db.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        if pred.fields.iter().any(|field| field.join.is_some()) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Finds the stored facts agreeing with `fact` on the given slots, along with
+// their ids
+fn matching_facts<DB: FactDB + ?Sized>(
+    db: &DB,
+    fact: &Fact,
+    slots: &[usize],
+) -> Result<Vec<(FactId, Vec<Value>)>> {
+    let mut next_var = 0;
+    let args: Vec<MatchExpr> = fact.args
+        .iter()
+        .enumerate()
+        .map(|(slot, arg)| if slots.contains(&slot) {
+            MatchExpr::Const(arg.clone())
+        } else {
+            next_var += 1;
+            MatchExpr::Var(next_var - 1)
+        })
+        .collect();
+    let query = vec![
+        Clause {
+            pred_name: fact.pred_name.clone(),
+            args: args.clone(),
+            negated: false,
+        },
+    ];
+    Ok(
+        db.search_facts(&query, None)?
+            .into_iter()
+            .map(|(ids, vals)| {
+                let row = args.iter()
+                    .map(|arg| match *arg {
+                        MatchExpr::Var(var) => vals[var].clone(),
+                        MatchExpr::Const(ref val) => val.clone(),
+                        MatchExpr::Unbound => unreachable!(),
+                    })
+                    .collect();
+                (ids[0], row)
+            })
+            .collect(),
+    )
+}
@@ -0,0 +1,691 @@
+//! Holmes Language Parser
+//!
+//! This module reads Holmes programs written as text, following the grammar
+//! in `formal/holmes.tex`, so that programs can be written without going
+//! through the Rust EDSL. For example,
+//!
+//! ```c
+//! // Declarations give each field a type, and optionally a name,
+//! // a join function (for lattices) and a description
+//! predicate edge(src: uint64 "Source node", dst: uint64).
+//! predicate path(uint64, uint64) "dst is reachable from src".
+//! predicate label(node: uint64, names: [string]).
+//!
+//! edge(1, 2).
+//! edge(2, 3).
+//!
+//! path_base: path(x, y) <= edge(x, y).
+//! path_step: path(x, z) <= path(x, y) & edge(y, z).
+//! named: label(x, collect(n)) <= edge(x, _) where [n] = names_of(x).
+//!
+//! ?- path(1, x).
+//! ```
+//!
+//! Rules are as for the `rule!` macro: heads are separated by `,`, body
+//! clauses by `&` and alternative bodies by `|`, `<-` makes a backward rule,
+//! and `not(...)` negates a body clause. A head slot may be wrapped in one of
+//! `count`, `min`, `max`, `sum` or `collect` to aggregate it. Where clauses
+//! follow `where`, separated by `,`, binding with `[x]` to iterate over a
+//! list and `(x, y)` to destructure a tuple.
+//!
+//! Constants are unsigned integers, `"strings"`, `b"bytes"`, `true` and
+//! `false`, as well as `[lists]` and `(tuples)` of these. Any other
+//! identifier in a slot is a variable, and `_` leaves the slot unbound.
+//! Comments run from `//` to the end of the line.
+
+use std::collections::HashMap;
+
+use engine::types::{Aggregate, BindExpr, Clause, Expr, Fact, Field, MatchExpr, Predicate,
+                    Rule, Var, WhereClause};
+use pg::dyn::{types, values};
+use pg::dyn::{Type, Value};
+use pg::dyn::values::ToValue;
+
+#[allow(missing_docs)]
+mod errors {
+    error_chain! {
+        errors {
+            Syntax(line: usize, column: usize, msg: String) {
+                description("Syntax Error")
+                display("Syntax Error at {}:{}: {}", line, column, msg)
+            }
+        }
+    }
+}
+
+pub use self::errors::*;
+
+/// A query written in a program, along with the names of its variables, in
+/// the order of the values in each answer.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Query {
+    /// Clauses to search for
+    pub clauses: Vec<Clause>,
+    /// Variable names, indexed by `Var`
+    pub vars: Vec<String>,
+}
+
+/// A single top level item of a program
+#[derive(PartialEq, Clone, Debug)]
+pub enum Statement {
+    /// `predicate name(fields) "description".`
+    Predicate(Predicate),
+    /// `name(constants).`
+    Fact(Fact),
+    /// `name: heads <= bodies where binds.`
+    Rule(Rule),
+    /// `?- clauses.`
+    Query(Query),
+}
+
+/// Parses a program into its statements, in order. Types are looked up by
+/// name through `types`.
+pub fn parse(src: &str, types: &dyn Fn(&str) -> Option<Type>) -> Result<Vec<Statement>> {
+    let mut parser = Parser {
+        tokens: lex(src)?,
+        pos: 0,
+        types,
+    };
+    let mut stmts = Vec::new();
+    while parser.peek().tok != Tok::Eof {
+        stmts.push(parser.statement()?);
+    }
+    Ok(stmts)
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Tok {
+    Ident(String),
+    Int(u64),
+    Str(String),
+    Bytes(Vec<u8>),
+    Punct(&'static str),
+    Eof,
+}
+
+impl Tok {
+    fn describe(&self) -> String {
+        match *self {
+            Tok::Ident(ref name) => format!("`{}`", name),
+            Tok::Int(n) => format!("`{}`", n),
+            Tok::Str(_) => "a string".to_string(),
+            Tok::Bytes(_) => "a byte string".to_string(),
+            Tok::Punct(p) => format!("`{}`", p),
+            Tok::Eof => "end of input".to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    tok: Tok,
+    line: usize,
+    column: usize,
+}
+
+// Longer punctuation comes first, so that it wins over its prefixes
+const PUNCT: &[&str] = &[
+    "<=", "<-", "?-", "(", ")", "[", "]", ",", ":", ".", "&", "|", "=",
+];
+
+fn lex(src: &str) -> Result<Vec<Token>> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    let mut line = 1;
+    let mut column = 1;
+    loop {
+        // Skip whitespace and comments
+        while i < chars.len() {
+            if chars[i] == '\n' {
+                line += 1;
+                column = 1;
+                i += 1;
+            } else if chars[i].is_whitespace() {
+                column += 1;
+                i += 1;
+            } else if chars[i] == '/' && chars.get(i + 1) == Some(&'/') {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            } else {
+                break;
+            }
+        }
+        let start = (line, column);
+        let fail = |msg: String| -> Error { ErrorKind::Syntax(start.0, start.1, msg).into() };
+        if i == chars.len() {
+            tokens.push(Token {
+                tok: Tok::Eof,
+                line,
+                column,
+            });
+            return Ok(tokens);
+        }
+        let c = chars[i];
+        let begin = i;
+        let tok = if c == 'b' && chars.get(i + 1) == Some(&'"') {
+            i += 1;
+            Tok::Bytes(string_body(&chars, &mut i, true).map_err(&fail)?)
+        } else if c == '"' {
+            // Without `\x` escapes, the contents are always valid UTF-8
+            let bytes = string_body(&chars, &mut i, false).map_err(&fail)?;
+            Tok::Str(String::from_utf8(bytes).unwrap())
+        } else if c.is_alphabetic() || c == '_' {
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            Tok::Ident(chars[begin..i].iter().cloned().collect())
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && chars[i].is_ascii_digit() {
+                i += 1;
+            }
+            let digits: String = chars[begin..i].iter().cloned().collect();
+            Tok::Int(digits.parse().map_err(|_| {
+                fail(format!("{} does not fit in a uint64", digits))
+            })?)
+        } else {
+            let punct = PUNCT.iter().cloned().find(|p| {
+                p.chars().enumerate().all(|(off, pc)| chars.get(i + off) == Some(&pc))
+            });
+            match punct {
+                Some(p) => {
+                    i += p.len();
+                    Tok::Punct(p)
+                }
+                None => return Err(fail(format!("Unexpected character `{}`", c))),
+            }
+        };
+        // Tokens never span lines, since strings must escape newlines
+        column += i - begin;
+        tokens.push(Token {
+            tok,
+            line: start.0,
+            column: start.1,
+        });
+    }
+}
+
+// Reads a quoted string starting at the opening quote, leaving `i` just past
+// the closing one. `\xNN` escapes are only allowed in byte strings.
+fn string_body(
+    chars: &[char],
+    i: &mut usize,
+    bytes: bool,
+) -> ::std::result::Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    *i += 1;
+    loop {
+        let c = match chars.get(*i) {
+            Some(&'\n') | None => return Err("Unterminated string".to_string()),
+            Some(&c) => c,
+        };
+        *i += 1;
+        match c {
+            '"' => return Ok(out),
+            '\\' => {
+                let esc = chars.get(*i).cloned();
+                *i += 1;
+                match esc {
+                    Some('n') => out.push(b'\n'),
+                    Some('t') => out.push(b'\t'),
+                    Some('0') => out.push(0),
+                    Some('\\') => out.push(b'\\'),
+                    Some('"') => out.push(b'"'),
+                    Some('x') if bytes => {
+                        let hex: String = chars[*i..].iter().take(2).cloned().collect();
+                        *i += hex.len();
+                        match u8::from_str_radix(&hex, 16) {
+                            Ok(b) if hex.len() == 2 => out.push(b),
+                            _ => return Err(format!("Bad byte escape `\\x{}`", hex)),
+                        }
+                    }
+                    Some(e) => return Err(format!("Unknown escape `\\{}`", e)),
+                    None => return Err("Unterminated string".to_string()),
+                }
+            }
+            c => {
+                let mut buf = [0; 4];
+                out.extend(c.encode_utf8(&mut buf).as_bytes());
+            }
+        }
+    }
+}
+
+// A slot in a rule head, before variables are numbered
+enum Term {
+    Unbound,
+    Var(String),
+    Const(Value),
+}
+
+// Assigns variable numbers by name, in order of first use
+struct Scope {
+    vars: HashMap<String, Var>,
+    names: Vec<String>,
+}
+
+impl Scope {
+    fn new() -> Self {
+        Scope {
+            vars: HashMap::new(),
+            names: Vec::new(),
+        }
+    }
+
+    fn var(&mut self, name: &str) -> Var {
+        if let Some(var) = self.vars.get(name) {
+            return *var;
+        }
+        let var = self.names.len();
+        self.vars.insert(name.to_string(), var);
+        self.names.push(name.to_string());
+        var
+    }
+
+    fn term(&mut self, term: &Term) -> MatchExpr {
+        match *term {
+            Term::Unbound => MatchExpr::Unbound,
+            Term::Var(ref name) => MatchExpr::Var(self.var(name)),
+            Term::Const(ref val) => MatchExpr::Const(val.clone()),
+        }
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    types: &'a dyn Fn(&str) -> Option<Type>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> &Token {
+        self.peek_at(0)
+    }
+
+    fn peek_at(&self, off: usize) -> &Token {
+        let last = self.tokens.len() - 1;
+        &self.tokens[::std::cmp::min(self.pos + off, last)]
+    }
+
+    fn fail<T>(&self, msg: String) -> Result<T> {
+        let token = self.peek();
+        bail!(ErrorKind::Syntax(token.line, token.column, msg))
+    }
+
+    fn unexpected<T>(&self, wanted: &str) -> Result<T> {
+        let found = self.peek().tok.describe();
+        self.fail(format!("Expected {}, found {}", wanted, found))
+    }
+
+    fn at_punct(&self, p: &str) -> bool {
+        match self.peek().tok {
+            Tok::Punct(q) => q == p,
+            _ => false,
+        }
+    }
+
+    fn eat_punct(&mut self, p: &str) -> bool {
+        if self.at_punct(p) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect_punct(&mut self, p: &str) -> Result<()> {
+        if self.eat_punct(p) {
+            Ok(())
+        } else {
+            self.unexpected(&format!("`{}`", p))
+        }
+    }
+
+    fn at_keyword(&self, off: usize, word: &str) -> bool {
+        match self.peek_at(off).tok {
+            Tok::Ident(ref name) => name == word,
+            _ => false,
+        }
+    }
+
+    fn ident(&mut self, wanted: &str) -> Result<String> {
+        match self.peek().tok.clone() {
+            Tok::Ident(ref name) if !is_reserved(name) => {
+                self.pos += 1;
+                Ok(name.clone())
+            }
+            _ => self.unexpected(wanted),
+        }
+    }
+
+    // Parses a comma separated list, with at least one element
+    fn list<T, F: FnMut(&mut Self) -> Result<T>>(&mut self, mut elem: F) -> Result<Vec<T>> {
+        let mut elems = vec![elem(self)?];
+        while self.eat_punct(",") {
+            elems.push(elem(self)?);
+        }
+        Ok(elems)
+    }
+
+    fn statement(&mut self) -> Result<Statement> {
+        let is_ident = |tok: &Tok| matches!(*tok, Tok::Ident(_));
+        if self.at_keyword(0, "predicate") && is_ident(&self.peek_at(1).tok) {
+            self.predicate().map(Statement::Predicate)
+        } else if self.eat_punct("?-") {
+            self.query().map(Statement::Query)
+        } else if is_ident(&self.peek().tok) && self.peek_at(1).tok == Tok::Punct(":") {
+            self.rule().map(Statement::Rule)
+        } else if is_ident(&self.peek().tok) && self.peek_at(1).tok == Tok::Punct("(") {
+            self.fact().map(Statement::Fact)
+        } else {
+            self.unexpected("a predicate, fact, rule or query")
+        }
+    }
+
+    fn predicate(&mut self) -> Result<Predicate> {
+        self.pos += 1;
+        let name = self.ident("a predicate name")?;
+        self.expect_punct("(")?;
+        let fields = self.list(|p| p.field())?;
+        self.expect_punct(")")?;
+        let description = self.description();
+        self.expect_punct(".")?;
+        Ok(Predicate {
+            name,
+            description,
+            fields,
+        })
+    }
+
+    fn description(&mut self) -> Option<String> {
+        match self.peek().tok.clone() {
+            Tok::Str(descr) => {
+                self.pos += 1;
+                Some(descr)
+            }
+            _ => None,
+        }
+    }
+
+    fn field(&mut self) -> Result<Field> {
+        if self.peek_at(1).tok != Tok::Punct(":") {
+            return Ok(Field {
+                name: None,
+                description: None,
+                type_: self.type_()?,
+                join: None,
+            });
+        }
+        let name = self.ident("a field name")?;
+        self.pos += 1;
+        let type_ = self.type_()?;
+        let join = if self.at_keyword(0, "join") {
+            self.pos += 1;
+            Some(self.ident("a join function")?)
+        } else {
+            None
+        };
+        Ok(Field {
+            name: Some(name),
+            description: self.description(),
+            type_,
+            join,
+        })
+    }
+
+    fn type_(&mut self) -> Result<Type> {
+        if self.eat_punct("[") {
+            let elem = self.type_()?;
+            self.expect_punct("]")?;
+            return Ok(types::List::new(elem) as Type);
+        }
+        if self.eat_punct("(") {
+            let mut elems = self.list(|p| p.type_())?;
+            self.expect_punct(")")?;
+            if elems.len() == 1 {
+                return Ok(elems.remove(0));
+            }
+            return Ok(types::Tuple::new(elems) as Type);
+        }
+        let name = match self.peek().tok.clone() {
+            Tok::Ident(name) => name,
+            _ => return self.unexpected("a type"),
+        };
+        match (self.types)(name.as_str()) {
+            Some(type_) => {
+                self.pos += 1;
+                Ok(type_)
+            }
+            None => self.fail(format!("Unknown type `{}`", name)),
+        }
+    }
+
+    fn fact(&mut self) -> Result<Fact> {
+        let pred_name = self.ident("a predicate name")?;
+        self.expect_punct("(")?;
+        let args = self.list(|p| p.constant())?;
+        self.expect_punct(")")?;
+        self.expect_punct(".")?;
+        Ok(Fact {
+            pred_name,
+            args,
+        })
+    }
+
+    fn constant(&mut self) -> Result<Value> {
+        let val = match self.peek().tok.clone() {
+            Tok::Int(n) => n.to_value(),
+            Tok::Str(s) => s.to_value(),
+            Tok::Bytes(b) => b.to_value(),
+            Tok::Ident(ref word) if word == "true" => true.to_value(),
+            Tok::Ident(ref word) if word == "false" => false.to_value(),
+            Tok::Punct("[") => {
+                self.pos += 1;
+                let elems = if self.at_punct("]") {
+                    Vec::new()
+                } else {
+                    self.list(|p| p.constant())?
+                };
+                self.expect_punct("]")?;
+                return Ok(values::List::new(elems) as Value);
+            }
+            Tok::Punct("(") => {
+                self.pos += 1;
+                let mut elems = self.list(|p| p.constant())?;
+                self.expect_punct(")")?;
+                if elems.len() == 1 {
+                    return Ok(elems.remove(0));
+                }
+                return Ok(values::Tuple::new(elems) as Value);
+            }
+            _ => return self.unexpected("a constant"),
+        };
+        self.pos += 1;
+        Ok(val)
+    }
+
+    fn term(&mut self) -> Result<Term> {
+        match self.peek().tok.clone() {
+            Tok::Ident(ref name) if name == "_" => {
+                self.pos += 1;
+                Ok(Term::Unbound)
+            }
+            Tok::Ident(ref name) if !is_reserved(name) => {
+                self.pos += 1;
+                Ok(Term::Var(name.clone()))
+            }
+            _ => self.constant().map(Term::Const),
+        }
+    }
+
+    fn clause(&mut self, scope: &mut Scope) -> Result<Clause> {
+        let negated = self.at_keyword(0, "not") && self.peek_at(1).tok == Tok::Punct("(");
+        if negated {
+            self.pos += 2;
+        }
+        let pred_name = self.ident("a predicate name")?;
+        self.expect_punct("(")?;
+        let args = self.list(|p| p.term())?;
+        self.expect_punct(")")?;
+        if negated {
+            self.expect_punct(")")?;
+        }
+        Ok(Clause {
+            pred_name,
+            args: args.iter().map(|term| scope.term(term)).collect(),
+            negated,
+        })
+    }
+
+    fn body(&mut self, scope: &mut Scope) -> Result<Vec<Clause>> {
+        let mut body = vec![self.clause(scope)?];
+        while self.eat_punct("&") {
+            body.push(self.clause(scope)?);
+        }
+        Ok(body)
+    }
+
+    fn query(&mut self) -> Result<Query> {
+        let mut scope = Scope::new();
+        let clauses = self.body(&mut scope)?;
+        self.expect_punct(".")?;
+        Ok(Query {
+            clauses,
+            vars: scope.names,
+        })
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn head(&mut self) -> Result<(String, Vec<(Term, Option<Aggregate>)>)> {
+        let pred_name = self.ident("a predicate name")?;
+        self.expect_punct("(")?;
+        let slots = self.list(|p| {
+            let agg = match p.peek().tok {
+                Tok::Ident(ref name) if p.peek_at(1).tok == Tok::Punct("(") => {
+                    match name.as_str() {
+                        "count" => Some(Aggregate::Count),
+                        "min" => Some(Aggregate::Min),
+                        "max" => Some(Aggregate::Max),
+                        "sum" => Some(Aggregate::Sum),
+                        "collect" => Some(Aggregate::Collect),
+                        _ => return p.fail(format!("Unknown aggregate `{}`", name)),
+                    }
+                }
+                _ => None,
+            };
+            if agg.is_none() {
+                return Ok((p.term()?, None));
+            }
+            p.pos += 2;
+            let term = p.term()?;
+            p.expect_punct(")")?;
+            Ok((term, agg))
+        })?;
+        self.expect_punct(")")?;
+        Ok((pred_name, slots))
+    }
+
+    fn bind(&mut self, scope: &mut Scope) -> Result<BindExpr> {
+        if self.eat_punct("[") {
+            let inner = self.bind(scope)?;
+            self.expect_punct("]")?;
+            return Ok(BindExpr::Iterate(Box::new(inner)));
+        }
+        if self.eat_punct("(") {
+            let mut elems = self.list(|p| p.bind(scope))?;
+            self.expect_punct(")")?;
+            if elems.len() == 1 {
+                return Ok(elems.remove(0));
+            }
+            return Ok(BindExpr::Destructure(elems));
+        }
+        let term = self.term()?;
+        Ok(BindExpr::Normal(scope.term(&term)))
+    }
+
+    fn expr(&mut self, scope: &mut Scope) -> Result<Expr> {
+        let is_app = match self.peek().tok {
+            Tok::Ident(_) => self.peek_at(1).tok == Tok::Punct("("),
+            _ => false,
+        };
+        if is_app {
+            let func = self.ident("a function name")?;
+            self.pos += 1;
+            let args = if self.at_punct(")") {
+                Vec::new()
+            } else {
+                self.list(|p| p.expr(scope))?
+            };
+            self.expect_punct(")")?;
+            return Ok(Expr::App(func, args));
+        }
+        match self.term()? {
+            Term::Var(name) => Ok(Expr::Var(scope.var(&name))),
+            Term::Const(val) => Ok(Expr::Val(val)),
+            Term::Unbound => self.fail("`_` may not be used in an expression".to_string()),
+        }
+    }
+
+    fn rule(&mut self) -> Result<Rule> {
+        let name = self.ident("a rule name")?;
+        self.pos += 1;
+        let heads = self.list(|p| p.head())?;
+        let backward = if self.eat_punct("<-") {
+            true
+        } else {
+            self.expect_punct("<=")?;
+            false
+        };
+        // Number variables as the `rule!` macro does: bodies first, then
+        // where clauses, then heads
+        let mut scope = Scope::new();
+        let mut bodies = vec![self.body(&mut scope)?];
+        while self.eat_punct("|") {
+            bodies.push(self.body(&mut scope)?);
+        }
+        let mut wheres = Vec::new();
+        if self.at_keyword(0, "where") {
+            self.pos += 1;
+            wheres = self.list(|p| {
+                let lhs = p.bind(&mut scope)?;
+                p.expect_punct("=")?;
+                Ok(WhereClause {
+                    lhs,
+                    rhs: p.expr(&mut scope)?,
+                })
+            })?;
+        }
+        self.expect_punct(".")?;
+        let mut head = Vec::new();
+        let mut aggregates = Vec::new();
+        for (pred_name, slots) in heads {
+            let mut args = Vec::new();
+            for (slot, (term, agg)) in slots.into_iter().enumerate() {
+                args.push(scope.term(&term));
+                if let Some(agg) = agg {
+                    aggregates.push((slot, agg));
+                }
+            }
+            head.push(Clause {
+                pred_name,
+                args,
+                negated: false,
+            });
+        }
+        Ok(Rule {
+            name,
+            head,
+            body: bodies.remove(0),
+            alternatives: bodies,
+            wheres,
+            aggregates,
+            backward,
+        })
+    }
+}
+
+// Words which can't be used as names of predicates, functions or variables
+fn is_reserved(word: &str) -> bool {
+    matches!(word, "_" | "true" | "false" | "not" | "where")
+}
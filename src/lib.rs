@@ -4,7 +4,6 @@
 //! together.
 //!
 #![warn(missing_docs)]
-#[macro_use]
 extern crate lazy_static;
 extern crate sha2;
 extern crate postgres;
@@ -21,11 +20,23 @@ extern crate futures;
 
 extern crate env_logger;
 extern crate url;
+#[cfg(feature = "sqlite")]
+extern crate rusqlite;
 
+pub mod fact_db;
 pub mod pg;
+pub mod mem;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+mod sql;
 pub mod engine;
 pub mod edsl;
+pub mod lang;
 pub mod simple;
 
 pub use engine::{Engine, Error, ErrorKind, Result};
+pub use fact_db::FactDB;
+pub use mem::MemDB;
 pub use pg::PgDB;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteDB;
@@ -0,0 +1,506 @@
+//! In-Memory Fact Database
+//!
+//! `MemDB` keeps its facts in ordinary Rust collections, so nothing persists
+//! past the lifetime of the object. It needs no server, making it a good fit
+//! for tests and quick experiments.
+//!
+//! Each slot of each predicate is indexed by value, and searches are answered
+//! with a nested loop join which looks up candidate facts through the most
+//! selective index available at each clause. Incremental searches are
+//! evaluated semi-naively, joining the new facts for each clause in turn.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::ops::Range;
+
+use engine::types::{Clause, Fact, MatchExpr, Predicate};
+use fact_db::{ErrorKind, FactDB, FactId, Result};
+use pg::dyn::types;
+use pg::dyn::{Type, Value};
+
+// Facts for a single predicate
+struct Table {
+    // Fact arguments, by id
+    rows: BTreeMap<FactId, Vec<Value>>,
+    // For each slot, the ids of the facts holding each value
+    indexes: Vec<HashMap<Value, BTreeSet<FactId>>>,
+    // Slots which must be unique together, i.e. the non-lattice ones
+    key: Vec<usize>,
+    // Fact id for each key in use
+    unique: HashMap<Vec<Value>, FactId>,
+}
+
+impl Table {
+    fn new(pred: &Predicate) -> Self {
+        Table {
+            rows: BTreeMap::new(),
+            indexes: pred.fields.iter().map(|_| HashMap::new()).collect(),
+            key: (0..pred.fields.len())
+                .filter(|slot| pred.fields[*slot].join.is_none())
+                .collect(),
+            unique: HashMap::new(),
+        }
+    }
+
+    fn key_of(&self, args: &[Value]) -> Vec<Value> {
+        self.key.iter().map(|slot| args[*slot].clone()).collect()
+    }
+
+    fn insert(&mut self, id: FactId, args: Vec<Value>) {
+        for (slot, arg) in args.iter().enumerate() {
+            self.indexes[slot]
+                .entry(arg.clone())
+                .or_default()
+                .insert(id);
+        }
+        self.unique.insert(self.key_of(&args), id);
+        self.rows.insert(id, args);
+    }
+
+    fn remove(&mut self, id: FactId) -> bool {
+        let args = match self.rows.remove(&id) {
+            Some(args) => args,
+            None => return false,
+        };
+        for (slot, arg) in args.iter().enumerate() {
+            let emptied = match self.indexes[slot].get_mut(arg) {
+                Some(ids) => {
+                    ids.remove(&id);
+                    ids.is_empty()
+                }
+                None => false,
+            };
+            if emptied {
+                self.indexes[slot].remove(arg);
+            }
+        }
+        let key = self.key_of(&args);
+        self.unique.remove(&key);
+        true
+    }
+
+    // Ids in `range` of the facts which could match `args` under the given
+    // bindings, using the smallest index which applies
+    fn candidates(
+        &self,
+        args: &[MatchExpr],
+        binding: &[Value],
+        range: &Range<FactId>,
+    ) -> Vec<FactId> {
+        let mut best: Option<&BTreeSet<FactId>> = None;
+        for (slot, arg) in args.iter().enumerate() {
+            let val = match *arg {
+                MatchExpr::Const(ref val) => val,
+                MatchExpr::Var(var) if var < binding.len() => &binding[var],
+                _ => continue,
+            };
+            match self.indexes[slot].get(val) {
+                Some(ids) => {
+                    if best.map(|best| ids.len() < best.len()).unwrap_or(true) {
+                        best = Some(ids)
+                    }
+                }
+                None => return Vec::new(),
+            }
+        }
+        match best {
+            Some(ids) => ids.range(range.clone()).cloned().collect(),
+            None => self.rows.range(range.clone()).map(|(id, _)| *id).collect(),
+        }
+    }
+}
+
+fn all_ids() -> Range<FactId> {
+    FactId::MIN..FactId::MAX
+}
+
+// Matches a fact's arguments against a clause, extending the binding.
+// Variables are numbered by first appearance, so any variable not yet bound
+// is the next one.
+fn unify(args: &[MatchExpr], row: &[Value], binding: &mut Vec<Value>) -> bool {
+    for (arg, val) in args.iter().zip(row.iter()) {
+        match *arg {
+            MatchExpr::Unbound => (),
+            MatchExpr::Const(ref expected) => {
+                if expected != val {
+                    return false;
+                }
+            }
+            MatchExpr::Var(var) => {
+                if var < binding.len() {
+                    if &binding[var] != val {
+                        return false;
+                    }
+                } else {
+                    binding.push(val.clone());
+                }
+            }
+        }
+    }
+    true
+}
+
+/// Object representing an in-memory fact database instance
+pub struct MemDB {
+    named_types: RefCell<HashMap<String, Type>>,
+    pred_by_name: RefCell<HashMap<String, Predicate>>,
+    tables: RefCell<HashMap<String, Table>>,
+    #[allow(clippy::type_complexity)]
+    provenance: RefCell<HashMap<FactId, (String, Vec<(String, FactId)>)>>,
+    cursors: RefCell<HashMap<(String, String), FactId>>,
+    next_id: Cell<FactId>,
+}
+
+impl Default for MemDB {
+    fn default() -> Self {
+        MemDB::new()
+    }
+}
+
+impl MemDB {
+    /// Create a new, empty, in-memory database
+    pub fn new() -> MemDB {
+        MemDB {
+            named_types: RefCell::new(
+                types::default_types()
+                    .iter()
+                    .filter_map(|type_| {
+                        type_.name().map(|name| (name.to_owned(), type_.clone()))
+                    })
+                    .collect(),
+            ),
+            pred_by_name: RefCell::new(HashMap::new()),
+            tables: RefCell::new(HashMap::new()),
+            provenance: RefCell::new(HashMap::new()),
+            cursors: RefCell::new(HashMap::new()),
+            next_id: Cell::new(1),
+        }
+    }
+
+    // Applies the same checks as `PgDB` to a query, so both backends accept
+    // the same programs
+    fn check_query(&self, query: &[Clause]) -> Result<()> {
+        if query.is_empty() {
+            bail!(ErrorKind::Arg("Empty search query".to_string()));
+        }
+        if query.iter().all(|clause| clause.negated) {
+            bail!(ErrorKind::Arg(
+                "Search query has no positive clauses".to_string(),
+            ));
+        }
+        let mut var_type: Vec<Type> = Vec::new();
+        let positive = query.iter().filter(|clause| !clause.negated);
+        let negative = query.iter().filter(|clause| clause.negated);
+        for clause in positive.chain(negative) {
+            let pred = match self.get_predicate(&clause.pred_name) {
+                Some(pred) => pred,
+                None => {
+                    bail!(ErrorKind::Arg(format!(
+                        "{} is not a registered predicate.",
+                        clause.pred_name
+                    )))
+                }
+            };
+            if clause.args.len() != pred.fields.len() {
+                bail!(ErrorKind::Arg(format!(
+                    "{} takes {} arguments, but was given {}",
+                    clause.pred_name,
+                    pred.fields.len(),
+                    clause.args.len()
+                )));
+            }
+            for (n, binding) in clause.args.iter().enumerate() {
+                if let MatchExpr::Var(v) = *binding {
+                    let type_ = pred.fields[n].type_.clone();
+                    if clause.negated && v >= var_type.len() {
+                        bail!(ErrorKind::Arg(format!(
+                            "Variable {} is only bound under negation in {}",
+                            v,
+                            clause.pred_name
+                        )));
+                    } else if v == var_type.len() {
+                        var_type.push(type_)
+                    } else if v > var_type.len() {
+                        bail!(ErrorKind::Arg(
+                            format!("Hole before {} in variable numbering.", v),
+                        ));
+                    } else if *var_type[v] != *type_ {
+                        bail!(ErrorKind::Arg(format!(
+                            "Variable {} attempt to unify incompatible types {:?} and {:?}",
+                            v,
+                            var_type[v],
+                            type_
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Extends a partial answer over the remaining positive clauses
+    #[allow(clippy::too_many_arguments)]
+    fn search_from(
+        &self,
+        tables: &HashMap<String, Table>,
+        positive: &[&Clause],
+        ranges: &[Range<FactId>],
+        negative: &[&Clause],
+        ids: &mut Vec<FactId>,
+        binding: &mut Vec<Value>,
+        out: &mut Vec<(Vec<FactId>, Vec<Value>)>,
+    ) {
+        let (clause, rest) = match positive.split_first() {
+            Some(split) => split,
+            None => {
+                let excluded = negative.iter().any(|clause| {
+                    let table = &tables[&clause.pred_name];
+                    table.candidates(&clause.args, binding, &all_ids()).iter().any(|id| {
+                        unify(&clause.args, &table.rows[id], &mut binding.clone())
+                    })
+                });
+                if !excluded {
+                    out.push((ids.clone(), binding.clone()));
+                }
+                return;
+            }
+        };
+        let table = &tables[&clause.pred_name];
+        for id in table.candidates(&clause.args, binding, &ranges[0]) {
+            let bound = binding.len();
+            if unify(&clause.args, &table.rows[&id], binding) {
+                ids.push(id);
+                self.search_from(tables, rest, &ranges[1..], negative, ids, binding, out);
+                ids.pop();
+            }
+            binding.truncate(bound);
+        }
+    }
+}
+
+impl FactDB for MemDB {
+    fn add_type(&self, type_: Type) -> Result<()> {
+        let name = type_.name().ok_or(ErrorKind::Arg(
+            "Tried to add a type with no name".to_string(),
+        ))?;
+        if self.named_types.borrow().contains_key(name) {
+            bail!(ErrorKind::Type(format!("{} already registered", name)))
+        }
+        self.named_types.borrow_mut().insert(name.to_owned(), type_.clone());
+        Ok(())
+    }
+
+    fn get_type(&self, type_str: &str) -> Option<Type> {
+        if type_str.starts_with('[') && type_str.ends_with(']') {
+            return self.get_type(&type_str[1..type_str.len() - 1]).map(|elem| {
+                types::List::new(elem) as Type
+            });
+        }
+        self.named_types.borrow().get(type_str).cloned()
+    }
+
+    fn new_predicate(&self, pred: &Predicate) -> Result<()> {
+        if let Some(existing) = self.pred_by_name.borrow().get(&pred.name) {
+            if existing != pred {
+                bail!(ErrorKind::Arg(format!(
+                    "Predicate {} already registered at a different type.\n\
+                     Existing: {:?}\nNew: {:?}",
+                    &pred.name,
+                    existing,
+                    pred
+                )));
+            }
+            return Ok(());
+        }
+        self.tables.borrow_mut().insert(
+            pred.name.clone(),
+            Table::new(pred),
+        );
+        self.pred_by_name.borrow_mut().insert(
+            pred.name.clone(),
+            pred.clone(),
+        );
+        Ok(())
+    }
+
+    fn get_predicate(&self, pred_name: &str) -> Option<Predicate> {
+        self.pred_by_name.borrow().get(pred_name).cloned()
+    }
+
+    fn list_predicates(&self) -> Vec<Predicate> {
+        let mut preds: Vec<Predicate> = self.pred_by_name.borrow().values().cloned().collect();
+        preds.sort_by(|a, b| a.name.cmp(&b.name));
+        preds
+    }
+
+    fn insert_fact(&self, fact: &Fact) -> Result<Option<FactId>> {
+        let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        if fact.args.len() != pred.fields.len() {
+            bail!(ErrorKind::Arg(format!(
+                "{} takes {} arguments, but was given {}",
+                fact.pred_name,
+                pred.fields.len(),
+                fact.args.len()
+            )));
+        }
+        for (arg, field) in fact.args.iter().zip(pred.fields.iter()) {
+            if *arg.type_() != *field.type_ {
+                bail!(ErrorKind::Type(format!(
+                    "{:?} is not a {:?} in {}",
+                    arg,
+                    field.type_,
+                    fact.pred_name
+                )));
+            }
+        }
+        let mut tables = self.tables.borrow_mut();
+        let table = tables.get_mut(&fact.pred_name).unwrap();
+        if table.unique.contains_key(&table.key_of(&fact.args)) {
+            return Ok(None);
+        }
+        let id = self.next_id.get();
+        self.next_id.set(id + 1);
+        table.insert(id, fact.args.clone());
+        Ok(Some(id))
+    }
+
+    fn search_facts(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> Result<Vec<(Vec<FactId>, Vec<Value>)>> {
+        self.check_query(query)?;
+        let positive: Vec<&Clause> = query.iter().filter(|clause| !clause.negated).collect();
+        let negative: Vec<&Clause> = query.iter().filter(|clause| clause.negated).collect();
+        let tables = self.tables.borrow();
+        let all = all_ids();
+        // Id ranges for each positive clause to search with. Incrementally,
+        // there is a search for each clause with new facts, in which it only
+        // matches those, and the clauses before it only match old facts.
+        let searches: Vec<Vec<Range<FactId>>> = match min_fact_id {
+            None => vec![vec![all.clone(); positive.len()]],
+            Some(min) => {
+                (0..positive.len())
+                    .filter(|n| {
+                        tables[&positive[*n].pred_name].rows.range(min..).next().is_some()
+                    })
+                    .map(|n| {
+                        (0..positive.len())
+                            .map(|m| if m < n {
+                                all.start..min
+                            } else if m == n {
+                                min..all.end
+                            } else {
+                                all.clone()
+                            })
+                            .collect()
+                    })
+                    .collect()
+            }
+        };
+        let mut out = Vec::new();
+        for ranges in searches.iter() {
+            self.search_from(
+                &tables,
+                &positive,
+                ranges,
+                &negative,
+                &mut Vec::new(),
+                &mut Vec::new(),
+                &mut out,
+            );
+        }
+        Ok(out)
+    }
+
+    fn delete_fact(&self, pred_name: &str, id: FactId) -> Result<bool> {
+        let deleted = match self.tables.borrow_mut().get_mut(pred_name) {
+            Some(table) => table.remove(id),
+            None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+        };
+        self.provenance.borrow_mut().remove(&id);
+        Ok(deleted)
+    }
+
+    fn get_fact(&self, pred_name: &str, id: FactId) -> Result<Option<Fact>> {
+        match self.tables.borrow().get(pred_name) {
+            Some(table) => Ok(table.rows.get(&id).map(|args| {
+                Fact {
+                    pred_name: pred_name.to_string(),
+                    args: args.clone(),
+                }
+            })),
+            None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+        }
+    }
+
+    fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> Result<()> {
+        self.provenance.borrow_mut().entry(fact_id).or_insert_with(|| {
+            (rule.to_string(), premises.to_vec())
+        });
+        Ok(())
+    }
+
+    fn get_provenance(&self, fact_id: FactId) -> Result<Option<(String, Vec<(String, FactId)>)>> {
+        Ok(self.provenance.borrow().get(&fact_id).cloned())
+    }
+
+    fn get_cursor(&self, rule: &str, hash: &str) -> Result<Option<FactId>> {
+        Ok(self.cursors.borrow().get(&(rule.to_string(), hash.to_string())).cloned())
+    }
+
+    fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> Result<()> {
+        let key = (rule.to_string(), hash.to_string());
+        match cursor {
+            Some(cursor) => self.cursors.borrow_mut().insert(key, cursor),
+            None => self.cursors.borrow_mut().remove(&key),
+        };
+        Ok(())
+    }
+
+    fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> Result<()> {
+        let mut tables = self.tables.borrow_mut();
+        let (table, dropped) = {
+            let old = match tables.get(&pred.name) {
+                Some(table) => table,
+                None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+            };
+            let mut table = Table::new(pred);
+            let mut dropped = Vec::new();
+            for (id, args) in old.rows.iter() {
+                let args = match migrate(args) {
+                    Some(args) => args,
+                    None => bail!(ErrorKind::Arg(
+                        format!("Fact {} of {} cannot be migrated", id, pred.name),
+                    )),
+                };
+                if table.unique.contains_key(&table.key_of(&args)) {
+                    dropped.push(*id);
+                } else {
+                    table.insert(*id, args);
+                }
+            }
+            (table, dropped)
+        };
+        tables.insert(pred.name.clone(), table);
+        for id in dropped {
+            self.provenance.borrow_mut().remove(&id);
+        }
+        self.pred_by_name.borrow_mut().insert(
+            pred.name.clone(),
+            pred.clone(),
+        );
+        Ok(())
+    }
+}
@@ -32,11 +32,11 @@ use std::sync::Arc;
 pub trait HashTO {
     /// `hash_to` captures the same functionality as `Hash`'s `hash()`, but in
     /// a trait object safe way.
-    fn hash_to(&self, &mut Hasher);
+    fn hash_to(&self, _: &mut dyn Hasher);
 }
 
 struct HashProxy<'a> {
-    pub hasher: &'a mut Hasher,
+    pub hasher: &'a mut dyn Hasher,
 }
 
 impl<'a> Hasher for HashProxy<'a> {
@@ -49,15 +49,25 @@ impl<'a> Hasher for HashProxy<'a> {
 }
 
 impl<T: Hash> HashTO for T {
-    fn hash_to(&self, h: &mut Hasher) {
+    fn hash_to(&self, h: &mut dyn Hasher) {
         self.hash(&mut HashProxy { hasher: h })
     }
 }
 
 /// Represents the type of a dynamic value as a threadsafe trait object.
-pub type Type = Arc<self::types::TypeT>;
+pub type Type = Arc<dyn self::types::TypeT>;
 /// Represents a dynamic value as a threadsafe trait object.
-pub type Value = Arc<self::values::ValueT>;
+pub type Value = Arc<dyn self::values::ValueT>;
+
+/// The kinds of database a value may be stored in. Types describe their
+/// storage for each of these via `TypeT::repr_on`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Backend {
+    /// PostgreSQL, via `PgDB`
+    Postgres,
+    /// SQLite, via `SqliteDB`
+    Sqlite,
+}
 
 pub mod types {
     //! This module defines the trait new types must implement, along with
@@ -70,22 +80,25 @@ pub mod types {
     use std::sync::Arc;
     use std::hash::{Hash, Hasher};
     use super::HashTO;
+    use super::Backend;
     use super::Type;
     use super::Value;
 
+    /// Implements `TypeT::inner` for a type
     #[macro_export]
     macro_rules! typet_inner {
       () => {
-          fn inner(&self) -> &::std::any::Any {
-              self as &::std::any::Any
+          fn inner(&self) -> &dyn (::std::any::Any) {
+              self as &dyn (::std::any::Any)
           }
       }
   }
 
+    /// Implements `TypeT::inner_eq` by downcasting and comparing with `==`
     #[macro_export]
     macro_rules! typet_inner_eq {
       () => {
-          fn inner_eq(&self, other : &TypeT) -> bool {
+          fn inner_eq(&self, other : &dyn TypeT) -> bool {
               let other_self = match other.inner().downcast_ref() {
                   Some(x) => x,
                   None => return false
@@ -95,6 +108,7 @@ pub mod types {
       }
   }
 
+    /// Implements the common `TypeT` methods for a type stored inline
     #[macro_export]
     macro_rules! typet_boiler {
       () => {
@@ -117,41 +131,67 @@ pub mod types {
         fn name(&self) -> Option<&'static str>;
         /// Takes in an iterator over the row, then attempts to read a value of the
         /// type specified.
-        fn extract(&self, &mut RowIter) -> Option<Value>;
+        fn extract(&self, _: &mut RowIter) -> Option<Value>;
         /// Generates the database representation of the field required.
         fn repr(&self) -> &'static str;
+        /// Generates the representation of the field for a particular kind of
+        /// database. By default, the SQLite representation is the storage
+        /// class corresponding to the Postgres one.
+        fn repr_on(&self, backend: Backend) -> &'static str {
+            match backend {
+                Backend::Postgres => self.repr(),
+                Backend::Sqlite => sqlite_repr(self.repr()),
+            }
+        }
         /// Returns a dynamic representation of the trait object.
         ///
         /// Trait objects cannot be cast to other trait objects, even if they
         /// implement the other trait necessarily. In order to access functions
         /// under the `Any` trait, I have the non-trait-object implementation
         /// provide access to its &Any representation.
-        fn inner(&self) -> &Any;
+        fn inner(&self) -> &dyn Any;
         /// Check equality
         ///
         /// Similar to `inner`, `inner_eq` exports a `PartialEq` instance from
         /// the underlying type.
-        fn inner_eq(&self, other: &TypeT) -> bool;
+        fn inner_eq(&self, other: &dyn TypeT) -> bool;
         /// List of subindexes to be ignored when checking uniqueness.
         /// Intended to be used to ignore large payloads in favor of hashes
         fn large(&self) -> bool;
     }
 
-    impl Hash for TypeT {
+    // SQLite storage class for a Postgres column type. Lists are encoded as
+    // text, and anything unfamiliar is kept as an uninterpreted blob.
+    fn sqlite_repr(pg_repr: &str) -> &'static str {
+        if pg_repr.ends_with("[]") {
+            return "text";
+        }
+        match pg_repr {
+            "int8" | "bool" => "integer",
+            "varchar" | "char(64)" => "text",
+            _ => "blob",
+        }
+    }
+
+    impl Hash for dyn TypeT {
         fn hash<H: Hasher>(&self, hasher: &mut H) {
             self.hash_to(hasher)
         }
     }
 
-    impl fmt::Debug for TypeT {
+    impl fmt::Debug for dyn TypeT {
         fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            // Tuples have no database representation, so show their elements
+            if let Some(tuple) = self.inner().downcast_ref::<Tuple>() {
+                return write!(f, "[Tuple: {:?}]", tuple.elements());
+            }
             write!(f, "[Name: {:?}, Repr: {:?}]", self.name(), self.repr())
         }
     }
 
-    impl Eq for TypeT {}
-    impl PartialEq for TypeT {
-        fn eq(&self, t: &TypeT) -> bool {
+    impl Eq for dyn TypeT {}
+    impl PartialEq for dyn TypeT {
+        fn eq(&self, t: &dyn TypeT) -> bool {
             self.inner_eq(t)
         }
     }
@@ -189,9 +229,14 @@ pub mod types {
 
     impl Tuple {
         /// Construct a new tuple from a vector of other types
+        #[allow(clippy::arc_with_non_send_sync)]
         pub fn new(elems: Vec<Type>) -> Arc<Self> {
             Arc::new(Tuple { elements: elems })
         }
+        /// The types of the tuple's elements, in order
+        pub fn elements(&self) -> &[Type] {
+            &self.elements
+        }
     }
 
     impl TypeT for Tuple {
@@ -208,16 +253,37 @@ pub mod types {
     }
 
     /// A list of another `Type`
-    /// This type is anonymous.
+    /// This type is anonymous, but lists of the builtin types may still be
+    /// stored, and are recorded under the name `[elem]`.
     #[derive(Debug, Clone, Hash)]
+    #[allow(clippy::derived_hash_with_manual_eq)]
     pub struct List {
         elem: Type,
     }
 
     impl List {
         /// Constructs a list type of the provided element type
+        #[allow(clippy::arc_with_non_send_sync)]
         pub fn new(elem: Type) -> Arc<Self> {
-            Arc::new(List { elem: elem })
+            Arc::new(List { elem })
+        }
+        /// The type of the list's elements
+        pub fn elem(&self) -> &Type {
+            &self.elem
+        }
+    }
+
+    /// Provides the name a type is persisted under.
+    /// Lists of named types are named by wrapping their element's name in
+    /// brackets, other anonymous types have no name.
+    pub fn name_of(type_: &dyn TypeT) -> Option<::std::string::String> {
+        match type_.name() {
+            Some(name) => Some(name.to_string()),
+            None => {
+                type_.inner().downcast_ref::<List>().and_then(|list| {
+                    name_of(&*list.elem).map(|elem| format!("[{}]", elem))
+                })
+            }
         }
     }
 
@@ -229,15 +295,62 @@ pub mod types {
     }
 
     impl TypeT for List {
-        typet_boiler!();
+        typet_inner!();
+        typet_inner_eq!();
+        fn large(&self) -> bool {
+            self.elem.large()
+        }
         fn name(&self) -> Option<&'static str> {
             None
         }
-        fn extract(&self, _rows: &mut RowIter) -> Option<Value> {
-            panic!("Cannot extract a list")
+        fn extract(&self, rows: &mut RowIter) -> Option<Value> {
+            let elems: Option<Vec<Value>> = match self.elem.name() {
+                Some("uint64") => {
+                    rows.next().map(|v: Vec<i64>| {
+                        v.into_iter()
+                            .map(|x| values::UInt64::new(x as u64) as Value)
+                            .collect()
+                    })
+                }
+                Some("string") | Some("largestring") => {
+                    rows.next().map(|v: Vec<::std::string::String>| {
+                        v.into_iter()
+                            .map(|x| values::String::new(x) as Value)
+                            .collect()
+                    })
+                }
+                Some("bytes") => {
+                    rows.next().map(|v: Vec<Vec<u8>>| {
+                        v.into_iter()
+                            .map(|x| values::Bytes::new(x) as Value)
+                            .collect()
+                    })
+                }
+                Some("bool") => {
+                    rows.next().map(|v: Vec<bool>| {
+                        v.into_iter().map(|x| values::Bool::new(x) as Value).collect()
+                    })
+                }
+                Some("largebytes") => {
+                    rows.next().map(|v: Vec<::std::string::String>| {
+                        v.into_iter()
+                            .map(|x| values::LargeBytes::from_hash(&x) as Value)
+                            .collect()
+                    })
+                }
+                _ => panic!("Cannot extract a list of {:?}", self.elem),
+            };
+            elems.map(|elems| values::List::of(self.elem.clone(), elems) as Value)
         }
         fn repr(&self) -> &'static str {
-            panic!("Cannot represent a list")
+            match self.elem.repr() {
+                "int8" => "int8[]",
+                "varchar" => "varchar[]",
+                "bytea" => "bytea[]",
+                "bool" => "bool[]",
+                "char(64)" => "char(64)[]",
+                _ => panic!("Cannot represent a list of {:?}", self.elem),
+            }
         }
     }
 
@@ -307,6 +420,7 @@ pub mod types {
         }
     }
 
+    /// `LargeString` is text with no length limit, kept out of indexes
     #[derive(Debug, Clone, Hash, PartialEq)]
     pub struct LargeString;
 
@@ -384,20 +498,21 @@ pub mod values {
     use super::types;
     use std::cmp::Ordering;
 
+    /// Implements the common `ValueT` methods for a value stored inline
     #[macro_export]
     macro_rules! valuet_boiler {
       () => {
-        fn inner(&self) -> &::std::any::Any {
-          self as &::std::any::Any
+        fn inner(&self) -> &dyn (::std::any::Any) {
+          self as &dyn (::std::any::Any)
         }
-        fn inner_eq(&self, other : &ValueT) -> bool {
+        fn inner_eq(&self, other : &dyn ValueT) -> bool {
           let other_typed = match other.inner().downcast_ref::<Self>() {
             Some(x) => x,
             None => return false
           };
           self == other_typed
         }
-        fn inner_ord(&self, other : &ValueT) -> Option<::std::cmp::Ordering> {
+        fn inner_ord(&self, other : &dyn ValueT) -> Option<::std::cmp::Ordering> {
           other.inner().downcast_ref::<Self>().and_then(|other_typed|{
             self.partial_cmp(other_typed)
           })
@@ -415,30 +530,30 @@ pub mod values {
         ///
         /// Since Holmes is a typed language, the user of this function should
         /// know what to expect and be able to cast from Any into it.
-        fn get(&self) -> &Any;
+        fn get(&self) -> &dyn Any;
         /// Converts the value into a list of ToSql trait objects to allow for
         /// insertion into the database via a prepared query
-        fn to_sql(&self) -> Vec<&ToSql>;
+        fn to_sql(&self) -> Vec<&dyn ToSql>;
         /// Returns a dynamic representation of the trait object.
         ///
         /// Trait objects cannot be cast to other trait objects, even if they
         /// implement the other trait necessarily. In order to access functions
         /// under the `Any` trait, I have the non-trait-object implementation
         /// provide access to its &Any representation.
-        fn inner(&self) -> &Any;
+        fn inner(&self) -> &dyn Any;
         /// Check equality
         ///
         /// Similar to `inner`, `inner_eq` exports a `PartialEq` instance from
         /// the underlying type.
-        fn inner_eq(&self, other: &ValueT) -> bool;
+        fn inner_eq(&self, other: &dyn ValueT) -> bool;
         /// Check order
         ///
         /// Similar to `inner`, `inner_ord` exports an `Ord` instance form
         /// the underlying type
-        fn inner_ord(&self, &ValueT) -> Option<Ordering>;
+        fn inner_ord(&self, _: &dyn ValueT) -> Option<Ordering>;
     }
 
-    impl Hash for ValueT {
+    impl Hash for dyn ValueT {
         fn hash<H: Hasher>(&self, hasher: &mut H) {
             self.hash_to(hasher)
         }
@@ -453,29 +568,120 @@ pub mod values {
         fn to_value(self) -> Value;
     }
 
-    impl Eq for ValueT {}
-    impl PartialEq for ValueT {
-        fn eq(&self, other: &ValueT) -> bool {
+    impl Eq for dyn ValueT {}
+    impl PartialEq for dyn ValueT {
+        fn eq(&self, other: &dyn ValueT) -> bool {
             self.inner_eq(other)
         }
     }
 
-    impl Ord for ValueT {
-        fn cmp(&self, other: &ValueT) -> Ordering {
+    impl Ord for dyn ValueT {
+        fn cmp(&self, other: &dyn ValueT) -> Ordering {
             self.inner_ord(other).unwrap()
         }
     }
 
-    impl PartialOrd for ValueT {
-        fn partial_cmp(&self, other: &ValueT) -> Option<Ordering> {
+    // Values of different types are unordered, where `cmp` would panic
+    #[allow(clippy::non_canonical_partial_ord_impl)]
+    impl PartialOrd for dyn ValueT {
+        fn partial_cmp(&self, other: &dyn ValueT) -> Option<Ordering> {
             self.inner_ord(other)
         }
     }
 
     /// A list of samely typed values.
-    #[derive(Debug, Clone, PartialEq, Hash, PartialOrd, Eq)]
+    #[derive(Debug, Clone, Hash, Eq)]
+    #[allow(clippy::derived_hash_with_manual_eq)]
     pub struct List {
+        elem: Type,
         elements: Vec<Value>,
+        sql: ListSql,
+    }
+
+    // Manually implement PartialEq to work around rustc #[derive(PartialEq)] bug
+    // https://github.com/rust-lang/rust/issues/39128
+    impl PartialEq for List {
+        fn eq(&self, other: &List) -> bool {
+            *self.elem == *other.elem && self.elements == other.elements
+        }
+    }
+
+    // Types aren't ordered, so lists of different types are incomparable
+    impl PartialOrd for List {
+        fn partial_cmp(&self, other: &List) -> Option<Ordering> {
+            if *self.elem != *other.elem {
+                return None;
+            }
+            self.elements.partial_cmp(&other.elements)
+        }
+    }
+
+    // Postgres array form of a list, kept alongside the elements so that
+    // `to_sql` has something to borrow from.
+    #[derive(Debug, Clone, PartialEq, Hash, PartialOrd, Eq)]
+    enum ListSql {
+        Unsupported,
+        Int(Vec<i64>),
+        Text(Vec<::std::string::String>),
+        Bytes(Vec<Vec<u8>>),
+        Bool(Vec<bool>),
+    }
+
+    fn list_sql(elem: &Type, elements: &[Value]) -> ListSql {
+        match elem.name() {
+            Some("uint64") => {
+                ListSql::Int(
+                    elements
+                        .iter()
+                        .map(|elem| *elem.get().downcast_ref::<u64>().unwrap() as i64)
+                        .collect(),
+                )
+            }
+            Some("string") | Some("largestring") => {
+                ListSql::Text(
+                    elements
+                        .iter()
+                        .map(|elem| {
+                            elem.get()
+                                .downcast_ref::<::std::string::String>()
+                                .unwrap()
+                                .clone()
+                        })
+                        .collect(),
+                )
+            }
+            Some("bytes") => {
+                ListSql::Bytes(
+                    elements
+                        .iter()
+                        .map(|elem| elem.get().downcast_ref::<Vec<u8>>().unwrap().clone())
+                        .collect(),
+                )
+            }
+            Some("bool") => {
+                ListSql::Bool(
+                    elements
+                        .iter()
+                        .map(|elem| *elem.get().downcast_ref::<bool>().unwrap())
+                        .collect(),
+                )
+            }
+            Some("largebytes") => {
+                ListSql::Text(
+                    elements
+                        .iter()
+                        .map(|elem| {
+                            elem.inner()
+                                .downcast_ref::<LargeBytes>()
+                                .unwrap()
+                                .hash
+                                .clone()
+                        })
+                        .collect(),
+                )
+            }
+            _ => ListSql::Unsupported,
+        }
     }
 
     impl fmt::Display for List {
@@ -495,25 +701,46 @@ pub mod values {
 
     impl ValueT for List {
         fn type_(&self) -> Type {
-            match self.elements.first() {
-                Some(e) => types::List::new(e.type_()),
-                // TODO have some kind of poly type to default to? Equal to everything?
-                None => types::List::new(Arc::new(types::UInt64)),
+            types::List::new(self.elem.clone())
+        }
+        fn get(&self) -> &dyn Any {
+            &self.elements as &dyn Any
+        }
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
+            match self.sql {
+                ListSql::Int(ref v) => vec![v as &dyn ToSql],
+                ListSql::Text(ref v) => vec![v as &dyn ToSql],
+                ListSql::Bytes(ref v) => vec![v as &dyn ToSql],
+                ListSql::Bool(ref v) => vec![v as &dyn ToSql],
+                ListSql::Unsupported => panic!("List SQL disabled for {:?}", self.type_()),
             }
         }
-        fn get(&self) -> &Any {
-            &self.elements as &Any
-        }
-        fn to_sql(&self) -> Vec<&ToSql> {
-            panic!("List SQL disabled")
-        }
         valuet_boiler!();
     }
 
     impl List {
-        /// Create a dynamic `List` value from a list of `Value`s
+        /// Create a dynamic `List` value from a list of `Value`s, taking the
+        /// element type from the first of them. An empty list is taken to be
+        /// of `uint64`s, use `of` where the element type is known.
         pub fn new(elements: Vec<Value>) -> Arc<Self> {
-            Arc::new(List { elements: elements })
+            let elem = match elements.first() {
+                Some(e) => e.type_(),
+                // TODO have some kind of poly type to default to? Equal to everything?
+                None => Arc::new(types::UInt64),
+            };
+            List::of(elem, elements)
+        }
+
+        /// Create a dynamic `List` value with the given element type from a
+        /// list of `Value`s of that type
+        #[allow(clippy::arc_with_non_send_sync)]
+        pub fn of(elem: Type, elements: Vec<Value>) -> Arc<Self> {
+            let sql = list_sql(&elem, &elements);
+            Arc::new(List {
+                elem,
+                elements,
+                sql,
+            })
         }
     }
 
@@ -544,10 +771,10 @@ pub mod values {
         fn type_(&self) -> Type {
             types::Tuple::new(self.elements.iter().map(|val| val.type_()).collect())
         }
-        fn get(&self) -> &Any {
-            &self.elements as &Any
+        fn get(&self) -> &dyn Any {
+            &self.elements as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
             self.elements.iter().flat_map(|val| val.to_sql()).collect()
         }
         valuet_boiler!();
@@ -555,8 +782,9 @@ pub mod values {
 
     impl Tuple {
         /// Create a dynamic `Tuple` value from a vector of its components.
+        #[allow(clippy::arc_with_non_send_sync)]
         pub fn new(elements: Vec<Value>) -> Arc<Self> {
-            Arc::new(Tuple { elements: elements })
+            Arc::new(Tuple { elements })
         }
     }
 
@@ -584,10 +812,10 @@ pub mod values {
         fn type_(&self) -> Type {
             Arc::new(types::Bool)
         }
-        fn get(&self) -> &Any {
-            &self.val as &Any
+        fn get(&self) -> &dyn Any {
+            &self.val as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
             vec![&self.val]
         }
         valuet_boiler!();
@@ -673,10 +901,10 @@ pub mod values {
         fn type_(&self) -> Type {
             Arc::new(types::UInt64)
         }
-        fn get(&self) -> &Any {
-            &self.val as &Any
+        fn get(&self) -> &dyn Any {
+            &self.val as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
             vec![&self.sql]
         }
         valuet_boiler!();
@@ -686,7 +914,7 @@ pub mod values {
         /// Creates Holmes value holding an unsigned 64-bit integer
         pub fn new(val: u64) -> Arc<Self> {
             Arc::new(UInt64 {
-                val: val,
+                val,
                 sql: val as i64,
             })
         }
@@ -708,11 +936,11 @@ pub mod values {
         fn type_(&self) -> Type {
             Arc::new(types::String)
         }
-        fn get(&self) -> &Any {
-            &self.val as &Any
+        fn get(&self) -> &dyn Any {
+            &self.val as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
-            vec![&self.val as &ToSql]
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
+            vec![&self.val as &dyn ToSql]
         }
         valuet_boiler!();
     }
@@ -720,7 +948,7 @@ pub mod values {
     impl String {
         /// Creates a Holmes value holding a `String`
         pub fn new(val: ::std::string::String) -> Arc<Self> {
-            Arc::new(String { val: val })
+            Arc::new(String { val })
         }
     }
 
@@ -734,11 +962,11 @@ pub mod values {
         fn type_(&self) -> Type {
             Arc::new(types::Bytes)
         }
-        fn get(&self) -> &Any {
-            &self.val as &Any
+        fn get(&self) -> &dyn Any {
+            &self.val as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
-            vec![&self.val as &ToSql]
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
+            vec![&self.val as &dyn ToSql]
         }
         valuet_boiler!();
     }
@@ -752,7 +980,7 @@ pub mod values {
     impl Bytes {
         /// Creates a new Holmes value holding raw data.
         pub fn new(val: Vec<u8>) -> Arc<Self> {
-            Arc::new(Bytes { val: val })
+            Arc::new(Bytes { val })
         }
     }
 
@@ -785,13 +1013,13 @@ pub mod values {
         fn type_(&self) -> Type {
             Arc::new(types::LargeBytes)
         }
-        fn get(&self) -> &Any {
+        fn get(&self) -> &dyn Any {
             use std::borrow::Borrow;
             let file_borrow: &File = self.fd.borrow();
-            file_borrow as &Any
+            file_borrow as &dyn Any
         }
-        fn to_sql(&self) -> Vec<&ToSql> {
-            vec![&self.hash as &ToSql]
+        fn to_sql(&self) -> Vec<&dyn ToSql> {
+            vec![&self.hash as &dyn ToSql]
         }
         valuet_boiler!();
     }
@@ -802,6 +1030,9 @@ pub mod values {
         }
     }
 
+    // The engine is single threaded, so large values share their open file
+    // through an `Rc` even though `Value` is an `Arc`
+    #[allow(clippy::arc_with_non_send_sync)]
     impl LargeBytes {
         /// Creates a new Holmes value holding raw data.
         pub fn new(val: Vec<u8>) -> Arc<Self> {
@@ -831,6 +1062,21 @@ pub mod values {
                 hash: fname,
             })
         }
+        /// The hex encoded SHA-256 of the contents, which identifies them in
+        /// storage
+        pub fn hash(&self) -> &str {
+            &self.hash
+        }
+        /// Reads the contents back from storage
+        pub fn contents(&self) -> ::std::io::Result<Vec<u8>> {
+            use std::io::{Read, Seek, SeekFrom};
+            // The descriptor is shared, so its offset may be anywhere
+            let mut file = self.fd.try_clone()?;
+            file.seek(SeekFrom::Start(0))?;
+            let mut buf = Vec::new();
+            file.read_to_end(&mut buf)?;
+            Ok(buf)
+        }
         /// Generate a `LargeBytes` value from its hash if already stored.
         /// This function does not have any error handling, so it should only be used if the user
         /// is certain the value has already been stored.
@@ -846,18 +1092,16 @@ pub mod values {
     use std::cell::RefCell;
     use std::rc::Rc;
     thread_local! {
+        /// Open files backing large values, by hash
         pub static FILE_CACHE: RefCell<HashMap<::std::string::String, Rc<File>>> =
             RefCell::new(HashMap::new());
     }
     fn cached_open(hash: &str) -> Rc<File> {
-        {
-            use std::ops::DerefMut;
-            FILE_CACHE.with(|cache| if cache.borrow().len() > 100 {
-                // We're thrashing on file descriptors, drop the cache
-                trace!("FILE_CACHE THRASHING");
-                *cache.borrow_mut() = HashMap::new()
-            })
-        }
+        FILE_CACHE.with(|cache| if cache.borrow().len() > 100 {
+            // We're thrashing on file descriptors, drop the cache
+            trace!("FILE_CACHE THRASHING");
+            *cache.borrow_mut() = HashMap::new()
+        });
         let file = FILE_CACHE.with(|cache| {
             cache
                 .borrow_mut()
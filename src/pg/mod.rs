@@ -21,12 +21,12 @@
 //!
 //! ## Other Databases
 //!
-//! For the moment, this is the only implementation, and there are no others
-//! on the horizon, so this interface is not abstract.
-//!
-//! The only major hurdle to using another backend would be figuring out how
-//! to make the `dyn` module abstract over databases.
+//! The engine talks to this database through the `FactDB` trait, which
+//! `MemDB` and `SqliteDB` also implement. The translation of queries to SQL
+//! is shared with `SqliteDB`. Values are still moved in and out of Postgres via
+//! the `dyn` module, so other backends need to translate them on their own.
 use std::collections::hash_map::HashMap;
+use std::collections::HashSet;
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use r2d2_postgres::{PostgresConnectionManager, TlsMode};
 use r2d2;
@@ -34,20 +34,23 @@ use r2d2;
 use fallible_iterator::FallibleIterator;
 
 use postgres;
-use postgres::{Connection, rows};
+use postgres::{Connection, GenericConnection, rows};
 use postgres::rows::LazyRows;
 use postgres::stmt::Statement;
 use postgres::transaction::Transaction;
 use postgres::params::IntoConnectParams;
 use postgres::params;
-use postgres::types::FromSql;
+use postgres::types::{FromSql, ToSql};
 
-use engine::types::{Clause, Fact, Field, MatchExpr, Predicate};
+use engine::types::{Aggregate, Clause, Fact, Field, Predicate, Var};
+use fact_db;
+use fact_db::FactDB;
 use std::cell::RefCell;
+use std::sync::Arc;
 
 pub mod dyn;
 
-#[allow(missing_docs)]
+#[allow(missing_docs, deprecated)]
 mod errors {
     use postgres as pg;
     use r2d2;
@@ -80,11 +83,11 @@ mod errors {
 pub use self::errors::*;
 
 use self::dyn::types;
-use self::dyn::{Type, Value};
+use self::dyn::{Backend, Type, Value};
+use sql;
+use sql::CompiledQuery;
 
-/// FactId is intended as a database-wide identifier for a fact - they are unique across tables and
-/// are intended for caching already run rules and recording providence.
-pub type FactId = i64;
+pub use fact_db::FactId;
 
 /// An iterator over a `postgres::rows::Row`.
 /// It does not implement the normal iter interface because it does not have
@@ -106,13 +109,13 @@ pub struct Query<'trans, 'stmt> {
 
 impl<'trans, 'stmt> Query<'trans, 'stmt> {
     /// Actually runs the query stored inside, transforming it into a lazy query iterator
-    pub fn run(&self) -> QueryIter {
+    pub fn run(&self) -> QueryIter<'_, '_> {
         let sql: Vec<_> = self.vals.iter().flat_map(|x| x.to_sql()).collect();
         trace!("Starting incremental query");
         let rows = self.stmt.lazy_query(self.trans, &sql, 16384).unwrap();
         trace!("Incremental query returned");
         QueryIter {
-            rows: rows,
+            rows,
             fact_ids: self.fact_ids,
             var_types: self.var_types.clone(),
         }
@@ -123,14 +126,12 @@ impl<'trans, 'stmt> Query<'trans, 'stmt> {
         // advance the 'fact_id' sequence while we don't have their facts in the read snapshot.
         // Luckily, we're async, not threaded atm, so this should be safe, just leave some holes in
         // the fact_id sequence, which I'm not terribly choked up about.
-        match self.trans
+        self.trans
             .query("select nextval('fact_id')", &[])
             .unwrap()
             .get(0)
-            .get(0) {
-            Some(fact_id) => fact_id,
-            _ => 0,
-        }
+            .get::<usize, Option<FactId>>(0)
+            .unwrap_or(0)
     }
 }
 
@@ -172,10 +173,11 @@ impl<'trans, 'stmt> Iterator for QueryIter<'trans, 'stmt> {
 impl<'a> RowIter<'a> {
     /// Create a new row iterator starting at the beginning of the provided row
     pub fn new(row: &'a rows::Row) -> Self {
-        RowIter { row: row, index: 0 }
+        RowIter { row, index: 0 }
     }
     /// Gets the next item in the row, using a `FromSql` instance to read it.
     /// If there is not a next item, returns `None`
+    #[allow(clippy::should_implement_trait)]
     pub fn next<T>(&mut self) -> Option<T>
     where
         T: FromSql,
@@ -206,14 +208,23 @@ pub struct PgDB {
 }
 
 impl PgDB {
-    /// Create a new PgDB object by passing in a Postgres connection string
-    // At the moment, persistence with custom types will result in failures
-    // on a reconnect, so use a fresh database every time.
-    // There's not a good way to persist custom types, so that fix will likely
-    // come with optional parameters to seed types in at db startup.
+    /// Create a new PgDB object by passing in a Postgres connection string.
+    /// Only the default types are available, so this will fail on a database
+    /// holding predicates over custom types; use `with_types` for those.
     pub fn new(uri: &str) -> Result<PgDB> {
+        PgDB::with_types(uri, Vec::new())
+    }
+
+    /// Create a new PgDB object, registering the provided custom types
+    /// alongside the default ones before loading any stored predicates.
+    ///
+    /// Types are not persisted, so every custom type used by a predicate in
+    /// the database must be provided again on each connection. If any are
+    /// missing, this fails with an error listing their names.
+    pub fn with_types(uri: &str, custom_types: Vec<Type>) -> Result<PgDB> {
+        let named_types = register_types(custom_types)?;
         // Create database if it doesn't already exist and we can
-        let params = try!(uri.into_connect_params().map_err(|_| ErrorKind::UriParse));
+        let params = uri.into_connect_params().map_err(|_| ErrorKind::UriParse)?;
         match Connection::connect(params.clone(), ::postgres::TlsMode::None) {
             // Database not found
             Err(ref db_error)
@@ -240,50 +251,68 @@ impl PgDB {
         let conn = pool.get()?;
 
         // Create schemas
-        try!(conn.execute("create schema if not exists facts", &[]));
+        conn.execute("create schema if not exists facts", &[])?;
 
         // Create Tables
-        try!(conn.execute(
+        conn.execute(
             "create table if not exists predicates (id serial primary key, \
                            name varchar not null, \
                            description varchar)",
             &[],
-        ));
-        try!(conn.execute(
+        )?;
+        conn.execute(
             "create table if not exists fields (\
                            pred_id serial references predicates(id), \
                            ordinal int4 not null, \
                            type varchar not null, \
                            name varchar, \
-                           description varchar)",
+                           description varchar, \
+                           join_func varchar)",
             &[],
-        ));
-        try!(conn.execute("create sequence if not exists fact_id", &[]));
+        )?;
+        // Databases made before lattice fields existed lack the join column
+        conn.execute(
+            "alter table fields add column if not exists join_func varchar",
+            &[],
+        )?;
+        conn.execute("create sequence if not exists fact_id", &[])?;
+        // Which rule derived each fact, and from which facts. Premises are
+        // listed by fact id along with the predicate to find them in.
+        conn.execute(
+            "create table if not exists provenance (\
+                           fact_id int8 primary key, \
+                           rule varchar not null, \
+                           premise_preds varchar[] not null, \
+                           premises int8[] not null)",
+            &[],
+        )?;
+        // How far each rule has got, so that it can carry on after a restart
+        conn.execute(
+            "create table if not exists rule_cursors (\
+                           rule varchar not null, \
+                           hash varchar not null, \
+                           cursor int8 not null, \
+                           primary key (rule, hash))",
+            &[],
+        )?;
 
         // Make array_to_string immutable to legalize index shenanigans
         // array_to_string is not actually immutable for some arrays (namely when ::text for the
         // element type is not immutable) so this is kind of taking off the safety rails
-        try!(conn.execute(
+        conn.execute(
             "alter function array_to_string(anyarray, text) IMMUTABLE",
             &[],
-        ));
+        )?;
 
         // Create incremental PgDB object
         let db = PgDB {
             conn_pool: pool,
             pred_by_name: RefCell::new(HashMap::new()),
             insert_by_name: RefCell::new(HashMap::new()),
-            named_types: RefCell::new(
-                types::default_types()
-                    .iter()
-                    .filter_map(|type_| {
-                        type_.name().map(|name| (name.to_owned(), type_.clone()))
-                    })
-                    .collect(),
-            ),
+            named_types: RefCell::new(named_types),
         };
 
-        try!(db.rebuild_predicate_cache());
+        db.rebuild_predicate_cache()?;
 
         Ok(db)
     }
@@ -295,10 +324,10 @@ impl PgDB {
 
     /// Kick everyone off the database and destroy the data at the provided URI
     pub fn destroy(uri: &str) -> Result<()> {
-        let params = try!(uri.into_connect_params().map_err(|_| ErrorKind::UriParse));
-        let old_db = try!(params.database().ok_or_else(|| {
+        let params = uri.into_connect_params().map_err(|_| ErrorKind::UriParse)?;
+        let old_db = params.database().ok_or_else(|| {
             ErrorKind::Arg(format!("No database specified to destroy in {}.", uri))
-        }));
+        })?;
         let pg_params = param_into_builder(&params).database("postgres").build(
             params
                 .host()
@@ -311,17 +340,15 @@ impl PgDB {
                                    pid <> pg_backend_pid()",
             &old_db
         );
-        try!(conn.execute(&disco_query, &[]));
+        conn.execute(&disco_query, &[])?;
         let drop_query = format!("DROP DATABASE {}", &old_db);
-        try!(conn.execute(&drop_query, &[]));
+        conn.execute(&drop_query, &[])?;
         Ok(())
     }
 
 
-    // Rebuilds the predicate cache
-    // I'm assuming for the moment that there isn't going to be a lot of
-    // dynamic type adding/removal, and so rebuilding the predicate/insert
-    // statement cache on add/remove isn't a big deal
+    // Loads the predicate and insert statement caches from the database.
+    // Every stored field's type must already be registered.
     fn rebuild_predicate_cache(&self) -> Result<()> {
         *self.pred_by_name.borrow_mut() = HashMap::new();
         *self.insert_by_name.borrow_mut() = HashMap::new();
@@ -333,11 +360,13 @@ impl PgDB {
                               predicates.description, \
                               fields.name, \
                               fields.description, \
-                              fields.type from predicates JOIN fields ON \
+                              fields.type, \
+                              fields.join_func from predicates JOIN fields ON \
                               predicates.id = fields.pred_id ORDER BY predicates.id, \
                               fields.ordinal",
             )?;
-            let pred_types = try!(pred_stmt.query(&[]));
+            let pred_types = pred_stmt.query(&[])?;
+            let mut missing = Vec::new();
             for type_entry in pred_types.iter() {
                 let mut row = RowIter::new(&type_entry);
                 let name: String = row.next().unwrap();
@@ -346,14 +375,21 @@ impl PgDB {
                 let field_name: Option<String> = row.next();
                 let field_descr: Option<String> = row.next();
                 let h_type_str: String = row.next().unwrap();
+                let join: Option<String> = row.next();
                 let h_type = match self.get_type(&h_type_str) {
                     Some(ty) => ty,
-                    None => types::Trap::new(),
+                    None => {
+                        if !missing.contains(&h_type_str) {
+                            missing.push(h_type_str);
+                        }
+                        continue;
+                    }
                 };
                 let field = Field {
                     name: field_name,
                     description: field_descr,
                     type_: h_type.clone(),
+                    join,
                 };
                 match self.pred_by_name.borrow_mut().entry(name.clone()) {
                     Vacant(entry) => {
@@ -368,6 +404,12 @@ impl PgDB {
                     }
                 }
             }
+            if !missing.is_empty() {
+                bail!(ErrorKind::Type(format!(
+                    "Stored predicates use unregistered types: {}",
+                    missing.join(", ")
+                )));
+            }
         }
         // Populate fact insert cache
         self.pred_by_name
@@ -403,119 +445,617 @@ impl PgDB {
     // Persist a predicate into the database
     // This function is internal because it does not add it to the object, it
     // _only_ puts record of the predicate into the database.
-    fn insert_predicate(&self, pred: &Predicate) -> Result<()> {
-        let &Predicate {
-            ref name,
-            ref description,
-            ref fields,
-        } = pred;
-        let conn = self.conn_pool.get()?;
+    fn insert_predicate(&self, conn: &dyn GenericConnection, pred: &Predicate) -> Result<()> {
         let stmt = conn.prepare(
             "insert into predicates (name, description) values ($1, $2) returning id",
         )?;
-        let pred_id: i32 = stmt.query(&[name, description])?.get(0).get(0);
-        for (ordinal, field) in fields.iter().enumerate() {
-            try!(conn.execute(
-                "insert into fields (pred_id, name, description, type, ordinal) \
-                          values ($1, $2, $3, $4, $5)",
-                &[
-                    &pred_id,
-                    &field.name,
-                    &field.description,
-                    &field.type_.name().ok_or(ErrorKind::Arg(
-                        "Field type had no name".to_string(),
-                    ))?,
-                    &(ordinal as i32),
-                ],
-            ));
+        let pred_id: i32 = stmt.query(&[&pred.name, &pred.description])?.get(0).get(0);
+        insert_fields(conn, pred_id, &pred.fields)?;
+        create_fact_table(conn, pred)
+    }
+
+    /// Changes the fields of a stored predicate to those of `pred`,
+    /// converting the arguments of each stored fact with `migrate`.
+    /// Facts keep their ids, except that facts which become duplicates of
+    /// earlier ones are dropped along with their provenance.
+    ///
+    /// The field records, fact table and its unique index are all replaced
+    /// in a single transaction.
+    pub fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> Result<()> {
+        let old = self.get_predicate(&pred.name).ok_or_else(|| {
+            ErrorKind::Arg(format!("{} is not a registered predicate", pred.name))
+        })?;
+        let conn = self.conn()?;
+        let trans = conn.transaction()?;
+        let cols = (0..old.fields.len())
+            .map(|slot| format!("arg{}", slot))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let rows = trans.query(
+            &format!("select id, {} from facts.{} order by id", cols, old.name),
+            &[],
+        )?;
+        let mut facts = Vec::new();
+        for row in rows.iter() {
+            let mut row_iter = RowIter::new(&row);
+            let id: FactId = row_iter.next().unwrap();
+            let mut args = Vec::new();
+            for field in old.fields.iter() {
+                match field.type_.extract(&mut row_iter) {
+                    Some(val) => args.push(val),
+                    None => bail!(ErrorKind::Internal(
+                        "Failure loading fact from row".to_string(),
+                    )),
+                }
+            }
+            match migrate(&args) {
+                Some(args) => facts.push((id, args)),
+                None => bail!(ErrorKind::Arg(
+                    format!("Fact {} of {} cannot be migrated", id, pred.name),
+                )),
+            }
         }
-        let table_str = fields
-            .iter()
-            .map(|field| field.type_.repr())
-            .enumerate()
-            .map(|(ord, repr)| format!("arg{} {}", ord, repr))
+
+        let pred_id: i32 = trans
+            .query("select id from predicates where name = $1", &[&pred.name])?
+            .get(0)
+            .get(0);
+        trans.execute("delete from fields where pred_id = $1", &[&pred_id])?;
+        insert_fields(&trans, pred_id, &pred.fields)?;
+        trans.execute(&format!("drop table facts.{}", pred.name), &[])?;
+        create_fact_table(&trans, pred)?;
+
+        let params = (0..pred.fields.len())
+            .map(|slot| format!("${}", slot + 2))
             .collect::<Vec<_>>()
             .join(", ");
-        let col_str = fields
+        {
+            let stmt = trans.prepare(&format!(
+                "insert into facts.{} values ($1, {}) ON CONFLICT DO NOTHING",
+                pred.name,
+                params
+            ))?;
+            for (id, args) in facts {
+                let mut vals: Vec<&dyn ToSql> = vec![&id as &dyn ToSql];
+                vals.extend(args.iter().flat_map(|arg| arg.to_sql().into_iter()));
+                if stmt.execute(&vals)? == 0 {
+                    trans.execute("delete from provenance where fact_id = $1", &[&id])?;
+                }
+            }
+        }
+        trans.commit()?;
+
+        self.gen_insert_stmt(pred);
+        self.pred_by_name.borrow_mut().insert(
+            pred.name.clone(),
+            pred.clone(),
+        );
+        Ok(())
+    }
+
+    /// Adds a new fact to the database, returning false if the fact was already
+    /// present in the database, and true if it was inserted.
+    pub fn insert_fact(&self, fact: &Fact) -> Result<Option<FactId>> {
+        self.insert_on(&*self.conn()?, fact)
+    }
+
+    fn insert_on(&self, conn: &dyn GenericConnection, fact: &Fact) -> Result<Option<FactId>> {
+        let stmt_str = self.insert_by_name
+            .borrow()
+            .get(&fact.pred_name)
+            .ok_or_else(|| {
+                ErrorKind::Internal("Insert Statement Missing".to_string())
+            })?.clone();
+        let stmt = conn.prepare_cached(&stmt_str)?;
+
+        let out = stmt.query(&fact.args
             .iter()
-            .map(|field| {
-                (field.type_.large(), field.type_.repr().contains("[]"))
-            })
-            .enumerate()
-            .map(|(ord, (large, is_array))| if large {
-                if is_array {
-                    format!("md5(array_to_string(arg{}, ','))", ord)
-                } else {
-                    format!("md5(arg{}::text)", ord)
+            .flat_map(|x| x.to_sql().into_iter())
+            .collect::<Vec<_>>())?;
+
+        Ok(out.iter().next().map(|x| x.get(0)))
+    }
+
+    /// Adds several facts in a single transaction, returning the id of each,
+    /// or `None` for those which were already present, including repeats
+    /// within `facts`.
+    ///
+    /// Facts are inserted a predicate at a time using multi-row statements,
+    /// which is much faster than inserting them one by one. Predicates with
+    /// lattice fields are not supported, since their facts need merging; use
+    /// `store_facts` for those.
+    pub fn insert_facts(&self, facts: &[Fact]) -> Result<Vec<Option<FactId>>> {
+        let conn = self.conn()?;
+        let trans = conn.transaction()?;
+        let ids = self.insert_facts_on(&trans, facts)?;
+        trans.commit()?;
+        Ok(ids)
+    }
+
+    fn insert_facts_on(
+        &self,
+        conn: &dyn GenericConnection,
+        facts: &[Fact],
+    ) -> Result<Vec<Option<FactId>>> {
+        // Group the facts by predicate, in order of first appearance
+        let mut groups: Vec<(&str, Vec<usize>)> = Vec::new();
+        {
+            let mut group_of: HashMap<&str, usize> = HashMap::new();
+            for (n, fact) in facts.iter().enumerate() {
+                let group = *group_of.entry(fact.pred_name.as_str()).or_insert_with(|| {
+                    groups.push((fact.pred_name.as_str(), Vec::new()));
+                    groups.len() - 1
+                });
+                groups[group].1.push(n);
+            }
+        }
+
+        let mut ids = vec![None; facts.len()];
+        for (pred_name, members) in groups {
+            let width = match self.pred_by_name.borrow().get(pred_name) {
+                Some(pred) => {
+                    if pred.fields.iter().any(|field| field.join.is_some()) {
+                        bail!(ErrorKind::Arg(format!(
+                            "{} has lattice fields, so its facts must be merged",
+                            pred_name
+                        )));
+                    }
+                    pred.fields.len()
+                }
+                None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+            };
+            if let Some(n) = members.iter().find(|n| facts[**n].args.len() != width) {
+                bail!(ErrorKind::Arg(format!(
+                    "{} takes {} arguments, but was given {}",
+                    pred_name,
+                    width,
+                    facts[*n].args.len()
+                )));
+            }
+            // The driver sends the parameter count as an i16, so a statement
+            // can carry at most 32767 parameters
+            for chunk in members.chunks(32767 / (width + 1)) {
+                // Draw the ids up front, so that the ids returned show which
+                // of the facts were new
+                let fresh: Vec<FactId> = conn
+                    .query(
+                        "select nextval('fact_id') from generate_series(1, $1)",
+                        &[&(chunk.len() as i32)],
+                    )?
+                    .iter()
+                    .map(|row| row.get(0))
+                    .collect();
+                let rows = (0..chunk.len())
+                    .map(|row| {
+                        let params = (1..width + 2)
+                            .map(|col| format!("${}", row * (width + 1) + col))
+                            .collect::<Vec<_>>();
+                        format!("({})", params.join(", "))
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let stmt = conn.prepare_cached(&format!(
+                    "insert into facts.{} values {} ON CONFLICT DO NOTHING RETURNING id",
+                    pred_name,
+                    rows
+                ))?;
+                let mut vals: Vec<&dyn ToSql> = Vec::new();
+                for (id, n) in fresh.iter().zip(chunk.iter()) {
+                    vals.push(id as &dyn ToSql);
+                    vals.extend(facts[*n].args.iter().flat_map(|arg| arg.to_sql().into_iter()));
                 }
+                let inserted: HashSet<FactId> =
+                    stmt.query(&vals)?.iter().map(|row| row.get(0)).collect();
+                for (id, n) in fresh.iter().zip(chunk.iter()) {
+                    if inserted.contains(id) {
+                        ids[*n] = Some(*id);
+                    }
+                }
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Stores several facts in a single transaction, so either all or none
+    /// of them are stored. Facts for predicates with lattice fields are
+    /// merged as per `merge_fact`, with `join` also receiving the fact.
+    pub fn store_facts(
+        &self,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let conn = self.conn()?;
+        let trans = conn.transaction()?;
+        let ids = self.store_on(&trans, facts, join)?;
+        trans.commit()?;
+        Ok(ids)
+    }
+
+    fn store_on(
+        &self,
+        conn: &dyn GenericConnection,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let mut ids = Vec::new();
+        for fact in facts.iter() {
+            if self.is_lattice(&fact.pred_name)? {
+                ids.push(self.merge_on(
+                    conn,
+                    fact,
+                    &|slot, old, new| join(fact, slot, old, new),
+                )?);
             } else {
-                format!("arg{}", ord)
+                ids.push(self.insert_on(conn, fact)?);
+            }
+        }
+        Ok(ids)
+    }
+
+    // Whether a predicate has lattice fields, whose facts must be merged
+    fn is_lattice(&self, pred_name: &str) -> Result<bool> {
+        match self.pred_by_name.borrow().get(pred_name) {
+            Some(pred) => Ok(pred.fields.iter().any(|field| field.join.is_some())),
+            None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+        }
+    }
+
+    /// Stores the facts derived by an activation of `rule` and records the
+    /// provenance of the new ones, all in a single transaction. Facts are
+    /// inserted in bulk as per `insert_facts`, unless some need merging, and
+    /// provenance is recorded with multi-row statements.
+    pub fn store_derived(
+        &self,
+        rule: &str,
+        derived: &[(Fact, Vec<(String, FactId)>)],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let facts: Vec<Fact> = derived.iter().map(|(fact, _)| fact.clone()).collect();
+        let mut lattice = false;
+        for fact in facts.iter() {
+            lattice |= self.is_lattice(&fact.pred_name)?;
+        }
+        let conn = self.conn()?;
+        let trans = conn.transaction()?;
+        let ids = if lattice {
+            self.store_on(&trans, &facts, join)?
+        } else {
+            self.insert_facts_on(&trans, &facts)?
+        };
+        let records: Vec<(FactId, Vec<String>, Vec<FactId>)> = ids.iter()
+            .zip(derived.iter())
+            .filter_map(|(id, (_, premises))| {
+                id.map(|id| {
+                    (
+                        id,
+                        premises.iter().map(|p| p.0.clone()).collect(),
+                        premises.iter().map(|p| p.1).collect(),
+                    )
+                })
             })
+            .collect();
+        // Four parameters a row, within the driver's limit of 32767
+        for chunk in records.chunks(8191) {
+            let rows = (0..chunk.len())
+                .map(|row| {
+                    format!(
+                        "(${}, ${}, ${}, ${})",
+                        row * 4 + 1,
+                        row * 4 + 2,
+                        row * 4 + 3,
+                        row * 4 + 4
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut vals: Vec<&dyn ToSql> = Vec::new();
+            for (id, preds, premises) in chunk.iter() {
+                vals.push(id);
+                vals.push(&rule);
+                vals.push(preds);
+                vals.push(premises);
+            }
+            trans
+                .prepare_cached(&format!(
+                    "insert into provenance (fact_id, rule, premise_preds, premises) \
+                     values {} on conflict do nothing",
+                    rows
+                ))?
+                .execute(&vals)?;
+        }
+        trans.commit()?;
+        Ok(ids)
+    }
+
+    /// Removes the fact with the given id, returning false if there was no
+    /// such fact.
+    pub fn delete_fact(&self, pred_name: &str, id: FactId) -> Result<bool> {
+        let conn = self.conn()?;
+        let deleted = conn.execute(
+            &format!("delete from facts.{} where id = $1", pred_name),
+            &[&id],
+        )?;
+        conn.execute("delete from provenance where fact_id = $1", &[&id])?;
+        Ok(deleted > 0)
+    }
+
+    /// Looks up a fact by its id
+    pub fn get_fact(&self, pred_name: &str, id: FactId) -> Result<Option<Fact>> {
+        let pred = match self.get_predicate(pred_name) {
+            Some(pred) => pred,
+            None => return Ok(None),
+        };
+        let cols = (0..pred.fields.len())
+            .map(|slot| format!("arg{}", slot))
             .collect::<Vec<_>>()
             .join(", ");
-        self.conn_pool.get()?.execute(
+        let conn = self.conn()?;
+        let rows = conn.query(
+            &format!("select {} from facts.{} where id = $1", cols, pred_name),
+            &[&id],
+        )?;
+        let row = match rows.iter().next() {
+            Some(row) => row,
+            None => return Ok(None),
+        };
+        let mut row_iter = RowIter::new(&row);
+        let mut args = Vec::new();
+        for field in pred.fields.iter() {
+            match field.type_.extract(&mut row_iter) {
+                Some(val) => args.push(val),
+                None => bail!(ErrorKind::Internal(
+                    "Failure loading fact from row".to_string(),
+                )),
+            }
+        }
+        Ok(Some(Fact {
+            pred_name: pred_name.to_string(),
+            args,
+        }))
+    }
+
+    /// Records that a fact was derived by `rule` from the `premises`, given
+    /// as predicate names and fact ids. Only the first derivation recorded
+    /// for a fact is kept.
+    pub fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> Result<()> {
+        let preds: Vec<String> = premises.iter().map(|p| p.0.clone()).collect();
+        let ids: Vec<FactId> = premises.iter().map(|p| p.1).collect();
+        self.conn()?.execute(
+            "insert into provenance (fact_id, rule, premise_preds, premises) \
+             values ($1, $2, $3, $4) on conflict do nothing",
+            &[&fact_id, &rule, &preds, &ids],
+        )?;
+        Ok(())
+    }
+
+    /// Finds the rule which derived a fact and the premises it used, or
+    /// `None` if the fact was not derived by a rule.
+    #[allow(clippy::type_complexity)]
+    pub fn get_provenance(
+        &self,
+        fact_id: FactId,
+    ) -> Result<Option<(String, Vec<(String, FactId)>)>> {
+        let rows = self.conn()?.query(
+            "select rule, premise_preds, premises from provenance where fact_id = $1",
+            &[&fact_id],
+        )?;
+        Ok(rows.iter().next().map(|row| {
+            let preds: Vec<String> = row.get(1);
+            let ids: Vec<FactId> = row.get(2);
+            (row.get(0), preds.into_iter().zip(ids).collect())
+        }))
+    }
+
+    /// Looks up how far a rule has got, by its name and the hash of its
+    /// definition
+    pub fn get_cursor(&self, rule: &str, hash: &str) -> Result<Option<FactId>> {
+        let rows = self.conn()?.query(
+            "select cursor from rule_cursors where rule = $1 and hash = $2",
+            &[&rule, &hash],
+        )?;
+        Ok(rows.iter().next().map(|row| row.get(0)))
+    }
+
+    /// Stores how far a rule has got, or forgets it if `cursor` is `None`
+    pub fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> Result<()> {
+        let conn = self.conn()?;
+        match cursor {
+            Some(cursor) => {
+                conn.execute(
+                    "insert into rule_cursors (rule, hash, cursor) values ($1, $2, $3) \
+                     on conflict (rule, hash) do update set cursor = excluded.cursor",
+                    &[&rule, &hash, &cursor],
+                )?
+            }
+            None => {
+                conn.execute(
+                    "delete from rule_cursors where rule = $1 and hash = $2",
+                    &[&rule, &hash],
+                )?
+            }
+        };
+        Ok(())
+    }
+
+    /// Indexes a predicate's facts by the given slots, unless there is such
+    /// an index already
+    pub fn create_index(&self, pred_name: &str, slots: &[usize]) -> Result<()> {
+        if !self.pred_by_name.borrow().contains_key(pred_name) {
+            bail!(ErrorKind::Arg("Predicate not registered".to_string()));
+        }
+        let name: Vec<String> = slots.iter().map(|slot| slot.to_string()).collect();
+        let cols: Vec<String> = slots.iter().map(|slot| format!("arg{}", slot)).collect();
+        self.conn()?.execute(
             &format!(
-                "create table facts.{} (id INT8 DEFAULT nextval('fact_id') NOT \
-                               NULL primary key, {})",
-                name,
-                table_str
+                "create index if not exists {}_by_{} on facts.{} ({})",
+                pred_name,
+                name.join("_"),
+                pred_name,
+                cols.join(", ")
             ),
             &[],
         )?;
-        if col_str != "" {
-            self.conn_pool.get()?.execute(
-                &format!(
-                    "create unique index on facts.{} ({})",
-                    name,
-                    col_str
-                ),
-                &[],
-            )?;
-        }
         Ok(())
     }
-    /// Adds a new fact to the database, returning false if the fact was already
-    /// present in the database, and true if it was inserted.
-    pub fn insert_fact(&self, fact: &Fact) -> Result<Option<FactId>> {
-        let stmt_str = try!(
-            self.insert_by_name
-                .borrow()
-                .get(&fact.pred_name)
-                .ok_or_else(|| {
-                    ErrorKind::Internal("Insert Statement Missing".to_string())
-                })
-        ).clone();
+
+    /// Stores a fact, replacing any facts which agree with it on the `key`
+    /// slots. Returns `None` if the fact was already present as-is.
+    pub fn replace_fact(&self, fact: &Fact, key: &[usize]) -> Result<Option<FactId>> {
+        let stmt_str = self.insert_by_name
+            .borrow()
+            .get(&fact.pred_name)
+            .ok_or_else(|| {
+                ErrorKind::Internal("Insert Statement Missing".to_string())
+            })?.clone();
         let conn = self.conn()?;
-        let stmt = conn.prepare_cached(&stmt_str)?;
+        let trans = conn.transaction()?;
 
+        let all: Vec<usize> = (0..fact.args.len()).collect();
+        let present = trans.query(
+            &format!(
+                "select 1 from facts.{} where {}",
+                fact.pred_name,
+                slot_condition(&all)
+            ),
+            &slot_values(fact, &all),
+        )?;
+        if !present.is_empty() {
+            return Ok(None);
+        }
 
-        let out = try!(
-            stmt.query(&fact.args
-                .iter()
-                .flat_map(|x| x.to_sql().into_iter())
-                .collect::<Vec<_>>())
-        );
+        trans.execute(
+            &format!(
+                "delete from facts.{} where {}",
+                fact.pred_name,
+                slot_condition(key)
+            ),
+            &slot_values(fact, key),
+        )?;
+        let out = trans.prepare_cached(&stmt_str)?.query(
+            &slot_values(fact, &all),
+        )?;
+        let id = out.iter().next().map(|x| x.get(0));
+        trans.commit()?;
+        Ok(id)
+    }
 
-        Ok(out.iter().next().map(|x| x.get(0)))
+    /// Stores a fact for a predicate with lattice fields. If a fact agreeing on
+    /// all the non-lattice fields is present, the lattice fields are combined
+    /// using `join`, which receives the slot and the stored and new values,
+    /// and fails if `join` gives `None`.
+    /// Returns `None` if this did not change the stored fact.
+    pub fn merge_fact(
+        &self,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Option<FactId>> {
+        self.merge_on(&*self.conn()?, fact, join)
+    }
+
+    fn merge_on(
+        &self,
+        conn: &dyn GenericConnection,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Option<FactId>> {
+        let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        let stmt_str = self.insert_by_name
+            .borrow()
+            .get(&fact.pred_name)
+            .ok_or_else(|| {
+                ErrorKind::Internal("Insert Statement Missing".to_string())
+            })?.clone();
+        let (lattice, key): (Vec<usize>, Vec<usize>) =
+            (0..pred.fields.len()).partition(|slot| pred.fields[*slot].join.is_some());
+        let trans = conn.transaction()?;
+
+        let cols = lattice
+            .iter()
+            .map(|slot| format!("arg{}", slot))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let rows = trans.query(
+            &format!(
+                "select {} from facts.{} where {}",
+                cols,
+                fact.pred_name,
+                slot_condition(&key)
+            ),
+            &slot_values(fact, &key),
+        )?;
+        let row = match rows.iter().next() {
+            Some(row) => row,
+            None => {
+                let all: Vec<usize> = (0..fact.args.len()).collect();
+                let out = trans.prepare_cached(&stmt_str)?.query(
+                    &slot_values(fact, &all),
+                )?;
+                let id = out.iter().next().map(|x| x.get(0));
+                trans.commit()?;
+                return Ok(id);
+            }
+        };
+        let mut row_iter = RowIter::new(&row);
+        let mut merged = fact.clone();
+        let mut changed = false;
+        for slot in lattice.iter() {
+            let old = match pred.fields[*slot].type_.extract(&mut row_iter) {
+                Some(val) => val,
+                None => bail!(ErrorKind::Internal(
+                    "Failure loading lattice field from row".to_string(),
+                )),
+            };
+            let joined = match join(*slot, &old, &fact.args[*slot]) {
+                Some(joined) => joined,
+                None => bail!(ErrorKind::Arg(
+                    format!("Slot {} of {} cannot be joined", slot, fact.pred_name),
+                )),
+            };
+            changed |= joined != old;
+            merged.args[*slot] = joined;
+        }
+        if !changed {
+            return Ok(None);
+        }
+        // The merged row takes a fresh id so that rules which have already
+        // seen the old value will see it again.
+        let sets = lattice
+            .iter()
+            .enumerate()
+            .map(|(k, slot)| format!(", arg{} = ${}", slot, key.len() + k + 1))
+            .collect::<String>();
+        let mut vals = slot_values(fact, &key);
+        vals.extend(slot_values(&merged, &lattice));
+        let out = trans.query(
+            &format!(
+                "update facts.{} set id = nextval('fact_id'){} where {} returning id",
+                fact.pred_name,
+                sets,
+                slot_condition(&key)
+            ),
+            &vals,
+        )?;
+        let id = out.iter().next().map(|x| x.get(0));
+        trans.commit()?;
+        Ok(id)
     }
 
     /// Registers a new type with the database.
-    /// This is unstable, and will likely need to be moved to the initialization
-    /// of the database object in order to allow reconnecting to an existing
-    /// database.
+    /// Types are not persisted, so if predicates using this type are stored,
+    /// it must also be passed to `with_types` when reconnecting.
     pub fn add_type(&self, type_: Type) -> Result<()> {
-        let name = type_.name().ok_or(ErrorKind::Arg(
-            "Tried to add a type with no name".to_string(),
-        ))?;
-        if !self.named_types.borrow().contains_key(name) {
-            self.named_types.borrow_mut().insert(
-                name.to_owned(),
-                type_.clone(),
-            );
-            self.rebuild_predicate_cache()
-        } else {
-            bail!(ErrorKind::Type(format!("{} already registered", name)))
-        }
+        add_named_type(&mut self.named_types.borrow_mut(), type_)
     }
 
     /// Looks for a named type in the database's registry.
@@ -523,7 +1063,12 @@ impl PgDB {
     /// queries, since it allows you to use names of types when declaring
     /// functions rather than type objects.
     pub fn get_type(&self, type_str: &str) -> Option<Type> {
-        self.named_types.borrow().get(type_str).map(|x| x.clone())
+        if type_str.starts_with('[') && type_str.ends_with(']') {
+            return self.get_type(&type_str[1..type_str.len() - 1]).map(|elem| {
+                types::List::new(elem) as Type
+            });
+        }
+        self.named_types.borrow().get(type_str).cloned()
     }
 
     /// Fetches a predicate by name
@@ -531,6 +1076,13 @@ impl PgDB {
         self.pred_by_name.borrow().get(pred_name).cloned()
     }
 
+    /// Lists all registered predicates, ordered by name
+    pub fn list_predicates(&self) -> Vec<Predicate> {
+        let mut preds: Vec<Predicate> = self.pred_by_name.borrow().values().cloned().collect();
+        preds.sort_by(|a, b| a.name.cmp(&b.name));
+        preds
+    }
+
     /// Persists a predicate by name
     /// The name *must* consist only of lower case ASCII and _, anything else
     /// will be rejected. This restriction is because the predicate name is
@@ -542,7 +1094,7 @@ impl PgDB {
     // TODO lift restriction on predicate names
     pub fn new_predicate(&self, pred: &Predicate) -> Result<()> {
         // The predicate name is used as a table name, check it for legality
-        if !valid_name(&pred.name) {
+        if !sql::valid_name(&pred.name) {
             bail!(ErrorKind::Arg(
                 "Invalid name: Use lowercase and \
                                  underscores only"
@@ -550,25 +1102,22 @@ impl PgDB {
             ));
         }
         // If this predicate was already registered, check for a match
-        match self.pred_by_name.borrow().get(&pred.name) {
-            Some(existing) => {
-                if existing != pred {
-                    bail!(ErrorKind::Arg(format!(
-                        "Predicate {} already registered at a \
-                                                  different type.\nExisting: {:?}\nNew: {:?}",
-                        &pred.name,
-                        existing,
-                        pred
-                    )));
-                } else {
-                    return Ok(());
-                }
+        if let Some(existing) = self.pred_by_name.borrow().get(&pred.name) {
+            if existing != pred {
+                bail!(ErrorKind::Arg(format!(
+                    "Predicate {} already registered at a \
+                                              different type.\nExisting: {:?}\nNew: {:?}",
+                    &pred.name,
+                    existing,
+                    pred
+                )));
+            } else {
+                return Ok(());
             }
-            None => (),
         }
 
-        try!(self.insert_predicate(&pred));
-        self.gen_insert_stmt(&pred);
+        self.insert_predicate(&*self.conn()?, pred)?;
+        self.gen_insert_stmt(pred);
         self.pred_by_name.borrow_mut().insert(
             pred.name.clone(),
             pred.clone(),
@@ -576,197 +1125,53 @@ impl PgDB {
         Ok(())
     }
 
+    // Checks a query and translates it into the pieces of a SQL select
+    fn compile_query(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> Result<CompiledQuery> {
+        Ok(sql::compile_query(
+            Backend::Postgres,
+            &self.pred_by_name.borrow(),
+            query,
+            min_fact_id,
+        ).map_err(ErrorKind::Arg)?)
+    }
+
     /// Attempt to match the right hand side of a datalog rule against the
     /// database, returning a list of solution assignments to the bound
     /// variables.
-    pub fn search_facts<'a>(
+    pub fn search_facts(
         &self,
-        query: &Vec<Clause>,
+        query: &[Clause],
         min_fact_id: Option<FactId>,
     ) -> Result<Vec<(Vec<FactId>, Vec<Value>)>> {
-        // Check there is at least one clause
-        if query.len() == 0 {
-            bail!(ErrorKind::Arg("Empty search query".to_string()));
-        };
-
-        // Check that clauses:
-        // * Have sequential variables
-        // * Reference predicates in the database
-        // * Only unify variables of equal type
-        {
-            let mut var_type: Vec<Type> = Vec::new();
-            for clause in query.iter() {
-                let pred = match self.pred_by_name.borrow().get(&clause.pred_name).cloned() {
-                    Some(pred) => pred,
-                    None => {
-                        bail!(ErrorKind::Arg(format!(
-                            "{} is not a registered predicate.",
-                            clause.pred_name
-                        )))
-                    }
-                };
-                for (n, binding) in clause.args.iter().enumerate() {
-                    match *binding {
-                        MatchExpr::Unbound |
-                        MatchExpr::Const(_) => (),
-                        MatchExpr::Var(v) => {
-                            let v = v as usize;
-                            let type_ = pred.fields[n].type_.clone();
-                            if v == var_type.len() {
-                                var_type.push(type_)
-                            } else if v > var_type.len() {
-                                if var_type.len() == 0 {
-                                    bail!(ErrorKind::Arg(format!(
-                                                "First variable not Var(0), got Var({})", v)));
-                                }
-                                bail!(ErrorKind::Arg(format!(
-                                    "Hole between {} and {} in \
-                                                              variable numbering.",
-                                    var_type.len() - 1,
-                                    v
-                                )));
-                            } else if &var_type[v] != &type_ {
-                                bail!(ErrorKind::Arg(format!(
-                                    "Variable {} attempt to unify \
-                                                              incompatible types {:?} and {:?}",
-                                    v,
-                                    var_type[v],
-                                    type_
-                                )));
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        // Actually build and execute the query
-        let mut tables = Vec::new(); // Predicate names involved in the query,
-        // in the sequence they appear
-        let mut restricts = vec![format!("1 = 1")]; // Unification expressions, indexed by
-        // which join they belong on.
-        let mut var_names = Vec::new(); // Translation of variable numbers to
-        // sql exprs
-        let mut fact_ids = Vec::new(); // Translation of fact ids to sql exprs
-        let mut var_types = Vec::new(); // Translation of variable numbers to
-        // Types
-        let mut vals: Vec<Value> = Vec::new(); // Values to be quoted into the
-        // prepared statement
-        let mut param_num = 1;
-
-        for (idxc, clause) in query.iter().enumerate() {
-            // The clause refers to a table named by the predicate
-            let table_name = format!("facts.{}", clause.pred_name);
-            // We will refer to it by a numbered alias, to make joining easier
-            let alias_name = format!("t{}", idxc);
-            let pred = self.pred_by_name
-                .borrow()
-                .get(&clause.pred_name)
-                .unwrap()
-                .clone();
-            fact_ids.push(format!("{}.id", alias_name));
-            let mut clause_elements = Vec::new();
-            for (n, arg) in clause.args.iter().enumerate() {
-                let proj_str = format!("{}.arg{}", alias_name, n);
-                match *arg {
-                    MatchExpr::Unbound => (),
-                    MatchExpr::Var(var) => {
-                        if var >= var_names.len() {
-                            // This situation means it's the first occurrence of the variable
-                            // We record this definition as the canonical definition for use
-                            // in the select, and store the type to know how to extract it.
-                            var_names.push(proj_str);
-                            let type_ = pred.fields[n].type_.clone();
-                            var_types.push(type_);
-                        } else {
-                            // The variable has occurred correctly, so we add it being equal
-                            // to the canonical definition to the join clause for this table
-                            let piece = format!("{} = {}", proj_str, var_names[var]);
-                            clause_elements.push(piece);
-                        }
-                    }
-                    MatchExpr::Const(ref val) => {
-                        // Since we're comparing against a constant, this restriction can
-                        // go in the where clause.
-                        // I stash the value in a buffer for later use with the prepared
-                        // statement, and put the index into the buffer into the where
-                        // clause chunk.
-                        vals.push(val.clone());
-                        restricts.push(format!("{} = ${}", proj_str, param_num));
-                        param_num += 1;
-                    }
-                }
-            }
-            restricts.extend(clause_elements);
-            tables.push(format!("{} as {}", table_name, alias_name));
-        }
+        let compiled = self.compile_query(query, min_fact_id)?;
         // Make sure we're never empty on bound variables. If we are, we will get
         // SELECT FROM
         // which will not work.
-        var_names.push("0".to_string());
-
-        let mut merge_vars = fact_ids.clone();
-
-        merge_vars.extend(var_names.into_iter());
-
-        let vars = format!("{}", merge_vars.join(", "));
-        let cache_clause = min_fact_id.map(|fid| {
-            (
-                format!(
-                    "({})",
-                    query
-                        .iter()
-                        .enumerate()
-                        .map(|(n, _)| format!("${} <= t{}.id", param_num, n))
-                        .collect::<Vec<_>>()
-                        .join(" OR ")
-                ),
-                fid,
-            )
-        });
-        match cache_clause {
-            Some((clause, fid)) => {
-                use pg::dyn::values::ToValue;
-                restricts.push(clause);
-                vals.push((fid as u64).to_value())
-            }
-            _ => (),
-        }
-        tables.reverse();
-        restricts.reverse();
-        let main_table = tables.pop().ok_or(ErrorKind::Internal(
-            format!("Match clause accesses no tables"),
-        ))?;
-        let join_query = tables
-            .iter()
-            .map(|table| format!("JOIN {} ON true", table))
-            .collect::<Vec<_>>()
-            .join(" ");
-        let where_clause = format!("WHERE {}", restricts.join(" AND "));
-        let raw_stmt = format!(
-            "SELECT {} FROM {} {} {}",
-            vars,
-            main_table,
-            join_query,
-            where_clause
-        );
+        let mut merge_vars = compiled.fact_ids.clone();
+        merge_vars.extend(compiled.var_names.iter().cloned());
+        merge_vars.push("0".to_string());
+        let raw_stmt = compiled.select(&merge_vars.join(", "));
         trace!("search_facts: {}", raw_stmt);
         let conn = self.conn()?;
         let stmt = conn.prepare_cached(&raw_stmt)?;
-        let sql_vals: Vec<_> = vals.iter().flat_map(|x| x.to_sql()).collect();
+        let sql_vals: Vec<_> = compiled.vals.iter().flat_map(|x| x.to_sql()).collect();
         let rows = stmt.query(&sql_vals)?;
         let mut out = Vec::new();
         for row in rows.iter() {
           let mut row_iter = RowIter::new(&row);
           let mut ids = Vec::new();
-          for _ in 0..fact_ids.len() {
+          for _ in 0..compiled.fact_ids.len() {
               match row_iter.next() {
                   Some(e) => ids.push(e),
                   None => panic!("Failure loading fact ids from row"),
               }
           }
           let mut vars = Vec::new();
-          for var_type in var_types.iter() {
+          for var_type in compiled.var_types.iter() {
               match var_type.extract(&mut row_iter) {
                   Some(e) => vars.push(e),
                   None => panic!("Failure loading var from row"),
@@ -776,11 +1181,306 @@ impl PgDB {
         }
         Ok(out)
     }
+
+    /// Matches a query as per `search_facts`, but groups the answers by the
+    /// `group` variables and reduces each `aggs` variable over the group.
+    /// Each returned row holds the group variables followed by the
+    /// aggregates, in the order requested.
+    ///
+    /// Returns `None` if some aggregate other than `Count` is not
+    /// `supported` for its variable's type, in which case the caller should
+    /// reduce the answers itself.
+    pub fn search_grouped(
+        &self,
+        query: &[Clause],
+        group: &[Var],
+        aggs: &[(Aggregate, Var)],
+        supported: &dyn Fn(&Type, Aggregate) -> bool,
+    ) -> Result<Option<Vec<Vec<Value>>>> {
+        let compiled = self.compile_query(query, None)?;
+        for var in group.iter().chain(aggs.iter().map(|(_, var)| var)) {
+            if *var >= compiled.var_names.len() {
+                bail!(ErrorKind::Arg(
+                    format!("Variable {} is not bound by the query", var),
+                ));
+            }
+        }
+        let mut select = Vec::new();
+        let mut out_types = Vec::new();
+        for var in group.iter() {
+            select.push(compiled.var_names[*var].clone());
+            out_types.push(compiled.var_types[*var].clone());
+        }
+        for &(agg, var) in aggs.iter() {
+            let name = &compiled.var_names[var];
+            let type_ = compiled.var_types[var].clone();
+            if agg != Aggregate::Count && !supported(&type_, agg) {
+                return Ok(None);
+            }
+            let (expr, out_type): (String, Type) = match agg {
+                Aggregate::Count => ("count(*)::int8".to_string(), Arc::new(types::UInt64)),
+                Aggregate::Min => (format!("min({})", name), type_),
+                Aggregate::Max => (format!("max({})", name), type_),
+                Aggregate::Sum => (format!("sum({})::int8", name), type_),
+                Aggregate::Collect => (format!("array_agg({})", name), types::List::new(type_)),
+            };
+            select.push(expr);
+            out_types.push(out_type);
+        }
+        let group_by = if group.is_empty() {
+            "".to_string()
+        } else {
+            format!(
+                "GROUP BY {}",
+                group
+                    .iter()
+                    .map(|var| compiled.var_names[*var].clone())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        };
+        // An aggregate with no grouping yields a row even over no answers,
+        // which we don't want to turn into a fact.
+        let raw_stmt = format!(
+            "SELECT {} {} {} HAVING count(*) > 0",
+            select.join(", "),
+            compiled.where_clause(),
+            group_by
+        );
+        trace!("search_grouped: {}", raw_stmt);
+        let conn = self.conn()?;
+        let stmt = conn.prepare_cached(&raw_stmt)?;
+        let sql_vals: Vec<_> = compiled.vals.iter().flat_map(|x| x.to_sql()).collect();
+        let rows = stmt.query(&sql_vals)?;
+        let mut out = Vec::new();
+        for row in rows.iter() {
+            let mut row_iter = RowIter::new(&row);
+            let mut vals = Vec::new();
+            for out_type in out_types.iter() {
+                match out_type.extract(&mut row_iter) {
+                    Some(e) => vals.push(e),
+                    None => panic!("Failure loading aggregate from row"),
+                }
+            }
+            out.push(vals);
+        }
+        Ok(Some(out))
+    }
+}
+
+// Builds a type registry from the default types and the custom ones
+fn register_types(custom_types: Vec<Type>) -> Result<HashMap<String, Type>> {
+    let mut named_types = HashMap::new();
+    for type_ in types::default_types().into_iter().chain(custom_types) {
+        add_named_type(&mut named_types, type_)?;
+    }
+    Ok(named_types)
+}
+
+fn add_named_type(named_types: &mut HashMap<String, Type>, type_: Type) -> Result<()> {
+    let name = type_.name().ok_or(ErrorKind::Arg(
+        "Tried to add a type with no name".to_string(),
+    ))?;
+    if named_types.contains_key(name) {
+        bail!(ErrorKind::Type(format!("{} already registered", name)))
+    }
+    named_types.insert(name.to_owned(), type_.clone());
+    Ok(())
 }
 
-fn valid_name(name: &String) -> bool {
-    name.chars().all(|ch| match ch {
-        'a'...'z' | '_' => true,
-        _ => false,
-    })
+// Records the fields of a predicate, in order
+fn insert_fields(conn: &dyn GenericConnection, pred_id: i32, fields: &[Field]) -> Result<()> {
+    for (ordinal, field) in fields.iter().enumerate() {
+        conn.execute(
+            "insert into fields (pred_id, name, description, type, ordinal, \
+                      join_func) values ($1, $2, $3, $4, $5, $6)",
+            &[
+                &pred_id,
+                &field.name,
+                &field.description,
+                &types::name_of(&*field.type_).ok_or(ErrorKind::Arg(
+                    "Field type had no name".to_string(),
+                ))?,
+                &(ordinal as i32),
+                &field.join,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+// Creates the table holding a predicate's facts, along with the unique index
+// which keeps them distinct
+fn create_fact_table(conn: &dyn GenericConnection, pred: &Predicate) -> Result<()> {
+    let fields = &pred.fields;
+    let table_str = fields
+        .iter()
+        .map(|field| field.type_.repr_on(Backend::Postgres))
+        .enumerate()
+        .map(|(ord, repr)| format!("arg{} {}", ord, repr))
+        .collect::<Vec<_>>()
+        .join(", ");
+    // Lattice fields are merged rather than kept distinct, so only the
+    // other fields take part in uniqueness.
+    let col_str = fields
+        .iter()
+        .map(|field| {
+            (field.type_.large(), field.type_.repr_on(Backend::Postgres).contains("[]"))
+        })
+        .enumerate()
+        .filter(|&(ord, _)| fields[ord].join.is_none())
+        .map(|(ord, (large, is_array))| if large {
+            if is_array {
+                format!("md5(array_to_string(arg{}, ','))", ord)
+            } else {
+                format!("md5(arg{}::text)", ord)
+            }
+        } else {
+            format!("arg{}", ord)
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    // The primary key on id doubles as the index semi-naive searches use
+    // to find facts newer than a rule's cursor
+    conn.execute(
+        &format!(
+            "create table facts.{} (id INT8 DEFAULT nextval('fact_id') NOT \
+                           NULL primary key, {})",
+            pred.name,
+            table_str
+        ),
+        &[],
+    )?;
+    if !col_str.is_empty() {
+        conn.execute(
+            &format!(
+                "create unique index on facts.{} ({})",
+                pred.name,
+                col_str
+            ),
+            &[],
+        )?;
+    }
+    Ok(())
+}
+
+// SQL condition matching the listed slots of a fact table against
+// parameters, numbered from 1 in the order of the slots.
+fn slot_condition(slots: &[usize]) -> String {
+    let mut conds: Vec<String> = slots
+        .iter()
+        .enumerate()
+        .map(|(param, slot)| format!("arg{} = ${}", slot, param + 1))
+        .collect();
+    conds.push("true".to_string());
+    conds.join(" AND ")
+}
+
+// Parameters to go with `slot_condition`
+fn slot_values<'a>(fact: &'a Fact, slots: &[usize]) -> Vec<&'a dyn ToSql> {
+    slots
+        .iter()
+        .flat_map(|slot| fact.args[*slot].to_sql().into_iter())
+        .collect()
+}
+
+impl FactDB for PgDB {
+    fn add_type(&self, type_: Type) -> fact_db::Result<()> {
+        Ok(PgDB::add_type(self, type_)?)
+    }
+    fn get_type(&self, name: &str) -> Option<Type> {
+        PgDB::get_type(self, name)
+    }
+    fn new_predicate(&self, pred: &Predicate) -> fact_db::Result<()> {
+        Ok(PgDB::new_predicate(self, pred)?)
+    }
+    fn get_predicate(&self, pred_name: &str) -> Option<Predicate> {
+        PgDB::get_predicate(self, pred_name)
+    }
+    fn list_predicates(&self) -> Vec<Predicate> {
+        PgDB::list_predicates(self)
+    }
+    fn insert_fact(&self, fact: &Fact) -> fact_db::Result<Option<FactId>> {
+        Ok(PgDB::insert_fact(self, fact)?)
+    }
+    fn search_facts(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> fact_db::Result<Vec<(Vec<FactId>, Vec<Value>)>> {
+        Ok(PgDB::search_facts(self, query, min_fact_id)?)
+    }
+    fn delete_fact(&self, pred_name: &str, id: FactId) -> fact_db::Result<bool> {
+        Ok(PgDB::delete_fact(self, pred_name, id)?)
+    }
+    fn get_fact(&self, pred_name: &str, id: FactId) -> fact_db::Result<Option<Fact>> {
+        Ok(PgDB::get_fact(self, pred_name, id)?)
+    }
+    fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> fact_db::Result<()> {
+        Ok(PgDB::record_provenance(self, fact_id, rule, premises)?)
+    }
+    fn get_provenance(
+        &self,
+        fact_id: FactId,
+    ) -> fact_db::Result<Option<(String, Vec<(String, FactId)>)>> {
+        Ok(PgDB::get_provenance(self, fact_id)?)
+    }
+    fn get_cursor(&self, rule: &str, hash: &str) -> fact_db::Result<Option<FactId>> {
+        Ok(PgDB::get_cursor(self, rule, hash)?)
+    }
+    fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> fact_db::Result<()> {
+        Ok(PgDB::set_cursor(self, rule, hash, cursor)?)
+    }
+    fn create_index(&self, pred_name: &str, slots: &[usize]) -> fact_db::Result<()> {
+        Ok(PgDB::create_index(self, pred_name, slots)?)
+    }
+    fn search_grouped(
+        &self,
+        query: &[Clause],
+        group: &[Var],
+        aggs: &[(Aggregate, Var)],
+        supported: &dyn Fn(&Type, Aggregate) -> bool,
+    ) -> fact_db::Result<Option<Vec<Vec<Value>>>> {
+        Ok(PgDB::search_grouped(self, query, group, aggs, supported)?)
+    }
+    fn replace_fact(&self, fact: &Fact, key: &[usize]) -> fact_db::Result<Option<FactId>> {
+        Ok(PgDB::replace_fact(self, fact, key)?)
+    }
+    fn merge_fact(
+        &self,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Option<FactId>> {
+        Ok(PgDB::merge_fact(self, fact, join)?)
+    }
+    fn store_facts(
+        &self,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(PgDB::store_facts(self, facts, join)?)
+    }
+    fn insert_facts(&self, facts: &[Fact]) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(PgDB::insert_facts(self, facts)?)
+    }
+    fn store_derived(
+        &self,
+        rule: &str,
+        derived: &[(Fact, Vec<(String, FactId)>)],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(PgDB::store_derived(self, rule, derived, join)?)
+    }
+    fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> fact_db::Result<()> {
+        Ok(PgDB::migrate_predicate(self, pred, migrate)?)
+    }
 }
@@ -1,7 +1,7 @@
 //! You likely don't want to use this module - its primary purpose is to make
 //! benchmarking and testing easier to do in practice.
 
-use std::sync::atomic::{ATOMIC_ISIZE_INIT, AtomicIsize};
+use std::sync::atomic::AtomicIsize;
 use std::sync::atomic::Ordering::SeqCst;
 use std::env;
 use url::percent_encoding::{PATH_SEGMENT_ENCODE_SET, percent_encode};
@@ -11,17 +11,20 @@ pub use std::sync::Arc;
 pub use super::pg::dyn::values::ToValue;
 pub use super::pg::dyn::{Type, Value};
 pub use super::pg::dyn::values;
-pub use super::engine::types::{Clause, Fact, MatchExpr, Rule};
+pub use super::engine::types::{Clause, Derivation, Fact, Field, MatchExpr, Migration, Rule};
 
-use super::PgDB;
+use super::FactDB;
+pub use super::PgDB;
+#[cfg(feature = "sqlite")]
+pub use super::SqliteDB;
 
 pub use engine::Result;
 
 pub use tokio_core::reactor::Core;
 
-pub use Engine;
+pub use {Engine, MemDB};
 
-static DB_NUM: AtomicIsize = ATOMIC_ISIZE_INIT;
+static DB_NUM: AtomicIsize = AtomicIsize::new(0);
 
 fn url_encode(input: &[u8]) -> String {
     percent_encode(input, PATH_SEGMENT_ENCODE_SET).to_string()
@@ -46,36 +49,84 @@ fn get_db_addr(db_num: isize) -> String {
     }
 }
 
-static LOGGER: ::std::sync::Once = ::std::sync::ONCE_INIT;
+static LOGGER: ::std::sync::Once = ::std::sync::Once::new();
 
 /// Call a sequence of functions on the database, simulating a program
 /// termination in between each by constructing a fresh `Engine`.
 /// Data is _destroyed_ unless an error occurs.
-pub fn multi<A>(tests: &[&Fn(&mut Engine, &mut Core) -> Result<A>]) {
+#[allow(clippy::type_complexity)]
+pub fn multi<A>(tests: &[&dyn Fn(&mut Engine, &mut Core) -> Result<A>]) {
+    multi_with_types(&[], tests)
+}
+
+/// As `multi`, but registers `types` with the database each time it is
+/// opened, so predicates using them survive the restarts.
+#[allow(clippy::type_complexity)]
+pub fn multi_with_types<A>(types: &[Type], tests: &[&dyn Fn(&mut Engine, &mut Core) -> Result<A>]) {
     LOGGER.call_once(|| env_logger::init().unwrap());
     let db_num = DB_NUM.fetch_add(1, SeqCst);
     let db_addr = get_db_addr(db_num);
     for test in tests {
         let mut core = Core::new().unwrap();
-        let db = PgDB::new(&db_addr).unwrap();
+        let db = PgDB::with_types(&db_addr, types.to_vec()).unwrap();
         let mut holmes = Engine::new(db, core.handle());
         test(&mut holmes, &mut core).unwrap();
     }
     PgDB::destroy(&db_addr).unwrap();
 }
 
+/// Runs a function with the address of a fresh database, for tests which
+/// need to open the database themselves.
+/// Data is _destroyed_ unless an error occurs.
+pub fn with_db_addr<A>(test: &dyn Fn(&str) -> Result<A>) {
+    LOGGER.call_once(|| env_logger::init().unwrap());
+    let db_num = DB_NUM.fetch_add(1, SeqCst);
+    let db_addr = get_db_addr(db_num);
+    test(&db_addr).unwrap();
+    PgDB::destroy(&db_addr).unwrap();
+}
+
 /// Convenience wrapper around `multi` which just runs a single function
 /// Data is _destroyed_ unless an error occurs.
-pub fn single<A>(test: &Fn(&mut Engine, &mut Core) -> Result<A>) {
+pub fn single<A>(test: &dyn Fn(&mut Engine, &mut Core) -> Result<A>) {
     multi(&[test])
 }
 
+/// Runs a function against an `Engine` backed by a fresh `MemDB`, so no
+/// Postgres server is needed.
+pub fn single_mem<A>(test: &dyn Fn(&mut Engine<MemDB>, &mut Core) -> Result<A>) {
+    LOGGER.call_once(|| env_logger::init().unwrap());
+    let mut core = Core::new().unwrap();
+    let mut holmes = Engine::new(MemDB::new(), core.handle());
+    test(&mut holmes, &mut core).unwrap();
+}
+
+/// As `multi`, but backed by a SQLite file in the temporary directory.
+/// The file is _destroyed_ unless an error occurs.
+#[cfg(feature = "sqlite")]
+#[allow(clippy::type_complexity)]
+pub fn multi_sqlite<A>(tests: &[&dyn Fn(&mut Engine<SqliteDB>, &mut Core) -> Result<A>]) {
+    LOGGER.call_once(|| env_logger::init().unwrap());
+    let db_num = DB_NUM.fetch_add(1, SeqCst);
+    let mut path = env::temp_dir();
+    path.push(format!("holmes_test_{}_{}.sqlite", ::std::process::id(), db_num));
+    for test in tests {
+        let mut core = Core::new().unwrap();
+        let db = SqliteDB::new(path.to_str().unwrap()).unwrap();
+        let mut holmes = Engine::new(db, core.handle());
+        test(&mut holmes, &mut core).unwrap();
+    }
+    ::std::fs::remove_file(&path).unwrap();
+}
+
 /// Panics on success, and suppresses an error
-pub fn should_fail<A, F>(f: F) -> Box<Fn(&mut Engine) -> Result<()>>
+#[allow(clippy::type_complexity)]
+pub fn should_fail<DB, A, F>(f: F) -> Box<dyn Fn(&mut Engine<DB>) -> Result<()>>
 where
-    F: 'static + Fn(&mut Engine) -> Result<A>,
+    DB: FactDB + 'static,
+    F: 'static + Fn(&mut Engine<DB>) -> Result<A>,
 {
-    Box::new(move |holmes: &mut Engine| {
+    Box::new(move |holmes: &mut Engine<DB>| {
         match f(holmes) {
             Ok(_) => panic!("should_fail"), //TODO put something more reasonable here?
             Err(_) => Ok(()),
@@ -0,0 +1,263 @@
+// Translation of search queries into SQL, shared by the SQL-backed fact
+// databases. The backends differ only in how fact tables are named and how
+// statement parameters are written, which `Backend` selects.
+
+use std::collections::HashMap;
+
+use engine::types::{Clause, MatchExpr, Predicate};
+use fact_db::FactId;
+use pg::dyn::{Backend, Type, Value};
+use pg::dyn::values::ToValue;
+
+// The pieces of a search query after translation to SQL
+pub struct CompiledQuery {
+    // Tables to select from, already joined together
+    pub from: String,
+    // Conditions which must all hold
+    pub restricts: Vec<String>,
+    // Values for the statement's parameters
+    pub vals: Vec<Value>,
+    // Expressions for the fact id of each positive clause
+    pub fact_ids: Vec<String>,
+    // Canonical expression for each variable
+    pub var_names: Vec<String>,
+    // Type of each variable
+    pub var_types: Vec<Type>,
+    // With a minimum fact id, the extra condition for each query in the
+    // union making up the search, see `select`
+    pub deltas: Vec<String>,
+}
+
+impl CompiledQuery {
+    pub fn where_clause(&self) -> String {
+        format!("FROM {} WHERE {}", self.from, self.restricts.join(" AND "))
+    }
+
+    // Selects `exprs` for each answer. With a minimum fact id, this is
+    // evaluated semi-naively, as a union with a query for each positive
+    // clause, in which that clause matches only new facts and the clauses
+    // before it only old ones. Each answer using a new fact then comes from
+    // exactly one query, and each query can find the new facts of its
+    // clause through the id index rather than rescanning the whole join.
+    pub fn select(&self, exprs: &str) -> String {
+        if self.deltas.is_empty() {
+            return format!("SELECT {} {}", exprs, self.where_clause());
+        }
+        self.deltas
+            .iter()
+            .map(|delta| format!("SELECT {} {} AND {}", exprs, self.where_clause(), delta))
+            .collect::<Vec<_>>()
+            .join(" UNION ALL ")
+    }
+}
+
+// Name of the table holding a predicate's facts
+pub fn fact_table(backend: Backend, pred_name: &str) -> String {
+    match backend {
+        Backend::Postgres => format!("facts.{}", pred_name),
+        Backend::Sqlite => format!("facts_{}", pred_name),
+    }
+}
+
+// Placeholder for the numbered statement parameter, counting from 1
+pub fn param(backend: Backend, num: usize) -> String {
+    match backend {
+        Backend::Postgres => format!("${}", num),
+        Backend::Sqlite => format!("?{}", num),
+    }
+}
+
+// Checks a query and translates it into the pieces of a SQL select.
+// Errors are returned as a message, for the backend to wrap.
+pub fn compile_query(
+    backend: Backend,
+    preds: &HashMap<String, Predicate>,
+    query: &[Clause],
+    min_fact_id: Option<FactId>,
+) -> Result<CompiledQuery, String> {
+    // Check there is at least one clause
+    if query.is_empty() {
+        return Err("Empty search query".to_string());
+    };
+
+    // Negated clauses can only filter, so something must be positive
+    if query.iter().all(|clause| clause.negated) {
+        return Err("Search query has no positive clauses".to_string());
+    }
+
+    // Check that clauses:
+    // * Have sequential variables
+    // * Reference predicates in the database
+    // * Only unify variables of equal type
+    // * Only use variables under negation which are bound positively
+    {
+        let mut var_type: Vec<Type> = Vec::new();
+        let positive = query.iter().filter(|clause| !clause.negated);
+        let negative = query.iter().filter(|clause| clause.negated);
+        for clause in positive.chain(negative) {
+            let pred = match preds.get(&clause.pred_name) {
+                Some(pred) => pred,
+                None => {
+                    return Err(format!(
+                        "{} is not a registered predicate.",
+                        clause.pred_name
+                    ))
+                }
+            };
+            for (n, binding) in clause.args.iter().enumerate() {
+                match *binding {
+                    MatchExpr::Unbound |
+                    MatchExpr::Const(_) => (),
+                    MatchExpr::Var(v) => {
+                        let type_ = pred.fields[n].type_.clone();
+                        if clause.negated && v >= var_type.len() {
+                            return Err(format!(
+                                "Variable {} is only bound under negation in {}",
+                                v,
+                                clause.pred_name
+                            ));
+                        } else if v == var_type.len() {
+                            var_type.push(type_)
+                        } else if v > var_type.len() {
+                            if var_type.is_empty() {
+                                return Err(format!("First variable not Var(0), got Var({})", v));
+                            }
+                            return Err(format!(
+                                "Hole between {} and {} in variable numbering.",
+                                var_type.len() - 1,
+                                v
+                            ));
+                        } else if *var_type[v] != *type_ {
+                            return Err(format!(
+                                "Variable {} attempt to unify incompatible types {:?} and {:?}",
+                                v,
+                                var_type[v],
+                                type_
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Actually build the query
+    let mut tables = Vec::new(); // Predicate names involved in the query,
+    // in the sequence they appear
+    let mut restricts = vec![format!("1 = 1")]; // Unification expressions, indexed by
+    // which join they belong on.
+    let mut var_names = Vec::new(); // Translation of variable numbers to
+    // sql exprs
+    let mut fact_ids = Vec::new(); // Translation of fact ids to sql exprs
+    let mut var_types = Vec::new(); // Translation of variable numbers to
+    // Types
+    let mut vals: Vec<Value> = Vec::new(); // Values to be quoted into the
+    // prepared statement
+    let mut param_num = 1;
+
+    let positive: Vec<&Clause> = query.iter().filter(|clause| !clause.negated).collect();
+    for (idxc, clause) in positive.iter().enumerate() {
+        // The clause refers to a table named by the predicate
+        let table_name = fact_table(backend, &clause.pred_name);
+        // We will refer to it by a numbered alias, to make joining easier
+        let alias_name = format!("t{}", idxc);
+        let pred = &preds[&clause.pred_name];
+        fact_ids.push(format!("{}.id", alias_name));
+        let mut clause_elements = Vec::new();
+        for (n, arg) in clause.args.iter().enumerate() {
+            let proj_str = format!("{}.arg{}", alias_name, n);
+            match *arg {
+                MatchExpr::Unbound => (),
+                MatchExpr::Var(var) => {
+                    if var >= var_names.len() {
+                        // This situation means it's the first occurrence of the variable
+                        // We record this definition as the canonical definition for use
+                        // in the select, and store the type to know how to extract it.
+                        var_names.push(proj_str);
+                        let type_ = pred.fields[n].type_.clone();
+                        var_types.push(type_);
+                    } else {
+                        // The variable has occurred correctly, so we add it being equal
+                        // to the canonical definition to the join clause for this table
+                        let piece = format!("{} = {}", proj_str, var_names[var]);
+                        clause_elements.push(piece);
+                    }
+                }
+                MatchExpr::Const(ref val) => {
+                    // Since we're comparing against a constant, this restriction can
+                    // go in the where clause.
+                    // I stash the value in a buffer for later use with the prepared
+                    // statement, and put the index into the buffer into the where
+                    // clause chunk.
+                    vals.push(val.clone());
+                    restricts.push(format!("{} = {}", proj_str, param(backend, param_num)));
+                    param_num += 1;
+                }
+            }
+        }
+        restricts.extend(clause_elements);
+        tables.push(format!("{} as {}", table_name, alias_name));
+    }
+
+    // Negated clauses become anti-joins. All their variables are already
+    // bound above, so they only ever refer back to canonical definitions.
+    for (idxn, clause) in query.iter().filter(|clause| clause.negated).enumerate() {
+        let alias_name = format!("n{}", idxn);
+        let mut conds = Vec::new();
+        for (n, arg) in clause.args.iter().enumerate() {
+            let proj_str = format!("{}.arg{}", alias_name, n);
+            match *arg {
+                MatchExpr::Unbound => (),
+                MatchExpr::Var(var) => conds.push(format!("{} = {}", proj_str, var_names[var])),
+                MatchExpr::Const(ref val) => {
+                    vals.push(val.clone());
+                    conds.push(format!("{} = {}", proj_str, param(backend, param_num)));
+                    param_num += 1;
+                }
+            }
+        }
+        conds.push("1 = 1".to_string());
+        restricts.push(format!(
+            "NOT EXISTS (SELECT 1 FROM {} as {} WHERE {})",
+            fact_table(backend, &clause.pred_name),
+            alias_name,
+            conds.join(" AND ")
+        ));
+    }
+
+    let mut deltas = Vec::new();
+    if let Some(fid) = min_fact_id {
+        let min = param(backend, param_num);
+        for n in 0..positive.len() {
+            let mut conds: Vec<String> = (0..n).map(|m| format!("t{}.id < {}", m, min)).collect();
+            conds.push(format!("t{}.id >= {}", n, min));
+            deltas.push(conds.join(" AND "));
+        }
+        vals.push((fid as u64).to_value())
+    }
+    tables.reverse();
+    restricts.reverse();
+    let main_table = tables.pop().ok_or_else(
+        || "Match clause accesses no tables".to_string(),
+    )?;
+    let join_query = tables
+        .iter()
+        .map(|table| format!("JOIN {} ON 1 = 1", table))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Ok(CompiledQuery {
+        from: format!("{} {}", main_table, join_query),
+        restricts,
+        vals,
+        fact_ids,
+        var_names,
+        var_types,
+        deltas,
+    })
+}
+
+// Predicate names become table names, so they are restricted to lower case
+// ASCII and underscores
+pub fn valid_name(name: &str) -> bool {
+    name.chars().all(|ch| matches!(ch, 'a'..='z' | '_'))
+}
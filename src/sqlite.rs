@@ -0,0 +1,1074 @@
+//! SQLite-based Fact Database
+//!
+//! `SqliteDB` keeps facts in a single SQLite file, for persistent use without
+//! a database server. It is only available with the `sqlite` feature.
+//!
+//! The layout mirrors `PgDB`: `predicates` and `fields` tables describe the
+//! registered predicates, each predicate gets a `facts_<name>` table, and
+//! fact ids are drawn from a `fact_id` table standing in for a sequence.
+//! Queries are translated to SQL the same way as for Postgres.
+//!
+//! Values of the builtin types are converted to SQLite's storage classes
+//! directly, while lists are stored as JSON text. Other types can be
+//! registered, but facts using them cannot be stored.
+//!
+//! Uniqueness is enforced over hashes of large columns, which relies on the
+//! `holmes_hash` function registered on each connection. Other programs can
+//! read the database, but will be unable to write to such tables.
+
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::collections::hash_map::Entry::{Occupied, Vacant};
+
+use rusqlite::Connection;
+use rusqlite::types::{ToSql, Value as SqlValue};
+use rustc_serialize::hex::{FromHex, ToHex};
+use rustc_serialize::json::{self, Json};
+
+use engine::types::{Clause, Fact, Field, Predicate};
+use fact_db;
+use fact_db::{FactDB, FactId};
+use pg::dyn::types;
+use pg::dyn::values;
+use pg::dyn::{Backend, Type, Value};
+use sql;
+
+#[allow(missing_docs, deprecated)]
+mod errors {
+    use rusqlite;
+    error_chain! {
+        errors {
+            Type(msg: String) {
+                description("Type Error")
+                display("Type Error: {}", msg)
+            }
+            Internal(msg: String) {
+                description("SqliteDB Internal Error")
+                display("SqliteDB Internal Error: {}", msg)
+            }
+            Arg(msg: String) {
+                description("Bad argument")
+                display("Bad argument: {}", msg)
+            }
+        }
+        foreign_links {
+            Db(rusqlite::Error);
+        }
+    }
+}
+
+pub use self::errors::*;
+
+/// Object representing a SQLite-backed fact database instance
+pub struct SqliteDB {
+    conn: Connection,
+    pred_by_name: RefCell<HashMap<String, Predicate>>,
+    named_types: RefCell<HashMap<String, Type>>,
+}
+
+impl SqliteDB {
+    /// Opens the database at `path`, creating it if needed.
+    /// Passing `":memory:"` gives a database which is not persisted.
+    pub fn new(path: &str) -> Result<SqliteDB> {
+        SqliteDB::with_types(path, Vec::new())
+    }
+
+    /// Opens the database at `path`, registering the provided custom types
+    /// alongside the default ones.
+    ///
+    /// Custom types may be used by functions, but SQLite has no way to store
+    /// them, so predicates are limited to the default types and lists of
+    /// them.
+    pub fn with_types(path: &str, custom_types: Vec<Type>) -> Result<SqliteDB> {
+        let mut named_types = HashMap::new();
+        for type_ in types::default_types().into_iter().chain(custom_types) {
+            add_named_type(&mut named_types, type_)?;
+        }
+        let conn = Connection::open(path)?;
+        conn.create_scalar_function("holmes_hash", 1, true, |ctx| {
+            use sha2::*;
+            // Text and blobs hash as their contents. Anything else is
+            // encoded as a tag byte followed by its big-endian bytes, so the
+            // hash doesn't depend on how the value happens to print.
+            let bytes = match ctx.get::<SqlValue>(0)? {
+                SqlValue::Text(text) => text.into_bytes(),
+                SqlValue::Blob(blob) => blob,
+                SqlValue::Integer(n) => tagged(b'i', &n.to_be_bytes()),
+                SqlValue::Real(x) => tagged(b'r', &x.to_bits().to_be_bytes()),
+                SqlValue::Null => vec![b'n'],
+            };
+            let mut hasher = Sha256::default();
+            hasher.input(&bytes);
+            Ok(hasher.result().to_hex())
+        })?;
+
+        // Create Tables
+        conn.execute_batch(
+            "create table if not exists predicates (id integer primary key, \
+                           name text not null, \
+                           description text);
+             create table if not exists fields (\
+                           pred_id integer references predicates(id), \
+                           ordinal integer not null, \
+                           type text not null, \
+                           name text, \
+                           description text, \
+                           join_func text);
+             create table if not exists fact_id (id integer primary key autoincrement);
+             create table if not exists provenance (\
+                           fact_id integer primary key, \
+                           rule text not null, \
+                           premise_preds text not null, \
+                           premises text not null);
+             create table if not exists rule_cursors (\
+                           rule text not null, \
+                           hash text not null, \
+                           cursor integer not null, \
+                           primary key (rule, hash));",
+        )?;
+
+        let db = SqliteDB {
+            conn,
+            pred_by_name: RefCell::new(HashMap::new()),
+            named_types: RefCell::new(named_types),
+        };
+
+        db.rebuild_predicate_cache()?;
+
+        Ok(db)
+    }
+
+    fn rebuild_predicate_cache(&self) -> Result<()> {
+        let mut preds: HashMap<String, Predicate> = HashMap::new();
+        let mut stmt = self.conn.prepare(
+            "select predicates.name, predicates.description, fields.name, \
+             fields.description, fields.type, fields.join_func from predicates \
+             JOIN fields ON predicates.id = fields.pred_id \
+             ORDER BY predicates.id, fields.ordinal",
+        )?;
+        let mut rows = stmt.query(&[])?;
+        let mut missing = Vec::new();
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let name: String = row.get_checked(0)?;
+            let h_type_str: String = row.get_checked(4)?;
+            let h_type = match self.get_type(&h_type_str) {
+                Some(ty) => ty,
+                None => {
+                    if !missing.contains(&h_type_str) {
+                        missing.push(h_type_str);
+                    }
+                    continue;
+                }
+            };
+            let field = Field {
+                name: row.get_checked(2)?,
+                description: row.get_checked(3)?,
+                type_: h_type,
+                join: row.get_checked(5)?,
+            };
+            match preds.entry(name.clone()) {
+                Vacant(entry) => {
+                    entry.insert(Predicate {
+                        name,
+                        description: row.get_checked(1)?,
+                        fields: vec![field],
+                    });
+                }
+                Occupied(mut entry) => {
+                    entry.get_mut().fields.push(field);
+                }
+            }
+        }
+        if !missing.is_empty() {
+            bail!(ErrorKind::Type(format!(
+                "Stored predicates use unregistered types: {}",
+                missing.join(", ")
+            )));
+        }
+        *self.pred_by_name.borrow_mut() = preds;
+        Ok(())
+    }
+
+    // Draws a fresh id from the `fact_id` table
+    fn next_fact_id(&self) -> Result<FactId> {
+        self.conn.execute("insert into fact_id default values", &[])?;
+        let id = self.conn.last_insert_rowid();
+        self.conn.execute("delete from fact_id", &[])?;
+        Ok(id)
+    }
+
+    // Runs `f` inside a savepoint, so that its changes are kept only if it
+    // succeeds. Savepoints nest, so this may be used recursively.
+    fn atomically<T, F: FnOnce() -> Result<T>>(&self, f: F) -> Result<T> {
+        self.conn.execute_batch("savepoint holmes")?;
+        match f() {
+            Ok(out) => {
+                self.conn.execute_batch("release holmes")?;
+                Ok(out)
+            }
+            Err(err) => {
+                self.conn.execute_batch(
+                    "rollback to holmes; release holmes",
+                )?;
+                Err(err)
+            }
+        }
+    }
+
+    // Persist a predicate into the database
+    fn insert_predicate(&self, pred: &Predicate) -> Result<()> {
+        self.conn.execute(
+            "insert into predicates (name, description) values (?1, ?2)",
+            &[&pred.name, &pred.description],
+        )?;
+        let pred_id = self.conn.last_insert_rowid();
+        self.insert_fields(pred_id, &pred.fields)?;
+        self.create_fact_table(pred)
+    }
+
+    // Records the fields of a predicate, in order
+    fn insert_fields(&self, pred_id: i64, fields: &[Field]) -> Result<()> {
+        for (ordinal, field) in fields.iter().enumerate() {
+            let type_name = types::name_of(&*field.type_).ok_or(ErrorKind::Arg(
+                "Field type had no name".to_string(),
+            ))?;
+            self.conn.execute(
+                "insert into fields (pred_id, name, description, type, ordinal, \
+                 join_func) values (?1, ?2, ?3, ?4, ?5, ?6)",
+                &[
+                    &pred_id,
+                    &field.name,
+                    &field.description,
+                    &type_name,
+                    &(ordinal as i64),
+                    &field.join,
+                ],
+            )?;
+        }
+        Ok(())
+    }
+
+    // Creates the table holding a predicate's facts, along with the unique
+    // index which keeps them distinct
+    fn create_fact_table(&self, pred: &Predicate) -> Result<()> {
+        let table_str = pred.fields
+            .iter()
+            .enumerate()
+            .map(|(ord, field)| {
+                format!("arg{} {}", ord, field.type_.repr_on(Backend::Sqlite))
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        // Lattice fields are merged rather than kept distinct, so only the
+        // other fields take part in uniqueness.
+        let col_str = pred.fields
+            .iter()
+            .enumerate()
+            .filter(|&(_, field)| field.join.is_none())
+            .map(|(ord, field)| if field.type_.large() {
+                format!("holmes_hash(arg{})", ord)
+            } else {
+                format!("arg{}", ord)
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let table = sql::fact_table(Backend::Sqlite, &pred.name);
+        // An integer primary key is the rowid, so the id ranges of
+        // semi-naive searches need no index of their own
+        self.conn.execute(
+            &format!(
+                "create table {} (id integer not null primary key, {})",
+                table,
+                table_str
+            ),
+            &[],
+        )?;
+        if !col_str.is_empty() {
+            self.conn.execute(
+                &format!(
+                    "create unique index {}_unique on {} ({})",
+                    table,
+                    table,
+                    col_str
+                ),
+                &[],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Registers a new named type with the database.
+    /// If predicates using this type are stored, it must also be passed to
+    /// `with_types` when reopening.
+    pub fn add_type(&self, type_: Type) -> Result<()> {
+        add_named_type(&mut self.named_types.borrow_mut(), type_)
+    }
+
+    /// Looks for a named type in the database's registry
+    pub fn get_type(&self, type_str: &str) -> Option<Type> {
+        if type_str.starts_with('[') && type_str.ends_with(']') {
+            return self.get_type(&type_str[1..type_str.len() - 1]).map(|elem| {
+                types::List::new(elem) as Type
+            });
+        }
+        self.named_types.borrow().get(type_str).cloned()
+    }
+
+    /// Fetches a predicate by name
+    pub fn get_predicate(&self, pred_name: &str) -> Option<Predicate> {
+        self.pred_by_name.borrow().get(pred_name).cloned()
+    }
+
+    /// Lists all registered predicates, ordered by name
+    pub fn list_predicates(&self) -> Vec<Predicate> {
+        let mut preds: Vec<Predicate> = self.pred_by_name.borrow().values().cloned().collect();
+        preds.sort_by(|a, b| a.name.cmp(&b.name));
+        preds
+    }
+
+    /// Persists a predicate by name.
+    /// As with `PgDB`, the name must consist only of lower case ASCII and _.
+    /// Predicates over custom types fail with `ErrorKind::Type`.
+    pub fn new_predicate(&self, pred: &Predicate) -> Result<()> {
+        if !sql::valid_name(&pred.name) {
+            bail!(ErrorKind::Arg(
+                "Invalid name: Use lowercase and underscores only".to_string(),
+            ));
+        }
+        if let Some(field) = pred.fields.iter().find(|field| !storable(&field.type_)) {
+            bail!(ErrorKind::Type(format!(
+                "{:?} cannot be stored in SQLite, so {} cannot use it",
+                field.type_,
+                pred.name
+            )));
+        }
+        if let Some(existing) = self.pred_by_name.borrow().get(&pred.name) {
+            if existing != pred {
+                bail!(ErrorKind::Arg(format!(
+                    "Predicate {} already registered at a different type.\n\
+                     Existing: {:?}\nNew: {:?}",
+                    &pred.name,
+                    existing,
+                    pred
+                )));
+            }
+            return Ok(());
+        }
+        self.atomically(|| self.insert_predicate(pred))?;
+        self.pred_by_name.borrow_mut().insert(
+            pred.name.clone(),
+            pred.clone(),
+        );
+        Ok(())
+    }
+
+    /// Adds a new fact to the database, returning its id, or `None` if the
+    /// fact was already present
+    pub fn insert_fact(&self, fact: &Fact) -> Result<Option<FactId>> {
+        let id = self.next_fact_id()?;
+        let mut vals = vec![SqlValue::Integer(id)];
+        for arg in fact.args.iter() {
+            vals.push(to_sqlite(arg)?);
+        }
+        let params = (1..vals.len() + 1)
+            .map(|num| sql::param(Backend::Sqlite, num))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let inserted = self.conn
+            .prepare_cached(&format!(
+                "insert or ignore into {} values ({})",
+                sql::fact_table(Backend::Sqlite, &fact.pred_name),
+                params
+            ))?
+            .execute(&sql_params(&vals))?;
+        Ok(if inserted > 0 { Some(id) } else { None })
+    }
+
+    /// Attempt to match the right hand side of a datalog rule against the
+    /// database, returning a list of solution assignments to the bound
+    /// variables.
+    pub fn search_facts(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> Result<Vec<(Vec<FactId>, Vec<Value>)>> {
+        let compiled = sql::compile_query(
+            Backend::Sqlite,
+            &self.pred_by_name.borrow(),
+            query,
+            min_fact_id,
+        ).map_err(ErrorKind::Arg)?;
+        let mut merge_vars = compiled.fact_ids.clone();
+        merge_vars.extend(compiled.var_names.iter().cloned());
+        merge_vars.push("0".to_string());
+        let raw_stmt = compiled.select(&merge_vars.join(", "));
+        trace!("search_facts: {}", raw_stmt);
+        let mut vals = Vec::new();
+        for val in compiled.vals.iter() {
+            vals.push(to_sqlite(val)?);
+        }
+        let mut stmt = self.conn.prepare_cached(&raw_stmt)?;
+        let mut rows = stmt.query(&sql_params(&vals))?;
+        let n_ids = compiled.fact_ids.len();
+        let mut out = Vec::new();
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let mut ids = Vec::new();
+            for col in 0..n_ids {
+                ids.push(row.get_checked(col)?);
+            }
+            let mut vars = Vec::new();
+            for (n, var_type) in compiled.var_types.iter().enumerate() {
+                vars.push(from_sqlite(
+                    var_type,
+                    row.get_checked(n_ids + n)?,
+                )?);
+            }
+            out.push((ids, vars));
+        }
+        Ok(out)
+    }
+
+    /// Removes the fact with the given id, returning false if there was no
+    /// such fact.
+    pub fn delete_fact(&self, pred_name: &str, id: FactId) -> Result<bool> {
+        let deleted = self.conn.execute(
+            &format!(
+                "delete from {} where id = ?1",
+                sql::fact_table(Backend::Sqlite, pred_name)
+            ),
+            &[&id],
+        )?;
+        self.conn.execute(
+            "delete from provenance where fact_id = ?1",
+            &[&id],
+        )?;
+        Ok(deleted > 0)
+    }
+
+    /// Looks up a fact by its id
+    pub fn get_fact(&self, pred_name: &str, id: FactId) -> Result<Option<Fact>> {
+        let pred = match self.get_predicate(pred_name) {
+            Some(pred) => pred,
+            None => return Ok(None),
+        };
+        let cols = (0..pred.fields.len())
+            .map(|slot| format!("arg{}", slot))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "select {} from {} where id = ?1",
+            cols,
+            sql::fact_table(Backend::Sqlite, pred_name)
+        ))?;
+        let mut rows = stmt.query(&[&id])?;
+        let row = match rows.next() {
+            Some(row) => row?,
+            None => return Ok(None),
+        };
+        let mut args = Vec::new();
+        for (slot, field) in pred.fields.iter().enumerate() {
+            args.push(from_sqlite(&field.type_, row.get_checked(slot)?)?);
+        }
+        Ok(Some(Fact {
+            pred_name: pred_name.to_string(),
+            args,
+        }))
+    }
+
+    /// Records that a fact was derived by `rule` from the `premises`. Only the
+    /// first derivation recorded for a fact is kept.
+    pub fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> Result<()> {
+        let preds: Vec<String> = premises.iter().map(|p| p.0.clone()).collect();
+        let ids: Vec<FactId> = premises.iter().map(|p| p.1).collect();
+        let encode_err = |_| ErrorKind::Internal("Failed to encode premises".to_string());
+        self.conn.execute(
+            "insert or ignore into provenance (fact_id, rule, premise_preds, premises) \
+             values (?1, ?2, ?3, ?4)",
+            &[
+                &fact_id,
+                &rule.to_string(),
+                &json::encode(&preds).map_err(&encode_err)?,
+                &json::encode(&ids).map_err(&encode_err)?,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Finds the rule which derived a fact and the premises it used, or
+    /// `None` if the fact was not derived by a rule.
+    #[allow(clippy::type_complexity)]
+    pub fn get_provenance(
+        &self,
+        fact_id: FactId,
+    ) -> Result<Option<(String, Vec<(String, FactId)>)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "select rule, premise_preds, premises from provenance where fact_id = ?1",
+        )?;
+        let mut rows = stmt.query(&[&fact_id])?;
+        let row = match rows.next() {
+            Some(row) => row?,
+            None => return Ok(None),
+        };
+        let decode_err = |_| ErrorKind::Internal("Failed to decode premises".to_string());
+        let preds: Vec<String> = json::decode(&row.get_checked::<usize, String>(1)?)
+            .map_err(&decode_err)?;
+        let ids: Vec<FactId> = json::decode(&row.get_checked::<usize, String>(2)?)
+            .map_err(&decode_err)?;
+        Ok(Some((
+            row.get_checked(0)?,
+            preds.into_iter().zip(ids).collect(),
+        )))
+    }
+
+    /// Looks up how far a rule has got, by its name and the hash of its
+    /// definition
+    pub fn get_cursor(&self, rule: &str, hash: &str) -> Result<Option<FactId>> {
+        let mut stmt = self.conn.prepare_cached(
+            "select cursor from rule_cursors where rule = ?1 and hash = ?2",
+        )?;
+        let mut rows = stmt.query(&[&rule.to_string(), &hash.to_string()])?;
+        match rows.next() {
+            Some(row) => Ok(Some(row?.get_checked(0)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Indexes a predicate's facts by the given slots, unless there is such
+    /// an index already
+    pub fn create_index(&self, pred_name: &str, slots: &[usize]) -> Result<()> {
+        if !self.pred_by_name.borrow().contains_key(pred_name) {
+            bail!(ErrorKind::Arg("Predicate not registered".to_string()));
+        }
+        let table = sql::fact_table(Backend::Sqlite, pred_name);
+        let name: Vec<String> = slots.iter().map(|slot| slot.to_string()).collect();
+        let cols: Vec<String> = slots.iter().map(|slot| format!("arg{}", slot)).collect();
+        self.conn.execute(
+            &format!(
+                "create index if not exists {}_by_{} on {} ({})",
+                table,
+                name.join("_"),
+                table,
+                cols.join(", ")
+            ),
+            &[],
+        )?;
+        Ok(())
+    }
+
+    /// Stores how far a rule has got, or forgets it if `cursor` is `None`
+    pub fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> Result<()> {
+        match cursor {
+            Some(cursor) => {
+                self.conn.execute(
+                    "insert or replace into rule_cursors (rule, hash, cursor) \
+                     values (?1, ?2, ?3)",
+                    &[&rule.to_string(), &hash.to_string(), &cursor],
+                )?
+            }
+            None => {
+                self.conn.execute(
+                    "delete from rule_cursors where rule = ?1 and hash = ?2",
+                    &[&rule.to_string(), &hash.to_string()],
+                )?
+            }
+        };
+        Ok(())
+    }
+
+    /// Stores a fact, replacing any facts which agree with it on the `key`
+    /// slots. Returns `None` if the fact was already present as-is.
+    pub fn replace_fact(&self, fact: &Fact, key: &[usize]) -> Result<Option<FactId>> {
+        self.atomically(|| {
+            let all: Vec<usize> = (0..fact.args.len()).collect();
+            if !self.matching(fact, &all, &[])?.is_empty() {
+                return Ok(None);
+            }
+            self.conn.execute(
+                &format!(
+                    "delete from {} where {}",
+                    sql::fact_table(Backend::Sqlite, &fact.pred_name),
+                    slot_condition(key)
+                ),
+                &sql_params(&slot_values(fact, key)?),
+            )?;
+            self.insert_fact(fact)
+        })
+    }
+
+    /// Stores a fact for a predicate with lattice fields. If a fact agreeing on
+    /// all the non-lattice fields is present, the lattice fields are combined
+    /// using `join`, which receives the slot and the stored and new values,
+    /// and fails if `join` gives `None`.
+    /// Returns `None` if this did not change the stored fact.
+    pub fn merge_fact(
+        &self,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Option<FactId>> {
+        let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        let (lattice, key): (Vec<usize>, Vec<usize>) =
+            (0..pred.fields.len()).partition(|slot| pred.fields[*slot].join.is_some());
+        self.atomically(|| {
+            let old = match self.matching(fact, &key, &lattice)?.pop() {
+                Some(old) => old,
+                None => return self.insert_fact(fact),
+            };
+            let mut merged = fact.clone();
+            let mut changed = false;
+            for (slot, old) in lattice.iter().zip(old) {
+                let joined = match join(*slot, &old, &fact.args[*slot]) {
+                    Some(joined) => joined,
+                    None => bail!(ErrorKind::Arg(
+                        format!("Slot {} of {} cannot be joined", slot, fact.pred_name),
+                    )),
+                };
+                changed |= joined != old;
+                merged.args[*slot] = joined;
+            }
+            if !changed {
+                return Ok(None);
+            }
+            // The merged row takes a fresh id so that rules which have already
+            // seen the old value will see it again.
+            let id = self.next_fact_id()?;
+            let sets = lattice
+                .iter()
+                .enumerate()
+                .map(|(k, slot)| {
+                    format!(", arg{} = {}", slot, sql::param(Backend::Sqlite, key.len() + k + 2))
+                })
+                .collect::<String>();
+            let mut vals = slot_values(fact, &key)?;
+            vals.push(SqlValue::Integer(id));
+            vals.extend(slot_values(&merged, &lattice)?);
+            self.conn.execute(
+                &format!(
+                    "update {} set id = {}{} where {}",
+                    sql::fact_table(Backend::Sqlite, &fact.pred_name),
+                    sql::param(Backend::Sqlite, key.len() + 1),
+                    sets,
+                    slot_condition(&key)
+                ),
+                &sql_params(&vals),
+            )?;
+            Ok(Some(id))
+        })
+    }
+
+    /// Adds several facts in a single savepoint, returning the id of each,
+    /// or `None` for those which were already present. Keeping them in one
+    /// transaction saves SQLite a sync to disk per fact.
+    pub fn insert_facts(&self, facts: &[Fact]) -> Result<Vec<Option<FactId>>> {
+        self.atomically(|| {
+            facts.iter().map(|fact| self.insert_fact(fact)).collect()
+        })
+    }
+
+    /// Stores several facts in a single savepoint, so either all or none
+    /// of them are stored. Facts for predicates with lattice fields are
+    /// merged as per `merge_fact`, with `join` also receiving the fact.
+    pub fn store_facts(
+        &self,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        self.atomically(|| {
+            let mut ids = Vec::new();
+            for fact in facts.iter() {
+                let lattice = match self.pred_by_name.borrow().get(&fact.pred_name) {
+                    Some(pred) => pred.fields.iter().any(|field| field.join.is_some()),
+                    None => bail!(ErrorKind::Arg("Predicate not registered".to_string())),
+                };
+                if lattice {
+                    ids.push(self.merge_fact(
+                        fact,
+                        &|slot, old, new| join(fact, slot, old, new),
+                    )?);
+                } else {
+                    ids.push(self.insert_fact(fact)?);
+                }
+            }
+            Ok(ids)
+        })
+    }
+
+    /// Stores the facts derived by an activation of `rule` and records the
+    /// provenance of the new ones, all in a single savepoint.
+    pub fn store_derived(
+        &self,
+        rule: &str,
+        derived: &[(Fact, Vec<(String, FactId)>)],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> Result<Vec<Option<FactId>>> {
+        let facts: Vec<Fact> = derived.iter().map(|(fact, _)| fact.clone()).collect();
+        self.atomically(|| {
+            let ids = self.store_facts(&facts, join)?;
+            for (id, (_, premises)) in ids.iter().zip(derived.iter()) {
+                if let Some(id) = *id {
+                    self.record_provenance(id, rule, premises)?;
+                }
+            }
+            Ok(ids)
+        })
+    }
+
+    /// Changes the fields of a stored predicate to those of `pred`,
+    /// converting the arguments of each stored fact with `migrate`.
+    /// Facts keep their ids, except that facts which become duplicates of
+    /// earlier ones are dropped along with their provenance.
+    ///
+    /// The field records, fact table and its unique index are all replaced
+    /// in a single transaction.
+    pub fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> Result<()> {
+        let old = self.get_predicate(&pred.name).ok_or_else(|| {
+            ErrorKind::Arg(format!("{} is not a registered predicate", pred.name))
+        })?;
+        let table = sql::fact_table(Backend::Sqlite, &pred.name);
+        self.atomically(|| {
+            let cols = (0..old.fields.len())
+                .map(|slot| format!("arg{}", slot))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut facts = Vec::new();
+            {
+                let mut stmt = self.conn.prepare(
+                    &format!("select id, {} from {} order by id", cols, table),
+                )?;
+                let mut rows = stmt.query(&[])?;
+                while let Some(row) = rows.next() {
+                    let row = row?;
+                    let id: FactId = row.get_checked(0)?;
+                    let mut args = Vec::new();
+                    for (slot, field) in old.fields.iter().enumerate() {
+                        args.push(from_sqlite(
+                            &field.type_,
+                            row.get_checked(slot + 1)?,
+                        )?);
+                    }
+                    match migrate(&args) {
+                        Some(args) => facts.push((id, args)),
+                        None => bail!(ErrorKind::Arg(
+                            format!("Fact {} of {} cannot be migrated", id, pred.name),
+                        )),
+                    }
+                }
+            }
+
+            let pred_id: i64 = self.conn.query_row(
+                "select id from predicates where name = ?1",
+                &[&pred.name],
+                |row| row.get(0),
+            )?;
+            self.conn.execute(
+                "delete from fields where pred_id = ?1",
+                &[&pred_id],
+            )?;
+            self.insert_fields(pred_id, &pred.fields)?;
+            self.conn.execute(&format!("drop table {}", table), &[])?;
+            self.create_fact_table(pred)?;
+
+            let params = (1..pred.fields.len() + 2)
+                .map(|num| sql::param(Backend::Sqlite, num))
+                .collect::<Vec<_>>()
+                .join(", ");
+            let mut stmt = self.conn.prepare(&format!(
+                "insert or ignore into {} values ({})",
+                table,
+                params
+            ))?;
+            for (id, args) in facts {
+                let mut vals = vec![SqlValue::Integer(id)];
+                for arg in args.iter() {
+                    vals.push(to_sqlite(arg)?);
+                }
+                if stmt.execute(&sql_params(&vals))? == 0 {
+                    self.conn.execute(
+                        "delete from provenance where fact_id = ?1",
+                        &[&id],
+                    )?;
+                }
+            }
+            Ok(())
+        })?;
+        // Cached statements may refer to the old layout of the table
+        self.conn.flush_prepared_statement_cache();
+        self.pred_by_name.borrow_mut().insert(
+            pred.name.clone(),
+            pred.clone(),
+        );
+        Ok(())
+    }
+
+    // Fetches the `fetch` slots of the facts agreeing with `fact` on the
+    // `key` slots
+    fn matching(&self, fact: &Fact, key: &[usize], fetch: &[usize]) -> Result<Vec<Vec<Value>>> {
+        let pred = self.get_predicate(&fact.pred_name).ok_or_else(|| {
+            ErrorKind::Arg("Predicate not registered".to_string())
+        })?;
+        let mut cols: Vec<String> = fetch.iter().map(|slot| format!("arg{}", slot)).collect();
+        cols.push("0".to_string());
+        let mut stmt = self.conn.prepare_cached(&format!(
+            "select {} from {} where {}",
+            cols.join(", "),
+            sql::fact_table(Backend::Sqlite, &fact.pred_name),
+            slot_condition(key)
+        ))?;
+        let mut rows = stmt.query(&sql_params(&slot_values(fact, key)?))?;
+        let mut out = Vec::new();
+        while let Some(row) = rows.next() {
+            let row = row?;
+            let mut vals = Vec::new();
+            for (col, slot) in fetch.iter().enumerate() {
+                vals.push(from_sqlite(
+                    &pred.fields[*slot].type_,
+                    row.get_checked(col)?,
+                )?);
+            }
+            out.push(vals);
+        }
+        Ok(out)
+    }
+}
+
+fn add_named_type(named_types: &mut HashMap<String, Type>, type_: Type) -> Result<()> {
+    let name = type_.name().ok_or(ErrorKind::Arg(
+        "Tried to add a type with no name".to_string(),
+    ))?;
+    if named_types.contains_key(name) {
+        bail!(ErrorKind::Type(format!("{} already registered", name)))
+    }
+    named_types.insert(name.to_owned(), type_.clone());
+    Ok(())
+}
+
+// A type tag followed by the given bytes, as hashed by `holmes_hash`
+fn tagged(tag: u8, bytes: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    out.extend_from_slice(bytes);
+    out
+}
+
+// SQL condition matching the listed slots of a fact table against
+// parameters, numbered from 1 in the order of the slots.
+fn slot_condition(slots: &[usize]) -> String {
+    let mut conds: Vec<String> = slots
+        .iter()
+        .enumerate()
+        .map(|(param, slot)| {
+            format!("arg{} = {}", slot, sql::param(Backend::Sqlite, param + 1))
+        })
+        .collect();
+    conds.push("1 = 1".to_string());
+    conds.join(" AND ")
+}
+
+// Parameters to go with `slot_condition`
+fn slot_values(fact: &Fact, slots: &[usize]) -> Result<Vec<SqlValue>> {
+    slots.iter().map(|slot| to_sqlite(&fact.args[*slot])).collect()
+}
+
+fn sql_params(vals: &[SqlValue]) -> Vec<&dyn ToSql> {
+    vals.iter().map(|val| val as &dyn ToSql).collect()
+}
+
+// Whether values of a type have a stored form, as given by `to_sqlite`
+fn storable(type_: &Type) -> bool {
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        return storable(list.elem());
+    }
+    matches!(
+        types::name_of(&**type_).as_deref(),
+        Some("uint64") | Some("bool") | Some("string") | Some("largestring") | Some("bytes") |
+            Some("largebytes")
+    )
+}
+
+// Converts a value to the form it is stored in
+fn to_sqlite(val: &Value) -> Result<SqlValue> {
+    let type_ = val.type_();
+    let name = types::name_of(&*type_);
+    Ok(match name.as_deref() {
+        Some("uint64") => SqlValue::Integer(*val.get().downcast_ref::<u64>().unwrap() as i64),
+        Some("bool") => SqlValue::Integer(*val.get().downcast_ref::<bool>().unwrap() as i64),
+        Some("string") |
+        Some("largestring") => SqlValue::Text(val.get().downcast_ref::<String>().unwrap().clone()),
+        Some("bytes") => SqlValue::Blob(val.get().downcast_ref::<Vec<u8>>().unwrap().clone()),
+        Some("largebytes") => {
+            SqlValue::Text(
+                val.inner()
+                    .downcast_ref::<values::LargeBytes>()
+                    .unwrap()
+                    .hash()
+                    .to_string(),
+            )
+        }
+        _ if type_.inner().downcast_ref::<types::List>().is_some() => {
+            let mut elems = Vec::new();
+            for elem in val.get().downcast_ref::<Vec<Value>>().unwrap().iter() {
+                elems.push(match to_sqlite(elem)? {
+                    SqlValue::Integer(i) => Json::I64(i),
+                    SqlValue::Text(text) => Json::String(text),
+                    SqlValue::Blob(blob) => Json::String(blob.to_hex()),
+                    other => bail!(ErrorKind::Internal(format!("Unexpected list element {:?}", other))),
+                });
+            }
+            SqlValue::Text(Json::Array(elems).to_string())
+        }
+        _ => bail!(ErrorKind::Type(format!("{:?} cannot be stored in SQLite", type_))),
+    })
+}
+
+// Recovers a value of the given type from the form it was stored in
+fn from_sqlite(type_: &Type, stored: SqlValue) -> Result<Value> {
+    if let Some(list) = type_.inner().downcast_ref::<types::List>() {
+        return list_from_sqlite(list.elem(), stored);
+    }
+    let name = types::name_of(&**type_);
+    Ok(match (name.as_deref(), stored) {
+        (Some("uint64"), SqlValue::Integer(i)) => values::UInt64::new(i as u64),
+        (Some("bool"), SqlValue::Integer(i)) => values::Bool::new(i != 0),
+        (Some("string"), SqlValue::Text(text)) |
+        (Some("largestring"), SqlValue::Text(text)) => values::String::new(text),
+        (Some("bytes"), SqlValue::Blob(blob)) => values::Bytes::new(blob),
+        (Some("largebytes"), SqlValue::Text(hash)) => values::LargeBytes::from_hash(&hash),
+        (_, stored) => {
+            bail!(ErrorKind::Type(
+                format!("Cannot load {:?} as {:?}", stored, type_),
+            ))
+        }
+    })
+}
+
+// Lists are stored as a JSON array of their elements' stored forms, with
+// blobs hex encoded
+fn list_from_sqlite(elem_type: &Type, stored: SqlValue) -> Result<Value> {
+    let text = match stored {
+        SqlValue::Text(text) => text,
+        stored => bail!(ErrorKind::Type(format!("Cannot load {:?} as a list", stored))),
+    };
+    let elems = match Json::from_str(&text) {
+        Ok(Json::Array(elems)) => elems,
+        _ => bail!(ErrorKind::Internal(format!("Malformed list {}", text))),
+    };
+    let mut out = Vec::new();
+    for elem in elems {
+        let stored = match (elem_type.repr_on(Backend::Sqlite), elem) {
+            ("integer", Json::I64(i)) => SqlValue::Integer(i),
+            ("integer", Json::U64(i)) => SqlValue::Integer(i as i64),
+            ("text", Json::String(text)) => SqlValue::Text(text),
+            ("blob", Json::String(hex)) => {
+                SqlValue::Blob(hex.from_hex().map_err(|_| {
+                    ErrorKind::Internal(format!("Malformed list {}", text))
+                })?)
+            }
+            _ => bail!(ErrorKind::Internal(format!("Malformed list {}", text))),
+        };
+        out.push(from_sqlite(elem_type, stored)?);
+    }
+    Ok(values::List::of(elem_type.clone(), out))
+}
+
+impl FactDB for SqliteDB {
+    fn add_type(&self, type_: Type) -> fact_db::Result<()> {
+        Ok(SqliteDB::add_type(self, type_)?)
+    }
+    fn get_type(&self, name: &str) -> Option<Type> {
+        SqliteDB::get_type(self, name)
+    }
+    fn new_predicate(&self, pred: &Predicate) -> fact_db::Result<()> {
+        Ok(SqliteDB::new_predicate(self, pred)?)
+    }
+    fn get_predicate(&self, pred_name: &str) -> Option<Predicate> {
+        SqliteDB::get_predicate(self, pred_name)
+    }
+    fn list_predicates(&self) -> Vec<Predicate> {
+        SqliteDB::list_predicates(self)
+    }
+    fn insert_fact(&self, fact: &Fact) -> fact_db::Result<Option<FactId>> {
+        Ok(SqliteDB::insert_fact(self, fact)?)
+    }
+    fn search_facts(
+        &self,
+        query: &[Clause],
+        min_fact_id: Option<FactId>,
+    ) -> fact_db::Result<Vec<(Vec<FactId>, Vec<Value>)>> {
+        Ok(SqliteDB::search_facts(self, query, min_fact_id)?)
+    }
+    fn delete_fact(&self, pred_name: &str, id: FactId) -> fact_db::Result<bool> {
+        Ok(SqliteDB::delete_fact(self, pred_name, id)?)
+    }
+    fn get_fact(&self, pred_name: &str, id: FactId) -> fact_db::Result<Option<Fact>> {
+        Ok(SqliteDB::get_fact(self, pred_name, id)?)
+    }
+    fn record_provenance(
+        &self,
+        fact_id: FactId,
+        rule: &str,
+        premises: &[(String, FactId)],
+    ) -> fact_db::Result<()> {
+        Ok(SqliteDB::record_provenance(self, fact_id, rule, premises)?)
+    }
+    fn get_provenance(
+        &self,
+        fact_id: FactId,
+    ) -> fact_db::Result<Option<(String, Vec<(String, FactId)>)>> {
+        Ok(SqliteDB::get_provenance(self, fact_id)?)
+    }
+    fn get_cursor(&self, rule: &str, hash: &str) -> fact_db::Result<Option<FactId>> {
+        Ok(SqliteDB::get_cursor(self, rule, hash)?)
+    }
+    fn set_cursor(&self, rule: &str, hash: &str, cursor: Option<FactId>) -> fact_db::Result<()> {
+        Ok(SqliteDB::set_cursor(self, rule, hash, cursor)?)
+    }
+    fn create_index(&self, pred_name: &str, slots: &[usize]) -> fact_db::Result<()> {
+        Ok(SqliteDB::create_index(self, pred_name, slots)?)
+    }
+    fn replace_fact(&self, fact: &Fact, key: &[usize]) -> fact_db::Result<Option<FactId>> {
+        Ok(SqliteDB::replace_fact(self, fact, key)?)
+    }
+    fn merge_fact(
+        &self,
+        fact: &Fact,
+        join: &dyn Fn(usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Option<FactId>> {
+        Ok(SqliteDB::merge_fact(self, fact, join)?)
+    }
+    fn store_facts(
+        &self,
+        facts: &[Fact],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(SqliteDB::store_facts(self, facts, join)?)
+    }
+    fn insert_facts(&self, facts: &[Fact]) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(SqliteDB::insert_facts(self, facts)?)
+    }
+    fn store_derived(
+        &self,
+        rule: &str,
+        derived: &[(Fact, Vec<(String, FactId)>)],
+        join: &dyn Fn(&Fact, usize, &Value, &Value) -> Option<Value>,
+    ) -> fact_db::Result<Vec<Option<FactId>>> {
+        Ok(SqliteDB::store_derived(self, rule, derived, join)?)
+    }
+    fn migrate_predicate(
+        &self,
+        pred: &Predicate,
+        migrate: &dyn Fn(&[Value]) -> Option<Vec<Value>>,
+    ) -> fact_db::Result<()> {
+        Ok(SqliteDB::migrate_predicate(self, pred, migrate)?)
+    }
+}
@@ -0,0 +1,89 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn count_blocks() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(block(string, uint64));
+            predicate!(block_count(string, uint64));
+            rule!(count_blocks: block_count(f, {count b}) <= block(f, b));
+            fact!(block("main", 16));
+            fact!(block("main", 32));
+            fact!(block("helper", 64))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, block_count(("main"), n))?,
+            vec![vec![2.to_value()]]
+        );
+        assert_eq!(
+            query!(holmes, block_count(("helper"), n))?,
+            vec![vec![1.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+// New inputs should replace the aggregate fact, not add a second one
+#[test]
+pub fn min_replaced() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(sym(string, uint64));
+            predicate!(sym_min(string, uint64));
+            rule!(min_addr: sym_min(s, {min a}) <= sym(s, a));
+            fact!(sym("foo", 64))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        fact!(holmes, sym("foo", 32))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, sym_min(("foo"), a))?,
+            vec![vec![32.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+// Where clauses force the engine to fold the groups itself
+#[test]
+pub fn sum_where() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(size(string, uint64));
+            predicate!(padded_total(string, uint64));
+            func!(let pad : uint64 -> uint64 = |n: &u64| n + 1);
+            rule!(pad_sum: padded_total(f, {sum p}) <= size(f, n), {
+                let p = {pad([n])}
+            });
+            fact!(size("main", 3));
+            fact!(size("main", 5))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, padded_total(("main"), t))?,
+            vec![vec![10.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn collect_list() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(succs(uint64, [uint64]));
+            rule!(collect_succs: succs(src, {collect dst}) <= edge(src, dst));
+            fact!(edge(1, 2))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, succs((1), l))?,
+            vec![vec![vec![2u64].to_value()]]
+        );
+        Ok(())
+    })
+}
@@ -0,0 +1,126 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use std::rc::Rc;
+use std::cell::Cell;
+
+// The rule should only run once asked, and then only for what was asked
+#[test]
+pub fn only_on_demand() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        let calls = Rc::new(Cell::new(0));
+        let calls_in = calls.clone();
+        holmes_exec!(holmes, {
+            predicate!(num(uint64));
+            predicate!(doubled(uint64, uint64))
+        })?;
+        func!(holmes, let double : uint64 -> uint64 = |n: &u64| {
+            calls_in.set(calls_in.get() + 1);
+            n * 2
+        })?;
+        holmes_exec!(holmes, {
+            rule!(double_num: doubled(n, d) <- num(n), {
+                let d = {double([n])}
+            });
+            fact!(num(1));
+            fact!(num(2));
+            fact!(num(3))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(calls.get(), 0);
+        assert_eq!(
+            query!(holmes, doubled((2), d))?,
+            vec![vec![4.to_value()]]
+        );
+        assert_eq!(calls.get(), 1);
+        query!(holmes, doubled((2), d))?;
+        assert_eq!(calls.get(), 1);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn recursive_goal() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            rule!(reach_edge: reach(x, y) <- edge(x, y));
+            rule!(reach_trans: reach(x, z) <- reach(x, y) & edge(y, z));
+            fact!(edge(1, 2));
+            fact!(edge(2, 3));
+            fact!(edge(4, 5))
+        })?;
+        let mut reached = query!(holmes, reach((1), y))?;
+        reached.sort();
+        assert_eq!(reached, vec![vec![2.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+// Forward rules reading a backward predicate ask for it when they run, and
+// are woken by changes to what the backward rules read.
+#[test]
+pub fn forward_demands() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            predicate!(far(uint64));
+            rule!(find_far: far(y) <= reach((1), y));
+            rule!(reach_edge: reach(x, y) <- edge(x, y));
+            rule!(reach_trans: reach(x, z) <- reach(x, y) & edge(y, z));
+            fact!(edge(1, 2))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, far(y))?, vec![vec![2.to_value()]]);
+        fact!(holmes, edge(2, 3))?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut far = query!(holmes, far(y))?;
+        far.sort();
+        assert_eq!(far, vec![vec![2.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+// While a backward rule is paused, what it reads no longer wakes the rules
+// reading its head, and resuming it links them up again
+#[test]
+pub fn pause_resume_backward() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            predicate!(far(uint64));
+            rule!(find_far: far(y) <= reach((1), y))
+        })?;
+        let reach_edge = rule!(holmes, reach_edge: reach(x, y) <- edge(x, y))?;
+        core.run(holmes.quiesce()).unwrap();
+        let activations = |holmes: &Engine<MemDB>| {
+            holmes
+                .dump_profile()
+                .into_iter()
+                .find(|profile| profile.name == "find_far")
+                .unwrap()
+                .activations
+        };
+        for _ in 0..3 {
+            holmes.pause_rule(reach_edge)?;
+            holmes.resume_rule(reach_edge)?;
+        }
+        holmes.pause_rule(reach_edge)?;
+        let before = activations(holmes);
+        fact!(holmes, edge(1, 2))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(activations(holmes), before);
+        assert_eq!(query!(holmes, far(y))?, Vec::<Vec<Value>>::new());
+
+        holmes.resume_rule(reach_edge)?;
+        fact!(holmes, edge(1, 3))?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut far = query!(holmes, far(y))?;
+        far.sort();
+        assert_eq!(far, vec![vec![2.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
@@ -0,0 +1,63 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+fn edge(src: u64, dst: u64) -> Fact {
+    Fact {
+        pred_name: "edge".to_string(),
+        args: vec![src.to_value(), dst.to_value()],
+    }
+}
+
+#[test]
+pub fn batch_status() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            rule!(base: reach(x, y) <= edge(x, y));
+            fact!(edge(1, 2))
+        })?;
+        let new = holmes.new_facts(&[edge(1, 2), edge(2, 3), edge(3, 4), edge(2, 3)])?;
+        assert_eq!(new, vec![false, true, true, false]);
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, reach(x, y))?.len(), 3);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn batch_large() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, { predicate!(edge(uint64, uint64)) })?;
+        let facts: Vec<Fact> = (0..50000).map(|n| edge(n, n + 1)).collect();
+        assert!(holmes.new_facts(&facts)?.into_iter().all(|new| new));
+        assert_eq!(query!(holmes, edge(x, y))?.len(), 50000);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn batch_type_err() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, { predicate!(edge(uint64, uint64)) })?;
+        let bad = Fact {
+            pred_name: "edge".to_string(),
+            args: vec![1.to_value(), "two".to_value()],
+        };
+        assert!(holmes.new_facts(&[edge(1, 2), bad]).is_err());
+        // Nothing is stored if any fact is ill-typed
+        assert_eq!(query!(holmes, edge(x, y))?.len(), 0);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn batch_mem() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, { predicate!(edge(uint64, uint64)) })?;
+        let new = holmes.new_facts(&[edge(1, 2), edge(1, 2)])?;
+        assert_eq!(new, vec![true, false]);
+        Ok(())
+    })
+}
@@ -16,10 +16,10 @@ pub fn insert() {
 #[test]
 pub fn roundtrip() {
     single(&|holmes: &mut Engine, _| {
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
             predicate!(test_pred(uint64, largebytes));
             fact!(test_pred(3, LargeBWrap { inner: vec![3u8, 3u8] }))
-        }));
+        })?;
         assert_eq!(
             query!(holmes, test_pred((3), x)).unwrap(),
             vec![vec![LargeBWrap { inner: vec![3u8, 3u8] }.to_value()]]
@@ -32,10 +32,10 @@ pub fn roundtrip() {
 #[test]
 pub fn double_query() {
     single(&|holmes: &mut Engine, _| {
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
             predicate!(test_pred(uint64, largebytes));
             fact!(test_pred(3, LargeBWrap { inner: vec![3u8, 3u8] }))
-        }));
+        })?;
         assert_eq!(
             query!(holmes, test_pred((3), x)).unwrap(),
             vec![vec![LargeBWrap { inner: vec![3u8, 3u8] }.to_value()]]
@@ -0,0 +1,54 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn either_body() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(calls(string, string));
+            predicate!(exported(string));
+            predicate!(used(string));
+            rule!(find_used: used(f) <= calls([_], f) | exported(f));
+            fact!(calls("main", "helper"));
+            fact!(exported("api"))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut used = query!(holmes, used(f))?;
+        used.sort();
+        assert_eq!(used, vec![vec!["api".to_value()], vec!["helper".to_value()]]);
+        Ok(())
+    })
+}
+
+// Each alternative may bind variables the others don't use
+#[test]
+pub fn separate_variables() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(weight(uint64, uint64));
+            predicate!(node(uint64));
+            rule!(find_nodes: node(x) <= edge(x, [_]) | weight(w, x) & edge(w, [_]));
+            fact!(edge(1, 2));
+            fact!(weight(1, 7))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut nodes = query!(holmes, node(x))?;
+        nodes.sort();
+        assert_eq!(nodes, vec![vec![1.to_value()], vec![7.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn unbound_head() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(a(uint64));
+            predicate!(b(uint64));
+            predicate!(c(uint64));
+            should_fail(rule!(bad: c(x) <= a(x) | b([_])))
+        })
+    })
+}
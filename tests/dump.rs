@@ -0,0 +1,154 @@
+#[macro_use]
+extern crate holmes;
+extern crate rustc_serialize;
+extern crate sha2;
+use holmes::simple::*;
+use holmes::ErrorKind;
+use holmes::engine::types::Predicate;
+use holmes::pg::dyn::types::Trap;
+use holmes::pg::dyn::values::LargeBWrap;
+use rustc_serialize::hex::ToHex;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+
+fn dump_path(name: &str) -> String {
+    let mut path = env::temp_dir();
+    path.push(format!("holmes_dump_{}_{}.json", ::std::process::id(), name));
+    path.to_str().unwrap().to_string()
+}
+
+#[test]
+pub fn dump_roundtrip() {
+    let path = dump_path("roundtrip");
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(sample(string, bytes, [uint64], bool));
+            predicate!(blob([name string "File name"], largebytes));
+            fact!(sample("a", vec![1u8, 2u8], vec![3u64, 4u64], true));
+            fact!(sample("b", Vec::<u8>::new(), vec![5u64], false));
+            fact!(blob("x.bin", LargeBWrap { inner: vec![7u8; 1024] }))
+        })?;
+        holmes.export(&path)?;
+
+        let mut copy = Engine::new(MemDB::new(), core.handle());
+        assert_eq!(copy.import(&path)?, 3);
+        assert_eq!(
+            copy.get_predicate("blob")?,
+            holmes.get_predicate("blob")?
+        );
+        let mut samples = query!(copy, sample(w, x, y, z))?;
+        samples.sort();
+        assert_eq!(samples, {
+            let mut orig = query!(holmes, sample(w, x, y, z))?;
+            orig.sort();
+            orig
+        });
+        assert_eq!(
+            query!(copy, blob(("x.bin"), x))?,
+            vec![vec![LargeBWrap { inner: vec![7u8; 1024] }.to_value()]]
+        );
+
+        // Importing again adds nothing
+        assert_eq!(copy.import(&path)?, 0);
+        Ok(())
+    });
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn dump_type_check() {
+    let path = dump_path("type_check");
+    File::create(&path)
+        .unwrap()
+        .write_all(
+            br#"{"format": "holmes-dump", "version": 1, "blobs": {},
+                 "predicates": [{"name": "num", "description": null,
+                                 "fields": [{"name": null, "description": null,
+                                             "type": "uint64", "join": null}]}],
+                 "facts": {"num": [[1], ["two"]]}}"#,
+        )
+        .unwrap();
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        match *holmes.import(&path).unwrap_err().kind() {
+            ErrorKind::Type(_) => (),
+            ref other => panic!("Expected a type error, got {:?}", other),
+        }
+        // Nothing was registered, not even the well typed fact
+        assert_eq!(holmes.get_predicate("num")?, None);
+        Ok(())
+    });
+    fs::remove_file(&path).unwrap();
+}
+
+// A dump which fails its checks leaves nothing behind, not even the contents
+// of its large values
+#[test]
+pub fn dump_blobs_after_check() {
+    let path = dump_path("blobs_after_check");
+    let contents = format!("blob for {}", ::std::process::id()).into_bytes();
+    let mut hasher = Sha256::default();
+    hasher.input(&contents);
+    let hash = hasher.result().to_hex();
+    File::create(&path)
+        .unwrap()
+        .write_all(
+            format!(
+                r#"{{"format": "holmes-dump", "version": 1,
+                    "blobs": {{"{hash}": "{hex}"}},
+                    "predicates": [{{"name": "blob", "description": null,
+                                     "fields": [{{"name": null, "description": null,
+                                                  "type": "largebytes", "join": null}}]}},
+                                   {{"name": "num", "description": null,
+                                     "fields": [{{"name": null, "description": null,
+                                                  "type": "uint64", "join": null}}]}}],
+                    "facts": {{"blob": [["{hash}"]], "num": [["two"]]}}}}"#,
+                hash = hash,
+                hex = contents.to_hex()
+            ).as_bytes(),
+        )
+        .unwrap();
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        match *holmes.import(&path).unwrap_err().kind() {
+            ErrorKind::Type(_) => (),
+            ref other => panic!("Expected a type error, got {:?}", other),
+        }
+        Ok(())
+    });
+    let mut stored = match env::var("HOLMES_STORAGE") {
+        Ok(dir) => ::std::path::PathBuf::from(dir),
+        _ => {
+            let mut dir = env::home_dir().unwrap();
+            dir.push(".holmes");
+            dir
+        }
+    };
+    stored.push(&hash);
+    assert!(!stored.exists());
+    fs::remove_file(&path).unwrap();
+}
+
+#[test]
+pub fn dump_custom_type() {
+    let path = dump_path("custom_type");
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes.new_predicate(&Predicate {
+            name: "custom".to_string(),
+            description: None,
+            fields: vec![
+                Field {
+                    name: None,
+                    description: None,
+                    type_: Trap::new(),
+                    join: None,
+                },
+            ],
+        })?;
+        match *holmes.export(&path).unwrap_err().kind() {
+            ErrorKind::Type(ref msg) => assert!(msg.contains("custom")),
+            ref other => panic!("Expected a type error, got {:?}", other),
+        }
+        Ok(())
+    });
+}
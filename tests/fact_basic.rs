@@ -25,10 +25,10 @@ pub fn new_fact_type_err() {
 #[test]
 pub fn new_fact_echo() {
     single(&|holmes: &mut Engine, _| {
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
             predicate!(test_pred(string, bytes, uint64));
             fact!(test_pred("foo", vec![3u8, 3u8], 7))
-        }));
+        })?;
         assert_eq!(query!(holmes,
                        test_pred(("foo"), [_], x)).unwrap(),
                vec![vec![7.to_value()]]);
@@ -45,3 +45,20 @@ pub fn two_strings() {
         })
     })
 }
+
+#[test]
+pub fn empty_list() {
+    single(&|holmes: &mut Engine, _| {
+        use holmes::pg::dyn::types;
+        holmes_exec!(holmes, {
+            predicate!(test_pred(string, [string]))
+        })?;
+        let empty = values::List::of(Arc::new(types::String), vec![]) as Value;
+        holmes.new_fact(&Fact {
+            pred_name: "test_pred".to_string(),
+            args: vec!["foo".to_value(), empty.clone()],
+        })?;
+        assert_eq!(query!(holmes, test_pred(("foo"), x))?, vec![vec![empty]]);
+        Ok(())
+    })
+}
@@ -8,7 +8,7 @@ pub fn reg_func() {
         func!(holmes,
       let test_func : uint64 -> uint64 =
         |_v : &u64| {
-          42 as u64
+          42_u64
         })
     })
 }
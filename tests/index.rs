@@ -0,0 +1,87 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::engine::IndexUse;
+
+#[test]
+pub fn index_report() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(path(uint64, uint64));
+            predicate!(label(uint64, string));
+            // Looks up path by its second slot, and edge by its first, which
+            // the unique index already covers
+            rule!(path_step: path(x, z) <= path(x, y) & edge(y, z))
+        })?;
+        let root_edge = rule!(holmes, root_edge: path(x, y) <= edge(x, y) & label(y, ("root")))?;
+        assert_eq!(
+            holmes.index_report(),
+            vec![
+                IndexUse {
+                    pred_name: "edge".to_string(),
+                    slots: vec![1],
+                    rules: vec!["root_edge".to_string()],
+                },
+                IndexUse {
+                    pred_name: "path".to_string(),
+                    slots: vec![1],
+                    rules: vec!["path_step".to_string()],
+                },
+            ]
+        );
+        holmes.remove_rule(root_edge)?;
+        assert_eq!(
+            holmes.index_report()[0].to_string(),
+            "edge(1) used by no rules"
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn index_search() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(path(uint64, uint64));
+            fact!(edge(1, 2));
+            fact!(edge(2, 3));
+            rule!(path_base: path(x, y) <= edge(x, y));
+            rule!(path_step: path(x, z) <= path(x, y) & edge(y, z))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(holmes.index_report().len(), 1);
+        let mut paths = query!(holmes, path((1), x))?;
+        paths.sort();
+        assert_eq!(paths, vec![vec![2.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+// A rule which is refused must not leave indexes behind
+#[test]
+pub fn index_refused() {
+    with_db_addr(&|addr| {
+        let core = Core::new().unwrap();
+        let holmes = &mut Engine::new(PgDB::new(addr).unwrap(), core.handle());
+        holmes_exec!(holmes, {
+            predicate!(win(string));
+            predicate!(move_to(string, string));
+            should_fail(rule!(win_move: win(x) <= move_to(x, y) & move_to(z, y) & not(win(y))))
+        })?;
+        assert!(holmes.index_report().is_empty());
+        let indexes = PgDB::new(addr)
+            .unwrap()
+            .conn()
+            .unwrap()
+            .query(
+                "select indexname from pg_indexes where tablename = 'move_to' \
+                 and indexname like '%_by_%'",
+                &[],
+            )
+            .unwrap();
+        assert!(indexes.is_empty());
+        Ok(())
+    })
+}
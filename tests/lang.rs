@@ -0,0 +1,136 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::ErrorKind;
+use holmes::lang;
+
+#[test]
+pub fn lang_program() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        let queries = holmes.load_program(
+            r#"
+            // A small reachability analysis
+            predicate edge(src: uint64 "Source node", dst: uint64) "Graph edges".
+            predicate path(uint64, uint64).
+            edge(1, 2).
+            edge(2, 3).
+            edge(3, 4).
+            path_base: path(x, y) <= edge(x, y).
+            path_step: path(x, z) <= path(x, y) & edge(y, z).
+            ?- path(1, x).
+            "#,
+        )?;
+        core.run(holmes.quiesce()).unwrap();
+        let edge = holmes.get_predicate("edge")?.unwrap();
+        assert_eq!(edge.description, Some("Graph edges".to_string()));
+        assert_eq!(edge.fields[0].name, Some("src".to_string()));
+        assert_eq!(edge.fields[0].description, Some("Source node".to_string()));
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].vars, vec!["x".to_string()]);
+        let mut reach = holmes.derive(&queries[0].clauses)?;
+        reach.sort();
+        assert_eq!(
+            reach,
+            vec![
+                vec![2.to_value()],
+                vec![3.to_value()],
+                vec![4.to_value()],
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn lang_where_aggregate() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        func!(holmes, let split : string -> [string] = |s: &String| {
+            s.split(',').map(|w| w.to_string()).collect::<Vec<_>>()
+        })?;
+        let queries = holmes.load_program(
+            r#"
+            predicate names(uint64, string).
+            predicate name_count(id: uint64, count: uint64).
+            names(1, "a,b,c").
+            names(2, "d").
+            counted: name_count(id, count(n)) <= names(id, all) where [n] = split(all).
+            ?- name_count(x, y).
+            "#,
+        )?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut counts = holmes.derive(&queries[0].clauses)?;
+        counts.sort();
+        assert_eq!(
+            counts,
+            vec![
+                vec![1.to_value(), 3.to_value()],
+                vec![2.to_value(), 1.to_value()],
+            ]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn lang_matches_edsl() {
+    let stmts = lang::parse(
+        "split: lo(x), hi(y) <= pair(x, y, _) & not(skip(x)) | pair(y, x, \"rev\").",
+        &|_| None,
+    ).unwrap();
+    let x = MatchExpr::Var(0);
+    let y = MatchExpr::Var(1);
+    let clause = |name: &str, args: Vec<MatchExpr>, negated| {
+        Clause {
+            pred_name: name.to_string(),
+            args,
+            negated,
+        }
+    };
+    assert_eq!(
+        stmts,
+        vec![
+            lang::Statement::Rule(Rule {
+                name: "split".to_string(),
+                head: vec![
+                    clause("lo", vec![x.clone()], false),
+                    clause("hi", vec![y.clone()], false),
+                ],
+                body: vec![
+                    clause("pair", vec![x.clone(), y.clone(), MatchExpr::Unbound], false),
+                    clause("skip", vec![x.clone()], true),
+                ],
+                alternatives: vec![
+                    vec![
+                        clause(
+                            "pair",
+                            vec![y.clone(), x.clone(), MatchExpr::Const("rev".to_value())],
+                            false,
+                        ),
+                    ],
+                ],
+                wheres: vec![],
+                aggregates: vec![],
+                backward: false,
+            }),
+        ]
+    );
+}
+
+#[test]
+pub fn lang_errors() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        {
+            let mut position = |src: &str| match *holmes.load_program(src).unwrap_err().kind() {
+                ErrorKind::Lang(lang::ErrorKind::Syntax(line, column, _)) => (line, column),
+                ref other => panic!("Expected a syntax error, got {:?}", other),
+            };
+            assert_eq!(position("predicate p(uint64).\np(1 2)."), (2, 5));
+            assert_eq!(position("predicate q(widget)."), (1, 13));
+            assert_eq!(position("q(\"open"), (1, 3));
+            assert_eq!(position("r: out(x) <= in(x)\n  where y == f(x)."), (2, 12));
+        }
+        // Nothing was registered, since each program failed to parse
+        assert_eq!(holmes.get_predicate("p")?, None);
+        Ok(())
+    })
+}
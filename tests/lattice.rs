@@ -0,0 +1,90 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn merge_max() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(bound(string, [hi uint64 join max_join]));
+            func!(let max_join : (uint64, uint64) -> uint64 = |(a, b): (&u64, &u64)| {
+                ::std::cmp::max(*a, *b)
+            });
+            fact!(bound("x", 3));
+            fact!(bound("x", 7));
+            fact!(bound("x", 5));
+            fact!(bound("y", 2))
+        })?;
+        assert_eq!(
+            query!(holmes, bound(("x"), hi))?,
+            vec![vec![7.to_value()]]
+        );
+        assert_eq!(
+            query!(holmes, bound(("y"), hi))?,
+            vec![vec![2.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+// Raising the bound should wake rules depending on it
+#[test]
+pub fn merge_wakes() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(bound(string, [hi uint64 join max_join]));
+            predicate!(seen(string, uint64));
+            func!(let max_join : (uint64, uint64) -> uint64 = |(a, b): (&u64, &u64)| {
+                ::std::cmp::max(*a, *b)
+            });
+            rule!(see_bound: seen(v, hi) <= bound(v, hi));
+            fact!(bound("x", 3))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        fact!(holmes, bound("x", 7))?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut seen = query!(holmes, seen(("x"), hi))?;
+        seen.sort();
+        assert_eq!(seen, vec![vec![3.to_value()], vec![7.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn missing_join() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(bound(string, [hi uint64 join max_join]));
+            fact!(bound("x", 3));
+            should_fail(fact!(bound("x", 7)))
+        })
+    })
+}
+
+// A database made before fields could have joins should still open, and
+// take lattice predicates
+#[test]
+pub fn old_schema() {
+    with_db_addr(&|addr| {
+        {
+            let core = Core::new().unwrap();
+            let holmes = Engine::new(PgDB::new(addr).unwrap(), core.handle());
+            predicate!(holmes, plain(string, uint64))?;
+        }
+        PgDB::new(addr)
+            .unwrap()
+            .conn()
+            .unwrap()
+            .execute("alter table fields drop column join_func", &[])
+            .unwrap();
+        let core = Core::new().unwrap();
+        let holmes = Engine::new(PgDB::new(addr).unwrap(), core.handle());
+        assert!(holmes.get_predicate("plain")?.is_some());
+        predicate!(holmes, bound(string, [hi uint64 join max_join]))?;
+        assert_eq!(
+            holmes.get_predicate("bound")?.unwrap().fields[1].join,
+            Some("max_join".to_string())
+        );
+        Ok(())
+    })
+}
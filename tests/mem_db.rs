@@ -0,0 +1,89 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn mem_rule() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            rule!(reach_edge: reach(x, y) <= edge(x, y));
+            rule!(reach_trans: reach(x, z) <= reach(x, y) & edge(y, z));
+            fact!(edge(1, 2));
+            fact!(edge(2, 3));
+            fact!(edge(4, 5))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut reached = query!(holmes, reach((1), y))?;
+        reached.sort();
+        assert_eq!(reached, vec![vec![2.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn mem_negation() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(func(string));
+            predicate!(calls(string, string));
+            fact!(func("main"));
+            fact!(func("helper"));
+            fact!(calls("main", "helper"))
+        })?;
+        assert_eq!(
+            query!(holmes, func(f) & not(calls([_], f)))?,
+            vec![vec!["main".to_value()]]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn mem_lattice() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(bound(string, [hi uint64 join max_join]));
+            func!(let max_join : (uint64, uint64) -> uint64 = |(a, b): (&u64, &u64)| {
+                ::std::cmp::max(*a, *b)
+            });
+            fact!(bound("x", 3));
+            fact!(bound("x", 7));
+            fact!(bound("x", 5))
+        })?;
+        assert_eq!(
+            query!(holmes, bound(("x"), hi))?,
+            vec![vec![7.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn mem_retract() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            rule!(reach_edge: reach(x, y) <= edge(x, y));
+            fact!(edge(1, 2));
+            fact!(edge(1, 3))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert!(retract!(holmes, edge(1, 2))?);
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, reach((1), y))?, vec![vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn mem_type_mismatch() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(count(string, uint64));
+            should_fail(fact!(count(3, "three")))
+        })
+    })
+}
@@ -0,0 +1,123 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::ErrorKind;
+
+#[test]
+pub fn migrate_add_rename() {
+    multi(&[
+        &|holmes: &mut Engine, _| {
+            holmes_exec!(holmes, {
+                predicate!(size(string, uint64));
+                fact!(size("a", 1));
+                fact!(size("b", 2))
+            })?;
+            let unit = Field {
+                name: Some("unit".to_string()),
+                description: None,
+                type_: holmes.get_type("string").unwrap(),
+                join: None,
+            };
+            holmes.migrate_predicate(
+                "size",
+                &[
+                    Migration::RenameField(1, Some("bytes".to_string())),
+                    Migration::AddField(unit, "B".to_value()),
+                ],
+            )?;
+            fact!(holmes, size("c", 3, "KB"))
+        },
+        &|holmes: &mut Engine, _| {
+            let pred = holmes.get_predicate("size")?.unwrap();
+            assert_eq!(pred.fields.len(), 3);
+            assert_eq!(pred.fields[1].name, Some("bytes".to_string()));
+            let mut sizes = query!(holmes, size(name, n, unit))?;
+            sizes.sort();
+            assert_eq!(
+                sizes,
+                vec![
+                    vec!["a".to_value(), 1.to_value(), "B".to_value()],
+                    vec!["b".to_value(), 2.to_value(), "B".to_value()],
+                    vec!["c".to_value(), 3.to_value(), "KB".to_value()],
+                ]
+            );
+            Ok(())
+        },
+    ])
+}
+
+#[test]
+pub fn migrate_rewrite_drop() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            func!(let order : (uint64, uint64) -> (uint64, uint64) = |(a, b): (&u64, &u64)| {
+                (::std::cmp::min(*a, *b), ::std::cmp::max(*a, *b))
+            });
+            fact!(edge(1, 2));
+            fact!(edge(2, 1));
+            fact!(edge(3, 3))
+        })?;
+        holmes.migrate_predicate("edge", &[Migration::Rewrite("order".to_string())])?;
+        assert_eq!(query!(holmes, edge(x, y))?.len(), 2);
+        holmes.migrate_predicate("edge", &[Migration::DropField(1)])?;
+        let mut srcs = query!(holmes, edge(x))?;
+        srcs.sort();
+        assert_eq!(srcs, vec![vec![1.to_value()], vec![3.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn migrate_refused() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(src(uint64));
+            predicate!(dst(uint64));
+            rule!(copy: dst(x) <= src(x))
+        })?;
+        assert!(
+            holmes
+                .migrate_predicate("src", &[Migration::DropField(0)])
+                .is_err()
+        );
+        assert!(holmes.migrate_predicate("missing", &[]).is_err());
+        let flag = Field {
+            name: None,
+            description: None,
+            type_: holmes.get_type("bool").unwrap(),
+            join: None,
+        };
+        holmes_exec!(holmes, { predicate!(lone(uint64)) })?;
+        assert!(
+            holmes
+                .migrate_predicate("lone", &[Migration::AddField(flag, 3.to_value())])
+                .is_err()
+        );
+        Ok(())
+    })
+}
+
+// A rewrite returning the wrong shape is a type error, and changes nothing
+#[test]
+pub fn migrate_rewrite_lies() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            func!(let lie : (uint64, uint64) -> (uint64, uint64) = |(a, _): (&u64, &u64)| *a);
+            fact!(edge(1, 2))
+        })?;
+        let before = holmes.get_predicate("edge")?;
+        let res = holmes.migrate_predicate("edge", &[Migration::Rewrite("lie".to_string())]);
+        match *res.unwrap_err().kind() {
+            ErrorKind::Type(_) => (),
+            ref other => panic!("Expected a type error, got {:?}", other),
+        }
+        assert_eq!(holmes.get_predicate("edge")?, before);
+        assert_eq!(
+            query!(holmes, edge(x, y))?,
+            vec![vec![1.to_value(), 2.to_value()]]
+        );
+        Ok(())
+    })
+}
@@ -0,0 +1,51 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn two_heads() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(pair(uint64, string));
+            predicate!(num(uint64));
+            predicate!(name(string));
+            rule!(split: num(x), name(y) <= pair(x, y));
+            fact!(pair(3, "three"))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, num(x))?, vec![vec![3.to_value()]]);
+        assert_eq!(query!(holmes, name(y))?, vec![vec!["three".to_value()]]);
+        Ok(())
+    })
+}
+
+// Rules reading either head should be woken
+#[test]
+pub fn heads_wake_readers() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(pair(uint64, uint64));
+            predicate!(lo(uint64));
+            predicate!(hi(uint64));
+            predicate!(hi_copy(uint64));
+            rule!(copy_hi: hi_copy(y) <= hi(y));
+            rule!(split: lo(x), hi(y) <= pair(x, y));
+            fact!(pair(1, 2))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, hi_copy(y))?, vec![vec![2.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn aggregate_two_heads() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(block(string, uint64));
+            predicate!(block_count(string, uint64));
+            predicate!(func(string));
+            should_fail(rule!(bad: block_count(f, {count b}), func(f) <= block(f, b)))
+        })
+    })
+}
@@ -0,0 +1,85 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+#[test]
+pub fn negated_query() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(func(string));
+            predicate!(calls(string, string));
+            fact!(func("main"));
+            fact!(func("helper"));
+            fact!(calls("main", "helper"))
+        })?;
+        assert_eq!(
+            query!(holmes, func(f) & not(calls([_], f)))?,
+            vec![vec!["main".to_value()]]
+        );
+        Ok(())
+    })
+}
+
+// The negated predicate is produced by a rule registered after the negating
+// rule, so the negating rule must wait for it to finish.
+#[test]
+pub fn negated_rule_waits() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(func(string));
+            predicate!(call_site(string, string));
+            predicate!(calls(string, string));
+            predicate!(no_callers(string));
+            rule!(find_roots: no_callers(f) <= func(f) & not(calls([_], f)));
+            rule!(find_calls: calls(src, dst) <= call_site(src, dst));
+            fact!(func("main"));
+            fact!(func("helper"));
+            fact!(call_site("main", "helper"))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, no_callers(f))?,
+            vec![vec!["main".to_value()]]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn unstratifiable() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(win(string));
+            predicate!(move_to(string, string));
+            should_fail(rule!(win_move: win(x) <= move_to(x, y) & not(win(y))))
+        })
+    })
+}
+
+// The negated predicate is only reached through intermediate predicates, so
+// the negating rule must wait for the whole chain, not just the rule which
+// produces it directly.
+#[test]
+pub fn negated_rule_waits_transitively() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(base(string));
+            predicate!(a(string));
+            predicate!(b(string));
+            predicate!(c(string));
+            predicate!(d(string));
+            rule!(make_c: c(x) <= base(x) & not(d(x)));
+            rule!(make_a: a(x) <= base(x));
+            rule!(make_b: b(x) <= a(x));
+            rule!(make_d: d(x) <= b(x))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        holmes_exec!(holmes, {
+            fact!(base("x"))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, d(x))?, vec![vec!["x".to_value()]]);
+        assert_eq!(query!(holmes, c(x))?, Vec::<Vec<Value>>::new());
+        Ok(())
+    })
+}
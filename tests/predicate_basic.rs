@@ -30,3 +30,22 @@ pub fn double_register_compat() {
         })
     })
 }
+
+#[test]
+pub fn list_predicates() {
+    multi(&[
+        &|holmes: &mut Engine, _| {
+            holmes_exec!(holmes, {
+                predicate!(zeta(uint64));
+                predicate!(alpha(string, [name string "Who it is"]))
+            })
+        },
+        &|holmes: &mut Engine, _| {
+            let preds = holmes.list_predicates()?;
+            let names: Vec<&str> = preds.iter().map(|pred| pred.name.as_str()).collect();
+            assert_eq!(names, vec!["alpha", "zeta"]);
+            assert_eq!(preds[0].fields[1].description, Some("Who it is".to_string()));
+            Ok(())
+        },
+    ])
+}
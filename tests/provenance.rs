@@ -0,0 +1,68 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+fn fact(pred_name: &str, args: Vec<Value>) -> Fact {
+    Fact {
+        pred_name: pred_name.to_string(),
+        args,
+    }
+}
+
+fn stored(fact: Fact) -> Derivation {
+    Derivation {
+        fact,
+        rule: None,
+        premises: vec![],
+    }
+}
+
+#[test]
+pub fn explain_chain() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(reach(uint64, uint64));
+            rule!(reach_edge: reach(x, y) <= edge(x, y));
+            rule!(reach_trans: reach(x, z) <= reach(x, y) & edge(y, z));
+            fact!(edge(1, 2));
+            fact!(edge(2, 3))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        let edge_1_2 = fact("edge", vec![1.to_value(), 2.to_value()]);
+        let edge_2_3 = fact("edge", vec![2.to_value(), 3.to_value()]);
+        let reach_1_2 = fact("reach", vec![1.to_value(), 2.to_value()]);
+        let reach_1_3 = fact("reach", vec![1.to_value(), 3.to_value()]);
+        assert_eq!(
+            holmes.explain(&reach_1_3)?,
+            Some(Derivation {
+                fact: reach_1_3,
+                rule: Some("reach_trans".to_string()),
+                premises: vec![
+                    Derivation {
+                        fact: reach_1_2,
+                        rule: Some("reach_edge".to_string()),
+                        premises: vec![stored(edge_1_2)],
+                    },
+                    stored(edge_2_3.clone()),
+                ],
+            })
+        );
+        assert_eq!(holmes.explain(&edge_2_3)?, Some(stored(edge_2_3)));
+        Ok(())
+    })
+}
+
+#[test]
+pub fn explain_absent() {
+    single(&|holmes: &mut Engine, _| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64))
+        })?;
+        assert_eq!(
+            holmes.explain(&fact("edge", vec![1.to_value(), 2.to_value()]))?,
+            None
+        );
+        Ok(())
+    })
+}
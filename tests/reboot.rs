@@ -1,6 +1,9 @@
 #[macro_use]
 extern crate holmes;
 use holmes::simple::*;
+use holmes::pg::dyn::values::LargeBWrap;
+use std::cell::Cell;
+use std::rc::Rc;
 
 #[test]
 fn simple() {
@@ -68,3 +71,106 @@ fn fact_preserve() {
         ],
     )
 }
+
+// Registers `mark`, which counts how often it is called
+fn counted_mark(holmes: &mut Engine) -> Result<Rc<Cell<usize>>> {
+    let calls = Rc::new(Cell::new(0));
+    let counter = calls.clone();
+    func!(holmes, let mark: uint64 -> uint64 = |x: &u64| {
+        counter.set(counter.get() + 1);
+        *x
+    })?;
+    Ok(calls)
+}
+
+#[test]
+fn rule_resume() {
+    multi(
+        &[
+            &|holmes: &mut Engine, core: &mut Core| {
+                let calls = counted_mark(holmes)?;
+                holmes_exec!(holmes, {
+                    predicate!(src(uint64));
+                    predicate!(dst(uint64));
+                    fact!(src(1));
+                    fact!(src(2));
+                    rule!(copy: dst(y) <= src(x), {
+                        let y = {mark([x])}
+                    })
+                })?;
+                core.run(holmes.quiesce()).unwrap();
+                assert_eq!(calls.get(), 2);
+                Ok(())
+            },
+            // The rule is unchanged, so only the new fact is looked at
+            &|holmes: &mut Engine, core: &mut Core| {
+                let calls = counted_mark(holmes)?;
+                holmes_exec!(holmes, {
+                    predicate!(src(uint64));
+                    predicate!(dst(uint64));
+                    fact!(src(3));
+                    rule!(copy: dst(y) <= src(x), {
+                        let y = {mark([x])}
+                    })
+                })?;
+                core.run(holmes.quiesce()).unwrap();
+                assert_eq!(calls.get(), 1);
+                assert_eq!(query!(holmes, dst(x))?.len(), 3);
+                Ok(())
+            },
+            // The rule has changed, so it starts over
+            &|holmes: &mut Engine, core: &mut Core| {
+                let calls = counted_mark(holmes)?;
+                holmes_exec!(holmes, {
+                    predicate!(src(uint64));
+                    predicate!(dst(uint64));
+                    rule!(copy: dst(y) <= src(x) & src(x), {
+                        let y = {mark([x])}
+                    })
+                })?;
+                core.run(holmes.quiesce()).unwrap();
+                assert_eq!(calls.get(), 3);
+                Ok(())
+            },
+        ],
+    )
+}
+
+// A large constant is opened afresh on each run, but the rule is still the
+// same rule
+#[test]
+fn rule_resume_large() {
+    multi(
+        &[
+            &|holmes: &mut Engine, core: &mut Core| {
+                let calls = counted_mark(holmes)?;
+                holmes_exec!(holmes, {
+                    predicate!(src(uint64, largebytes));
+                    predicate!(dst(uint64));
+                    fact!(src(1, LargeBWrap { inner: vec![1u8] }));
+                    fact!(src(2, LargeBWrap { inner: vec![1u8] }));
+                    rule!(copy: dst(y) <= src(x, (LargeBWrap { inner: vec![1u8] })), {
+                        let y = {mark([x])}
+                    })
+                })?;
+                core.run(holmes.quiesce()).unwrap();
+                assert_eq!(calls.get(), 2);
+                Ok(())
+            },
+            &|holmes: &mut Engine, core: &mut Core| {
+                let calls = counted_mark(holmes)?;
+                holmes_exec!(holmes, {
+                    predicate!(src(uint64, largebytes));
+                    predicate!(dst(uint64));
+                    fact!(src(3, LargeBWrap { inner: vec![1u8] }));
+                    rule!(copy: dst(y) <= src(x, (LargeBWrap { inner: vec![1u8] })), {
+                        let y = {mark([x])}
+                    })
+                })?;
+                core.run(holmes.quiesce()).unwrap();
+                assert_eq!(calls.get(), 1);
+                Ok(())
+            },
+        ],
+    )
+}
@@ -0,0 +1,118 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+fn reach_rules(holmes: &mut Engine) -> Result<()> {
+    holmes_exec!(holmes, {
+        predicate!(edge(uint64, uint64));
+        predicate!(reach(uint64, uint64));
+        rule!(reach_edge: reach(x, y) <= edge(x, y));
+        rule!(reach_trans: reach(x, z) <= reach(x, y) & edge(y, z))
+    })
+}
+
+#[test]
+pub fn retract_derived() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        reach_rules(holmes)?;
+        holmes_exec!(holmes, {
+            fact!(edge(1, 2));
+            fact!(edge(2, 3));
+            fact!(edge(1, 3))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert!(retract!(holmes, edge(2, 3))?);
+        core.run(holmes.quiesce()).unwrap();
+        let mut reached = query!(holmes, reach(x, y))?;
+        reached.sort();
+        // reach(1, 3) survives through edge(1, 3), reach(2, 3) does not
+        assert_eq!(
+            reached,
+            vec![
+                vec![1.to_value(), 2.to_value()],
+                vec![1.to_value(), 3.to_value()],
+            ]
+        );
+        Ok(())
+    })
+}
+
+// Facts supporting each other around a cycle must not keep each other alive
+#[test]
+pub fn retract_cycle() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        reach_rules(holmes)?;
+        holmes_exec!(holmes, {
+            fact!(edge(1, 2));
+            fact!(edge(2, 1))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert!(retract!(holmes, edge(1, 2))?);
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, reach(x, y))?,
+            vec![vec![2.to_value(), 1.to_value()]]
+        );
+        Ok(())
+    })
+}
+
+#[test]
+pub fn retract_absent() {
+    single(&|holmes: &mut Engine, _| {
+        reach_rules(holmes)?;
+        fact!(holmes, edge(1, 2))?;
+        assert!(!retract!(holmes, edge(2, 1))?);
+        Ok(())
+    })
+}
+
+// Removing a fact can give a negating rule new answers
+#[test]
+pub fn retract_negated() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(func(string));
+            predicate!(calls(string, string));
+            predicate!(no_callers(string));
+            rule!(find_roots: no_callers(f) <= func(f) & not(calls([_], f)));
+            fact!(func("main"));
+            fact!(func("helper"));
+            fact!(calls("main", "helper"))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        retract!(holmes, calls("main", "helper"))?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut roots = query!(holmes, no_callers(f))?;
+        roots.sort();
+        assert_eq!(
+            roots,
+            vec![vec!["helper".to_value()], vec!["main".to_value()]]
+        );
+        Ok(())
+    })
+}
+
+// Only facts fitting a clause's constants and repeated variables are
+// followed through it
+#[test]
+pub fn retract_pinned() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(self_loop(uint64));
+            predicate!(from_one(uint64));
+            rule!(find_loops: self_loop(x) <= edge(x, x));
+            rule!(find_from_one: from_one(y) <= edge((1), y));
+            fact!(edge(1, 1));
+            fact!(edge(1, 2));
+            fact!(edge(2, 2))
+        })?;
+        core.run(holmes.quiesce()).unwrap();
+        assert!(retract!(holmes, edge(1, 1))?);
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, self_loop(x))?, vec![vec![2.to_value()]]);
+        assert_eq!(query!(holmes, from_one(x))?, vec![vec![2.to_value()]]);
+        Ok(())
+    })
+}
@@ -5,11 +5,11 @@ use holmes::simple::*;
 #[test]
 pub fn one_step() {
     single(&|holmes: &mut Engine, core: &mut Core| {
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
       predicate!(test_pred(string, bytes, uint64));
       fact!(test_pred("foo", vec![3u8;3], 7));
       rule!(test_forward: test_pred(("bar"), (vec![2u8;2]), x) <= test_pred(("foo"), [_], x))
-    }));
+    })?;
         core.run(holmes.quiesce()).unwrap();
         assert_eq!(query!(holmes, test_pred(("bar"), [_], x)).unwrap(),
                vec![vec![7.to_value()]]);
@@ -20,16 +20,48 @@ pub fn one_step() {
 #[test]
 pub fn closure() {
     single(&|holmes: &mut Engine, core: &mut Core| {
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
       predicate!(reaches(string, string));
       fact!(reaches("foo", "bar"));
       fact!(reaches("bar", "baz"));
       fact!(reaches("baz", "bang"));
       rule!(reaches_trans: reaches(src, dst) <= reaches(src, mid) & reaches(mid, dst))
-    }));
+    })?;
         core.run(holmes.quiesce()).unwrap();
-        let ans = try!(query!(holmes, reaches(("foo"), tgt)));
+        let ans = query!(holmes, reaches(("foo"), tgt))?;
         assert_eq!(ans, vec![["bar".to_value()], ["baz".to_value()], ["bang".to_value()]]);
         Ok(())
     })
 }
+
+#[test]
+pub fn batch_output() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(num(uint64));
+            predicate!(parity(uint64, string));
+            func!(let parity_name : uint64 -> string = |n: &u64| {
+                if n.is_multiple_of(2) { "even".to_string() } else { "odd".to_string() }
+            });
+            rule!(classify: parity(n, name) <= num(n), {
+                let name = {parity_name([n])}
+            });
+            rule!(any_parity: parity((0), name) <= parity([_], name))
+        })?;
+        let nums: Vec<Fact> = (1..10001)
+            .map(|n| {
+                Fact {
+                    pred_name: "num".to_string(),
+                    args: vec![n.to_value()],
+                }
+            })
+            .collect();
+        holmes.new_facts(&nums)?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, parity(n, [_]))?.len(), 10002);
+        let mut names = query!(holmes, parity((0), name))?;
+        names.sort();
+        assert_eq!(names, vec![vec!["even".to_value()], vec!["odd".to_value()]]);
+        Ok(())
+    })
+}
@@ -0,0 +1,76 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+
+// Functions are only checked by their declared types, so one which returns
+// something else can only be caught as the rule runs
+#[test]
+pub fn lying_func() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(src(uint64));
+            predicate!(out(uint64));
+            predicate!(copy(uint64));
+            func!(let lie : uint64 -> uint64 = |x: &u64| format!("{}", x));
+            rule!(broken: out(y) <= src(x), {
+                let y = {lie([x])}
+            });
+            rule!(good: copy(x) <= src(x));
+            fact!(src(1))
+        })?;
+        let errors = core.run(holmes.quiesce()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, "broken");
+        assert_eq!(holmes.rule_errors(), errors);
+        assert_eq!(query!(holmes, copy(x))?, vec![vec![1.to_value()]]);
+
+        // The remaining rules keep going, and the old error isn't reported
+        // again
+        fact!(holmes, src(2))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, copy(x))?.len(), 2);
+        assert_eq!(query!(holmes, out(x))?.len(), 0);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn lying_func_mem() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(src(uint64));
+            predicate!(out(uint64));
+            func!(let lie : uint64 -> uint64 = |x: &u64| format!("{}", x));
+            rule!(broken: out(y) <= src(x), {
+                let y = {lie([x])}
+            });
+            fact!(src(1))
+        })?;
+        let errors = core.run(holmes.quiesce()).unwrap_err();
+        assert_eq!(errors[0].rule, "broken");
+        assert!(errors[0].message.contains("out"));
+        Ok(())
+    })
+}
+
+// A list's length is not part of its type, so destructuring one which is
+// too short can only be caught as the rule runs
+#[test]
+pub fn short_list() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(p(uint64));
+            predicate!(q(uint64));
+            func!(let one : uint64 -> [uint64] = |x: &u64| vec![*x]);
+            rule!(split: q(b) <= p(x), {
+                let {a, b} = {one([x])}
+            });
+            fact!(p(1))
+        })?;
+        let errors = core.run(holmes.quiesce()).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].rule, "split");
+        assert_eq!(query!(holmes, q(x))?.len(), 0);
+        Ok(())
+    })
+}
@@ -0,0 +1,65 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::ErrorKind;
+
+#[test]
+pub fn pause_resume() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(src(uint64));
+            predicate!(dst(uint64));
+            fact!(src(1))
+        })?;
+        let copy = rule!(holmes, copy: dst(x) <= src(x))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, dst(x))?, vec![vec![1.to_value()]]);
+
+        holmes.pause_rule(copy)?;
+        fact!(holmes, src(2))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, dst(x))?, vec![vec![1.to_value()]]);
+
+        // Catches up on what arrived while it was paused
+        holmes.resume_rule(copy)?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut dsts = query!(holmes, dst(x))?;
+        dsts.sort();
+        assert_eq!(dsts, vec![vec![1.to_value()], vec![2.to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn remove_replace() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(src(uint64));
+            predicate!(dst(uint64, string));
+            fact!(src(1))
+        })?;
+        let buggy = rule!(holmes, copy: dst(x, ("buggy")) <= src(x))?;
+        core.run(holmes.quiesce()).unwrap();
+
+        holmes.remove_rule(buggy)?;
+        fact!(holmes, src(2))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(
+            query!(holmes, dst(x, y))?,
+            vec![vec![1.to_value(), "buggy".to_value()]]
+        );
+        assert!(holmes.dump_profile().is_empty());
+        match *holmes.remove_rule(buggy).unwrap_err().kind() {
+            ErrorKind::Invalid(_) => (),
+            ref other => panic!("Expected an invalid handle, got {:?}", other),
+        }
+
+        // The replacement starts over, under the same name
+        rule!(holmes, copy: dst(x, ("fixed")) <= src(x))?;
+        core.run(holmes.quiesce()).unwrap();
+        let mut fixed = query!(holmes, dst(x, ("fixed")))?;
+        fixed.sort();
+        assert_eq!(fixed, vec![vec![1.to_value()], vec![2.to_value()]]);
+        Ok(())
+    })
+}
@@ -0,0 +1,79 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::engine::RuleConfig;
+use holmes::lang;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn parse_rule(src: &str) -> Rule {
+    match lang::parse(src, &|_| None).unwrap().remove(0) {
+        lang::Statement::Rule(rule) => rule,
+        other => panic!("Expected a rule, got {:?}", other),
+    }
+}
+
+#[test]
+pub fn schedule_priority() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let lo_log = log.clone();
+        let hi_log = log.clone();
+        holmes_exec!(holmes, {
+            predicate!(start(uint64));
+            predicate!(lo(uint64));
+            predicate!(hi(uint64))
+        })?;
+        func!(holmes, let mark_lo: uint64 -> uint64 = |n: &u64| {
+            lo_log.borrow_mut().push("lo");
+            *n
+        })?;
+        func!(holmes, let mark_hi: uint64 -> uint64 = |n: &u64| {
+            hi_log.borrow_mut().push("hi");
+            *n
+        })?;
+        // The low priority rule is queued first, but has to wait its turn
+        holmes.new_rule(&parse_rule("lo: lo(m) <= start(n) where m = mark_lo(n)."))?;
+        holmes.new_rule_with(
+            &parse_rule("hi: hi(m) <= start(n) where m = mark_hi(n)."),
+            RuleConfig {
+                priority: 10,
+                ..RuleConfig::default()
+            },
+        )?;
+        fact!(holmes, start(1))?;
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(*log.borrow(), vec!["hi", "lo"]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn schedule_budget() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(count(uint64));
+            fact!(count(0));
+            func!(let inc: uint64 -> uint64 = |i: &u64| *i + 1)
+        })?;
+        holmes.new_rule_with(
+            &parse_rule("inc: count(m) <= count(n) where m = inc(n)."),
+            RuleConfig {
+                max_activations: Some(5),
+                ..RuleConfig::default()
+            },
+        )?;
+        // Would never finish without the budget
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, count(x))?.len(), 6);
+        let profile = holmes
+            .dump_profile()
+            .into_iter()
+            .find(|profile| profile.name == "inc")
+            .unwrap();
+        assert_eq!(profile.activations, 5);
+        assert!(profile.exhausted);
+        assert_eq!(profile.config.max_activations, Some(5));
+        Ok(())
+    })
+}
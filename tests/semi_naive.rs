@@ -0,0 +1,76 @@
+extern crate holmes;
+use holmes::simple::*;
+use holmes::FactDB;
+use holmes::engine::types::Predicate;
+
+fn edge(src: u64, dst: u64) -> Fact {
+    Fact {
+        pred_name: "edge".to_string(),
+        args: vec![src.to_value(), dst.to_value()],
+    }
+}
+
+// Searches a path of two edges after adding the last two edges of 1 -> 4
+fn two_new_edges(db: &dyn FactDB) {
+    let uint64 = db.get_type("uint64").unwrap();
+    let field = Field {
+        name: None,
+        description: None,
+        type_: uint64,
+        join: None,
+    };
+    db.new_predicate(&Predicate {
+        name: "edge".to_string(),
+        description: None,
+        fields: vec![field.clone(), field],
+    }).unwrap();
+    db.insert_fact(&edge(1, 2)).unwrap();
+    let min = db.insert_fact(&edge(2, 3)).unwrap().unwrap();
+    db.insert_fact(&edge(3, 4)).unwrap();
+
+    let clause = |src, dst| {
+        Clause {
+            pred_name: "edge".to_string(),
+            args: vec![MatchExpr::Var(src), MatchExpr::Var(dst)],
+            negated: false,
+        }
+    };
+    let query = vec![clause(0, 1), clause(1, 2)];
+    // Paths using a new edge, once each, even when both edges are new
+    let mut paths: Vec<Vec<Value>> = db.search_facts(&query, Some(min))
+        .unwrap()
+        .into_iter()
+        .map(|(_, vals)| vals)
+        .collect();
+    paths.sort();
+    assert_eq!(
+        paths,
+        vec![
+            vec![1.to_value(), 2.to_value(), 3.to_value()],
+            vec![2.to_value(), 3.to_value(), 4.to_value()],
+        ]
+    );
+    assert_eq!(db.search_facts(&query, Some(min + 2)).unwrap(), vec![]);
+}
+
+#[test]
+pub fn semi_naive_search() {
+    two_new_edges(&MemDB::new());
+}
+
+#[test]
+pub fn semi_naive_search_pg() {
+    with_db_addr(&|addr| {
+        two_new_edges(&PgDB::new(addr).unwrap());
+        Ok(())
+    });
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+pub fn semi_naive_search_sqlite() {
+    let mut path = ::std::env::temp_dir();
+    path.push(format!("holmes_semi_naive_{}.sqlite", ::std::process::id()));
+    two_new_edges(&SqliteDB::new(path.to_str().unwrap()).unwrap());
+    ::std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,115 @@
+#![cfg(feature = "sqlite")]
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::{ErrorKind, fact_db, sqlite};
+use holmes::engine::types::Predicate;
+use holmes::pg::dyn::types::Trap;
+
+#[test]
+pub fn sqlite_reopen() {
+    multi_sqlite(&[
+        &|holmes: &mut Engine<SqliteDB>, _| {
+            holmes_exec!(holmes, {
+                predicate!(test_pred(string, bytes, [uint64]));
+                fact!(test_pred("foo", vec![3u8, 4u8], vec![5u64, 6u64]))
+            })
+        },
+        &|holmes: &mut Engine<SqliteDB>, _| {
+            assert_eq!(
+                query!(holmes, test_pred(x, y, z))?,
+                vec![
+                    vec![
+                        "foo".to_value(),
+                        vec![3u8, 4u8].to_value(),
+                        vec![5u64, 6u64].to_value(),
+                    ],
+                ]
+            );
+            Ok(())
+        },
+    ])
+}
+
+#[test]
+pub fn sqlite_rules() {
+    multi_sqlite(&[
+        &|holmes: &mut Engine<SqliteDB>, core: &mut Core| {
+            holmes_exec!(holmes, {
+                predicate!(func(string));
+                predicate!(calls(string, string));
+                predicate!(no_callers(string));
+                rule!(find_roots: no_callers(f) <= func(f) & not(calls([_], f)));
+                fact!(func("main"));
+                fact!(func("helper"));
+                fact!(calls("main", "helper"))
+            })?;
+            core.run(holmes.quiesce()).unwrap();
+            assert_eq!(
+                query!(holmes, no_callers(f))?,
+                vec![vec!["main".to_value()]]
+            );
+            assert!(retract!(holmes, calls("main", "helper"))?);
+            core.run(holmes.quiesce()).unwrap();
+            let mut roots = query!(holmes, no_callers(f))?;
+            roots.sort();
+            assert_eq!(
+                roots,
+                vec![vec!["helper".to_value()], vec!["main".to_value()]]
+            );
+            Ok(())
+        },
+    ])
+}
+
+#[test]
+pub fn sqlite_merge() {
+    multi_sqlite(&[
+        &|holmes: &mut Engine<SqliteDB>, _| {
+            holmes_exec!(holmes, {
+                predicate!(bound(string, [hi uint64 join max_join]));
+                func!(let max_join : (uint64, uint64) -> uint64 = |(a, b): (&u64, &u64)| {
+                    ::std::cmp::max(*a, *b)
+                });
+                fact!(bound("x", 3));
+                fact!(bound("x", 7));
+                fact!(bound("x", 5))
+            })?;
+            assert_eq!(
+                query!(holmes, bound(("x"), hi))?,
+                vec![vec![7.to_value()]]
+            );
+            Ok(())
+        },
+    ])
+}
+
+// SQLite has no stored form for custom types, so predicates over them are
+// refused up front rather than failing on their first fact
+#[test]
+pub fn sqlite_custom_type() {
+    multi_sqlite(&[
+        &|holmes: &mut Engine<SqliteDB>, _| {
+            let pred = Predicate {
+                name: "custom".to_string(),
+                description: None,
+                fields: vec![
+                    Field {
+                        name: None,
+                        description: None,
+                        type_: Trap::new(),
+                        join: None,
+                    },
+                ],
+            };
+            match *holmes.new_predicate(&pred).unwrap_err().kind() {
+                ErrorKind::FactDB(fact_db::ErrorKind::Sqlite(sqlite::ErrorKind::Type(ref msg))) => {
+                    assert!(msg.contains("custom"))
+                }
+                ref other => panic!("Expected a type error, got {:?}", other),
+            }
+            assert!(holmes.get_predicate("custom")?.is_none());
+            Ok(())
+        },
+    ])
+}
@@ -0,0 +1,92 @@
+#[macro_use]
+extern crate holmes;
+extern crate futures;
+use holmes::simple::*;
+use futures::Stream;
+
+#[test]
+pub fn subscribe_stream() {
+    single(&|holmes: &mut Engine, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(reaches(string, string));
+            fact!(reaches("foo", "bar"));
+            rule!(reaches_trans: reaches(src, dst) <= reaches(src, mid) & reaches(mid, dst))
+        })?;
+        let mut answers = holmes.subscribe(vec![
+            Clause {
+                pred_name: "reaches".to_string(),
+                args: vec![MatchExpr::Const("foo".to_value()), MatchExpr::Var(0)],
+                negated: false,
+            },
+        ]);
+        let first = core.run(answers.by_ref().take(1).collect())?;
+        assert_eq!(first, vec![vec!["bar".to_value()]]);
+
+        // Answers found by rules arrive without quiescing, and the ones
+        // already delivered are not repeated
+        holmes_exec!(holmes, {
+            fact!(reaches("bar", "baz"));
+            fact!(reaches("baz", "bang"))
+        })?;
+        let mut more = core.run(answers.by_ref().take(2).collect())?;
+        more.sort();
+        assert_eq!(more, vec![vec!["bang".to_value()], vec!["baz".to_value()]]);
+        Ok(())
+    })
+}
+
+#[test]
+pub fn subscribe_mem() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(path(uint64, uint64));
+            rule!(path_base: path(x, y) <= edge(x, y));
+            rule!(path_step: path(x, z) <= path(x, y) & edge(y, z))
+        })?;
+        let mut answers = holmes.subscribe(vec![
+            Clause {
+                pred_name: "path".to_string(),
+                args: vec![MatchExpr::Const(1.to_value()), MatchExpr::Var(0)],
+                negated: false,
+            },
+        ]);
+        for n in 1u64..4 {
+            fact!(holmes, edge(n, n + 1))?;
+            let next = core.run(answers.by_ref().take(1).collect())?;
+            assert_eq!(next, vec![vec![(n + 1).to_value()]]);
+        }
+        Ok(())
+    })
+}
+
+// Dropping a subscription forgets it, without disturbing the rules or other
+// subscriptions woken by the same predicates
+#[test]
+pub fn subscribe_dropped() {
+    single_mem(&|holmes: &mut Engine<MemDB>, core: &mut Core| {
+        holmes_exec!(holmes, {
+            predicate!(edge(uint64, uint64));
+            predicate!(path(uint64, uint64));
+            rule!(path_base: path(x, y) <= edge(x, y))
+        })?;
+        let path_from = |src: u64| {
+            vec![
+                Clause {
+                    pred_name: "path".to_string(),
+                    args: vec![MatchExpr::Const(src.to_value()), MatchExpr::Var(0)],
+                    negated: false,
+                },
+            ]
+        };
+        drop(holmes.subscribe(path_from(1)));
+        let mut answers = holmes.subscribe(path_from(2));
+        fact!(holmes, edge(1, 2))?;
+        fact!(holmes, edge(2, 3))?;
+        let next = core.run(answers.by_ref().take(1).collect())?;
+        assert_eq!(next, vec![vec![3.to_value()]]);
+        core.run(holmes.quiesce()).unwrap();
+        assert_eq!(query!(holmes, path(x, y))?.len(), 2);
+        Ok(())
+    })
+}
@@ -12,16 +12,16 @@ pub fn infinity() {
         let limit = Duration::new(2, 0);
         // Wiggle room to shut things down
         let wiggle = Duration::new(1, 0);
-        holmes.limit_time(limit.clone());
+        holmes.limit_time(limit);
         let start = Instant::now();
-        try!(holmes_exec!(holmes, {
+        holmes_exec!(holmes, {
       predicate!(count(uint64));
       fact!(count(0));
       func!(let inc: uint64 -> uint64 = |i: &u64| *i + 1);
       rule!(inc: count(n_plus_one) <= count(n), {
           let n_plus_one = {inc([n])}
       })
-    }));
+    })?;
         core.run(holmes.quiesce()).unwrap();
         assert!(start.elapsed() < limit + wiggle);
         Ok(())
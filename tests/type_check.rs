@@ -0,0 +1,113 @@
+#[macro_use]
+extern crate holmes;
+use holmes::simple::*;
+use holmes::ErrorKind;
+
+fn type_error<A: ::std::fmt::Debug>(res: Result<A>) -> String {
+    match *res.unwrap_err().kind() {
+        ErrorKind::Type(ref msg) => msg.clone(),
+        ref other => panic!("Expected a type error, got {:?}", other),
+    }
+}
+
+#[test]
+pub fn body_mismatch() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(name(string));
+            predicate!(num(uint64));
+            predicate!(out(uint64))
+        })?;
+        let msg = type_error(rule!(holmes, bad: out(x) <= name(x) & num(x)));
+        assert!(msg.contains("bad"));
+        Ok(())
+    })
+}
+
+#[test]
+pub fn func_arg_mismatch() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(name(string));
+            predicate!(out(uint64));
+            func!(let inc : uint64 -> uint64 = |n: &u64| n + 1)
+        })?;
+        let msg = type_error(rule!(holmes, bad: out(y) <= name(x), {
+            let y = {inc([x])}
+        }));
+        assert!(msg.contains("inc"));
+        Ok(())
+    })
+}
+
+#[test]
+pub fn func_unknown() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(num(uint64));
+            predicate!(out(uint64))
+        })?;
+        let msg = type_error(rule!(holmes, bad: out(y) <= num(x), {
+            let y = {missing([x])}
+        }));
+        assert!(msg.contains("missing"));
+        Ok(())
+    })
+}
+
+#[test]
+pub fn bind_shapes() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(num(uint64));
+            predicate!(out(uint64));
+            func!(let same : uint64 -> uint64 = |n: &u64| *n);
+            func!(let pair : uint64 -> (uint64, uint64) = |n: &u64| (*n, *n));
+            func!(let many : uint64 -> [uint64] = |n: &u64| vec![*n])
+        })?;
+        type_error(rule!(holmes, iter_scalar: out(y) <= num(x), {
+            let [y] = {same([x])}
+        }));
+        type_error(rule!(holmes, short_tuple: out(y) <= num(x), {
+            let {y} = {pair([x])}
+        }));
+        // The right shapes are fine
+        rule!(holmes, good_tuple: out(y) <= num(x), {
+            let {y, z} = {pair([x])}
+        })?;
+        rule!(holmes, good_list: out(y) <= num(x), {
+            let [y] = {many([x])}
+        })?;
+        Ok(())
+    })
+}
+
+#[test]
+pub fn head_mismatch() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(name(string));
+            predicate!(num(uint64));
+            predicate!(names(string, [string]))
+        })?;
+        type_error(rule!(holmes, bad_head: num(x) <= name(x)));
+        type_error(rule!(holmes, bad_collect: names((""), {collect x}) <= num(x)));
+        rule!(holmes, good_collect: names((""), {collect x}) <= name(x))?;
+        Ok(())
+    })
+}
+
+#[test]
+pub fn negation_only_var() {
+    single_mem(&|holmes: &mut Engine<MemDB>, _| {
+        holmes_exec!(holmes, {
+            predicate!(p(uint64));
+            predicate!(q(uint64));
+            predicate!(r(uint64))
+        })?;
+        let msg = type_error(rule!(holmes, bad: q(x) <= p(y) & not(r(x))));
+        assert!(msg.contains("bad"));
+        assert!(msg.contains("negation"));
+        Ok(())
+    })
+}
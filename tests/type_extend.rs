@@ -22,14 +22,11 @@ impl TypeT for BoolType {
     fn repr(&self) -> &'static str {
         "bool"
     }
-    fn inner(&self) -> &Any {
-        self as &Any
+    fn inner(&self) -> &dyn Any {
+        self as &dyn Any
     }
-    fn inner_eq(&self, other: &TypeT) -> bool {
-        match other.inner().downcast_ref::<Self>() {
-            Some(_) => true,
-            _ => false,
-        }
+    fn inner_eq(&self, other: &dyn TypeT) -> bool {
+        other.inner().downcast_ref::<Self>().is_some()
     }
     fn large(&self) -> bool {
         false
@@ -57,22 +54,22 @@ impl ValueT for BoolValue {
     fn type_(&self) -> Type {
         Arc::new(BoolType)
     }
-    fn get(&self) -> &Any {
-        &self.val as &Any
+    fn get(&self) -> &dyn Any {
+        &self.val as &dyn Any
     }
-    fn to_sql(&self) -> Vec<&ToSql> {
+    fn to_sql(&self) -> Vec<&dyn ToSql> {
         vec![&self.val]
     }
-    fn inner(&self) -> &Any {
-        self as &Any
+    fn inner(&self) -> &dyn Any {
+        self as &dyn Any
     }
-    fn inner_eq(&self, other: &ValueT) -> bool {
+    fn inner_eq(&self, other: &dyn ValueT) -> bool {
         match other.inner().downcast_ref::<Self>() {
             Some(x) => self == x,
             _ => false,
         }
     }
-    fn inner_ord(&self, other: &ValueT) -> Option<::std::cmp::Ordering> {
+    fn inner_ord(&self, other: &dyn ValueT) -> Option<::std::cmp::Ordering> {
         other.inner().downcast_ref::<Self>().and_then(
             |x| self.partial_cmp(x),
         )
@@ -81,23 +78,23 @@ impl ValueT for BoolValue {
 
 impl BoolValue {
     pub fn new(val: bool) -> Self {
-        BoolValue { val: val }
+        BoolValue { val }
     }
 }
 
 #[test]
 pub fn add_bool() {
     single(&|holmes: &mut Engine, _| {
-        try!(holmes.add_type(Arc::new(BoolType)));
-        try!(predicate!(holmes, type_pred(uint64, bool2)));
-        try!(fact!(holmes, type_pred(32, BoolValue::new(false))));
-        try!(fact!(holmes, type_pred(42, BoolValue::new(true))));
+        holmes.add_type(Arc::new(BoolType))?;
+        predicate!(holmes, type_pred(uint64, bool2))?;
+        fact!(holmes, type_pred(32, BoolValue::new(false)))?;
+        fact!(holmes, type_pred(42, BoolValue::new(true)))?;
         assert_eq!(
-            try!(query!(holmes, type_pred((32), x))),
+            query!(holmes, type_pred((32), x))?,
             vec![vec![BoolValue::new(false).to_value()]]
         );
         assert_eq!(
-            try!(query!(holmes, type_pred((42), x))),
+            query!(holmes, type_pred((42), x))?,
             vec![vec![BoolValue::new(true).to_value()]]
         );
         Ok(())
@@ -116,23 +113,22 @@ fn reboot() {
 
 #[test]
 fn reboot_reuse() {
-    multi(
+    multi_with_types(
+        &[Arc::new(BoolType)],
         &[
             &|holmes: &mut Engine, _| {
-                try!(holmes.add_type(Arc::new(BoolType)));
-                try!(predicate!(holmes, type_pred(uint64, bool2)));
+                predicate!(holmes, type_pred(uint64, bool2))?;
                 fact!(holmes, type_pred(32, BoolValue::new(false)))
             },
             &|holmes: &mut Engine, _| {
-                try!(holmes.add_type(Arc::new(BoolType)));
-                try!(predicate!(holmes, type_pred(uint64, bool2)));
-                try!(fact!(holmes, type_pred(42, BoolValue::new(true))));
+                predicate!(holmes, type_pred(uint64, bool2))?;
+                fact!(holmes, type_pred(42, BoolValue::new(true)))?;
                 assert_eq!(
-                    try!(query!(holmes, type_pred((32), x))),
+                    query!(holmes, type_pred((32), x))?,
                     vec![vec![BoolValue::new(false).to_value()]]
                 );
                 assert_eq!(
-                    try!(query!(holmes, type_pred((42), x))),
+                    query!(holmes, type_pred((42), x))?,
                     vec![vec![BoolValue::new(true).to_value()]]
                 );
                 Ok(())
@@ -140,3 +136,21 @@ fn reboot_reuse() {
         ],
     )
 }
+
+// Reopening without a type used by a stored predicate should fail, naming it
+#[test]
+fn reboot_missing() {
+    with_db_addr(&|addr| {
+        {
+            let core = Core::new().unwrap();
+            let db = PgDB::with_types(addr, vec![Arc::new(BoolType)]).unwrap();
+            let holmes = Engine::new(db, core.handle());
+            predicate!(holmes, type_pred(uint64, bool2))?;
+        }
+        match PgDB::new(addr) {
+            Ok(_) => panic!("Opened a database with an unregistered type"),
+            Err(err) => assert!(format!("{}", err).contains("bool2")),
+        }
+        Ok(())
+    })
+}